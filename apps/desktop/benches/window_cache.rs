@@ -0,0 +1,99 @@
+//! Measures the per-event cost of attributing recorded events to windows.
+//!
+//! Run with `cargo bench --bench window_cache`. When `DISPLAY` points to an X
+//! server, the uncached `WM_CLASS` round trip is measured as well, for comparison.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use quansat_dot::platform::linux::x11::cache::{AppSource, WindowCache, WindowInfo};
+use quansat_dot::platform::linux::x11::stream::{Element, Stream};
+use x11rb::connection::Connection;
+use x11rb::properties::WmClass;
+use x11rb::protocol::xproto;
+
+/// Number of events in a simulated RECORD reply
+const BATCH: usize = 1000;
+/// Number of distinct windows the events are spread over
+const WINDOWS: u32 = 16;
+
+fn main() {
+    let payload = payload();
+
+    run("decode", BATCH, || {
+        for element in Stream::new(&payload) {
+            black_box(element.unwrap());
+        }
+    });
+
+    let mut cache = WindowCache::new();
+    run("decode + cached lookup", BATCH, || {
+        for element in Stream::new(&payload) {
+            if let Ok(Element::MotionNotify(event)) = element {
+                let info = cache.get_or_fetch(event.child, || {
+                    Ok::<_, ()>(WindowInfo {
+                        app: Some("firefox".to_string()),
+                        source: AppSource::Class,
+                        title: Some("GitHub".to_string()),
+                        process: None,
+                        fullscreen: false,
+                    })
+                });
+                black_box(info.unwrap());
+            }
+        }
+    });
+
+    match x11rb::connect(None) {
+        Ok((conn, screen)) => {
+            let root = conn.setup().roots[screen].root;
+            run("uncached WM_CLASS round trip", 1, || {
+                let class = WmClass::get(&conn, root).unwrap().reply_unchecked();
+                black_box(class.unwrap());
+            });
+        }
+        Err(err) => println!("uncached WM_CLASS round trip: skipped ({err})"),
+    }
+}
+
+/// Builds a payload of pointer motions over a few windows, as RECORD delivers them.
+fn payload() -> Vec<u8> {
+    (0..BATCH as u32)
+        .flat_map(|i| {
+            let event = xproto::MotionNotifyEvent {
+                response_type: xproto::MOTION_NOTIFY_EVENT,
+                detail: xproto::Motion::NORMAL,
+                sequence: i as u16,
+                time: i,
+                root: 0x1e9,
+                event: 0x1e9,
+                child: 0x3a00000 + i % WINDOWS,
+                root_x: (i % 1920) as i16,
+                root_y: (i % 1080) as i16,
+                event_x: (i % 1920) as i16,
+                event_y: (i % 1080) as i16,
+                state: xproto::KeyButMask::default(),
+                same_screen: true,
+            };
+            <[u8; 32]>::from(&event)
+        })
+        .collect()
+}
+
+/// Runs `f` (which handles `events` events) repeatedly for about a second and
+/// prints the average cost per event.
+fn run(name: &str, events: usize, mut f: impl FnMut()) {
+    // warm up
+    for _ in 0..10 {
+        f();
+    }
+
+    let start = Instant::now();
+    let mut iterations = 0;
+    while start.elapsed() < Duration::from_secs(1) {
+        f();
+        iterations += 1;
+    }
+    let per_event = start.elapsed() / (iterations * events as u32);
+    println!("{name}: {per_event:?} per event");
+}
@@ -0,0 +1,21 @@
+//! Feeds arbitrary bytes to the RECORD stream decoder, which must never panic.
+//!
+//! Run with `cargo +nightly fuzz run record_stream` from `apps/desktop`.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use quansat_dot::platform::linux::x11::stream::Stream;
+
+fuzz_target!(|data: &[u8]| {
+    for element in Stream::new(data) {
+        if element.is_err() {
+            break;
+        }
+    }
+    for element in Stream::swapped(data) {
+        if element.is_err() {
+            break;
+        }
+    }
+});
@@ -0,0 +1,146 @@
+//! Command line options.
+
+use std::time::Duration;
+
+use quansat_dot::platform::{self, Backend, Settings};
+
+const USAGE: &str = "\\
+Usage: dot [OPTIONS]
+
+Options:
+  --backend <NAME>  the recorder backend to use instead of detecting it:
+                    x11, xinput, poll, wayland, sway (or i3), hyprland,
+                    or evdev; xinput and evdev also record input where
+                    the X server does not support RECORD. Built with the
+                    rdev feature, rdev records input through the rdev
+                    crate, which relies on RECORD like x11
+  --poll-interval <SECONDS>
+                    how often the active window is sampled by the poll
+                    backend, and by x11 without the RECORD extension
+                    [default: 5]
+  --idle-threshold <SECONDS>
+                    how long without input until the user is reported as
+                    idle, by the backends that report idle periods
+                    [default: 300]
+  --process <NAME>  report when processes running the program start and
+                    exit, named by its executable or its flatpak or snap
+                    id, can be given more than once
+  --ignore-app <NAME>
+                    do not log the events of apps whose name contains NAME,
+                    ignoring case, like the bars of window managers, can be
+                    given more than once
+  -h, --help        print this help
+";
+
+#[derive(Debug, Default)]
+pub struct Options {
+    /// the backend to use, detected from the session if not given
+    pub backend: Option<Backend>,
+    pub settings: Settings,
+    /// the apps whose events are not logged, matched case-insensitively on part
+    /// of their name
+    pub ignored_apps: Vec<String>,
+}
+
+impl Options {
+    /// Parses the options from the command line arguments, printing the usage
+    /// and exiting on `--help`.
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, platform::Error> {
+        let mut options = Options::default();
+        while let Some(arg) = args.next() {
+            let (name, inline) = match arg.split_once('=') {
+                Some((name, value)) => (name.to_string(), Some(value.to_string())),
+                None => (arg, None),
+            };
+            let mut value = || {
+                inline
+                    .clone()
+                    .or_else(|| args.next())
+                    .ok_or_else(|| format!("missing value for {name}"))
+            };
+            match name.as_str() {
+                "--backend" => options.backend = Some(value()?.parse()?),
+                "--poll-interval" => options.settings.poll_interval = seconds(&name, &value()?)?,
+                "--idle-threshold" => options.settings.idle_threshold = seconds(&name, &value()?)?,
+                "--process" => options.settings.processes.push(value()?),
+                "--ignore-app" => options.ignored_apps.push(value()?.to_lowercase()),
+                "-h" | "--help" => {
+                    print!("{USAGE}");
+                    std::process::exit(0);
+                }
+                _ => return Err(format!("unexpected argument {name:?}\n\n{USAGE}").into()),
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// Parses the value of an option as a positive number of seconds.
+fn seconds(name: &str, value: &str) -> Result<Duration, platform::Error> {
+    match value.parse() {
+        Ok(seconds) if seconds > 0 => Ok(Duration::from_secs(seconds)),
+        _ => Err(format!("invalid {name} {value:?}, expected a positive number of seconds").into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, platform::Error> {
+        Options::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn backend_is_optional() {
+        assert_eq!(parse(&[]).unwrap().backend, None);
+    }
+
+    #[test]
+    fn backend_as_separate_or_inline_value() {
+        assert_eq!(
+            parse(&["--backend", "x11"]).unwrap().backend,
+            Some(Backend::X11)
+        );
+        assert_eq!(
+            parse(&["--backend=x11"]).unwrap().backend,
+            Some(Backend::X11)
+        );
+    }
+
+    #[test]
+    fn intervals_in_seconds() {
+        let options = parse(&["--poll-interval", "30"]).unwrap();
+        assert_eq!(options.settings.poll_interval, Duration::from_secs(30));
+        let options = parse(&[]).unwrap();
+        assert_eq!(options.settings, Settings::default());
+        assert!(parse(&["--poll-interval=0"]).is_err());
+        assert!(parse(&["--poll-interval", "1.5"]).is_err());
+        let options = parse(&["--idle-threshold=600"]).unwrap();
+        assert_eq!(options.settings.idle_threshold, Duration::from_secs(600));
+    }
+
+    #[test]
+    fn processes_can_be_repeated() {
+        let options = parse(&["--process", "syncthing", "--process=org.mozilla.firefox"]).unwrap();
+        assert_eq!(
+            options.settings.processes,
+            ["syncthing", "org.mozilla.firefox"]
+        );
+        assert!(parse(&["--process"]).is_err());
+    }
+
+    #[test]
+    fn ignores_no_apps_unless_given() {
+        assert!(parse(&[]).unwrap().ignored_apps.is_empty());
+        let options = parse(&["--ignore-app", "i3bar", "--ignore-app=Polybar"]).unwrap();
+        assert_eq!(options.ignored_apps, ["i3bar", "polybar"]);
+    }
+
+    #[test]
+    fn rejects_unknown_arguments_and_values() {
+        assert!(parse(&["--backend"]).is_err());
+        assert!(parse(&["--backend", "gopher"]).is_err());
+        assert!(parse(&["--verbose"]).is_err());
+    }
+}
@@ -1,23 +1,174 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Instant;
+
 use chrono::{DateTime, Utc};
 
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum EventData {
     KeyPress(u32),
+    KeyRelease(u32),
     PointerPress(u8),
-    PointerMove { x: f64, y: f64 },
+    PointerRelease(u8),
+    PointerMove {
+        x: f64,
+        y: f64,
+    },
+    /// the pointer moved by `dx` and `dy` device units, for backends that do not
+    /// know where the pointer is on screen
+    PointerMotion {
+        dx: f64,
+        dy: f64,
+    },
+    /// scrolling by `dx` steps to the right and `dy` steps down
+    /// (negative values scroll left and up)
+    Scroll {
+        dx: f64,
+        dy: f64,
+    },
+    /// the pointer entered the window
+    EnterWindow,
+    /// the pointer left the window
+    LeaveWindow,
     FocusIn,
     FocusOut,
+    /// the title of the focused window changed without a focus change
+    TitleChange,
+    /// the focused window became fullscreen, or a fullscreen window was focused
+    FullscreenStart,
+    /// the focused window left fullscreen, or a fullscreen window lost the focus
+    FullscreenEnd,
+    /// a media player started playing, reported with the player as the app and
+    /// the track as the title. Together with fullscreen windows this tells apart
+    /// watching from being away, when there is no input for a while.
+    PlaybackStart,
+    /// a media player paused or stopped playing, or went away while playing
+    PlaybackEnd,
+    /// a top-level window was created, with the id of the window (in X11 its XID).
+    /// Window managers create windows of their own, like frames around the
+    /// windows of apps, which are only reported if they identify an app. As apps
+    /// identify their windows only before showing them, this is reported right
+    /// before the window is first mapped, with the time it was created.
+    WindowCreated(u32),
+    /// a top-level window was destroyed, reported with the app and title it was
+    /// last seen with
+    WindowDestroyed(u32),
+    /// a top-level window was shown. Window managers also unmap and map windows
+    /// when they are minimized and restored, or on workspace switches.
+    WindowMapped(u32),
+    /// a top-level window was hidden
+    WindowUnmapped(u32),
+    /// a process started running one of the followed programs, reported with
+    /// the process, and the name of its executable as the app
+    ProcessStarted,
+    /// a process running one of the followed programs exited, or ran another program
+    ProcessExited,
+    /// the user switched to another workspace, the one the event is reported with
+    WorkspaceChange,
+    /// monitors were connected, disconnected, or their resolution or arrangement changed,
+    /// reported with the new layout as the output
+    MonitorsChange,
+    /// the user became idle, no input was received for the idle timeout.
+    /// It is reported once the timeout passed, but stamped with the last input.
+    IdleStart,
+    /// the user became active again after being idle
+    IdleEnd,
+    /// the session was locked, no events are expected until it is unlocked
+    Lock,
+    /// the session was unlocked
+    Unlock,
+    /// the system is about to suspend or hibernate
+    Suspend,
+    /// the system resumed from suspend or hibernation
+    Resume,
+    /// the recorder lost its connection, no events are recorded until it resumes
+    RecorderDisconnected,
+    /// the recorder reconnected after being disconnected
+    RecorderResumed,
 }
 
 pub struct Event {
     /// when the event occurred
     pub timestamp: DateTime<Utc>,
+    /// when the event occurred on the monotonic clock.
+    /// Use this to measure durations between events,
+    /// it is not affected by changes of the system clock.
+    pub instant: Instant,
     /// the application generated the event (if known).
     /// This depends on platform capabilities, for example,
     /// in Linux X11 this is the `WM_CLASS` property of the window,
+    /// in Linux Wayland this is the app id of the toplevel,
     /// in MacOS this is the bundle identifier, and
     /// in Windows this is the executable name.
+    /// It is empty for events that are not tied to an application, and
+    /// [`crate::platform::UNKNOWN_APP`] for input or windows that cannot be attributed.
     pub app: String,
+    /// the title of the window the event was reported for (if known).
+    /// In Linux X11 this is `_NET_WM_NAME`, or `WM_NAME` for legacy applications.
+    pub title: Option<String>,
+    /// the name of the workspace the user is on (if known).
+    /// This is only reported by backends that talk to the window manager, like sway or Hyprland,
+    /// and in Linux X11 by EWMH window managers, through `_NET_CURRENT_DESKTOP` and `_NET_DESKTOP_NAMES`.
+    pub workspace: Option<String>,
+    /// the input device that generated the event (if known).
+    /// This is only reported by backends that see the devices, like evdev and XInput.
+    pub device: Option<Arc<Device>>,
+    /// the process that owns the window the event was reported for (if known).
+    /// Unlike `app`, this tells apart different apps built on the same toolkit,
+    /// like Electron or Java apps. In Linux X11 this is found through `_NET_WM_PID`.
+    pub process: Option<Arc<Process>>,
+    /// the monitor the event happened on (if known).
+    /// In Linux X11 this is the name of the RandR output, like "eDP-1" or "HDMI-1",
+    /// found through the pointer position for pointer events, and for focus events
+    /// as the output showing most of the window.
+    /// For [`EventData::MonitorsChange`] this is the new layout of all outputs,
+    /// with their size, position and the primary one, like
+    /// "eDP-1 1920x1080+0+0 primary, HDMI-1 2560x1440+1920+0".
+    pub output: Option<String>,
     /// event-specific data
     pub data: EventData,
 }
+
+/// A process, as identified when it was first seen.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Process {
+    pub pid: u32,
+    /// the id of the parent process, `None` for the processes the kernel starts itself, like init
+    pub parent: Option<u32>,
+    /// the executable, if it could be read
+    pub exe: Option<PathBuf>,
+    /// the arguments the process was started with, starting with the command
+    pub cmdline: Vec<String>,
+    /// the sandbox the process runs in, which identifies the app
+    pub sandbox: Option<Sandbox>,
+}
+
+/// A sandbox that apps are distributed in, with the id of the app.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Sandbox {
+    /// a flatpak app id, like "org.mozilla.firefox"
+    Flatpak(String),
+    /// a snap name, like "firefox"
+    Snap(String),
+}
+
+/// An input device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Device {
+    /// the number the backend knows the device by, like the XInput device id,
+    /// or the `N` of `/dev/input/eventN`
+    pub id: Option<u32>,
+    /// the name the device reports, like "Logitech USB Receiver"
+    pub name: String,
+    pub kind: DeviceKind,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DeviceKind {
+    Keyboard,
+    Mouse,
+    Touchpad,
+    Touchscreen,
+    /// buttons, switches and devices that could not be classified
+    Other,
+}
@@ -1,34 +1,106 @@
-pub mod event;
-pub mod platform;
+mod cli;
 
-use log::info;
+use std::sync::Arc;
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+use log::{error, info, warn};
+
+use quansat_dot::event;
+use quansat_dot::platform::linux::logind::LogindRecorder;
+use quansat_dot::platform::linux::mpris::MprisRecorder;
+use quansat_dot::platform::linux::proc_events::ProcessRecorder;
+use quansat_dot::platform::{self, Backend, Recorder};
+
+fn main() -> Result<(), platform::Error> {
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
 
-    let callback: Box<dyn Fn(&event::Event) + Send + Sync> = Box::new(|event: &event::Event| {
-        let ignored_apps = ["i3", "sway"];
-        for app in ignored_apps.iter() {
-            if event.app.to_lowercase().contains(app) {
-                return;
-            }
+    let options = cli::Options::parse(std::env::args().skip(1))?;
+    let backend = match options.backend {
+        Some(backend) => backend,
+        None => Backend::detect()?,
+    };
+    let mut recorder = backend.recorder(&options.settings)?;
+
+    // Report what the backend cannot observe, so gaps in the data are expected
+    let capabilities = recorder.capabilities();
+    info!("Using the {backend} backend ({capabilities})");
+    if !capabilities.titles {
+        warn!("The {backend} backend does not report window titles");
+    }
+    if !capabilities.input {
+        warn!("The {backend} backend does not report input, activity is based on focus only");
+    }
+    if !capabilities.idle {
+        warn!("The {backend} backend does not report idle periods");
+    }
+
+    let ignored_apps = options.ignored_apps.clone();
+    let callback = Arc::new(move |event: &event::Event| {
+        let app = event.app.to_lowercase();
+        if ignored_apps.iter().any(|ignored| app.contains(ignored)) {
+            return;
         }
 
         info!(
-            "[{}] App: {}, Event: {:?}",
+            "[{}] App: {}, Title: {}, Event: {:?}",
             event.timestamp.to_rfc3339(),
             event.app,
+            event.title.as_deref().unwrap_or_default(),
             event.data
         );
     });
 
-    // Start the recording in a separate thread/connection
-    // The "data" connection will block while receiving recorded data
-    std::thread::spawn(move || platform::linux::x11::record(callback));
+    let share = || -> platform::Callback {
+        let callback = Arc::clone(&callback);
+        Box::new(move |event| callback(event))
+    };
+
+    // Start the recording in the background
+    recorder.start(share())?;
 
-    // Keep the main thread alive
+    // Screen locks and sleep are followed through logind, media playback
+    // through MPRIS, and the programs given with --process through the kernel,
+    // whatever the backend
+    let mut companions: Vec<(&str, &str, Box<dyn Recorder>)> = vec![
+        (
+            "logind",
+            "screen locks and sleep",
+            Box::new(LogindRecorder::default()),
+        ),
+        (
+            "MPRIS",
+            "media playback",
+            Box::new(MprisRecorder::default()),
+        ),
+    ];
+    if !options.settings.processes.is_empty() {
+        companions.push((
+            "processes",
+            "application launches",
+            Box::new(ProcessRecorder::new(&options.settings)),
+        ));
+    }
+    for (name, what, companion) in &mut companions {
+        if let Err(err) = companion.start(share()) {
+            warn!("Cannot follow {name}, not reporting {what}: {err}");
+        }
+    }
+
+    // Keep the main thread alive for as long as the recorder runs
     info!("Listening for global events... (Ctrl+C to stop)");
-    loop {
-        std::thread::park();
+    let result = recorder.wait();
+    for (name, _, companion) in &mut companions {
+        if let Err(err) = companion.stop() {
+            warn!("Following {name} failed: {err}");
+        }
+    }
+    match result {
+        Ok(()) => {
+            info!("Recording stopped");
+            Ok(())
+        }
+        Err(err) => {
+            error!("Recorder failed: {err}");
+            std::process::exit(1);
+        }
     }
 }
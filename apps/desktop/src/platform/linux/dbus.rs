@@ -0,0 +1,173 @@
+//! Following signals of the services on a bus, through `zbus`.
+//!
+//! A connection stalls once a reader of its messages falls behind, so the
+//! signals are taken off the connection on a thread of their own. That lets
+//! the code handling them call methods on the same connection.
+
+use std::sync::mpsc;
+
+use zbus::blocking::fdo::DBusProxy;
+use zbus::blocking::{Connection, MessageIterator};
+use zbus::{MatchRule, Message};
+
+/// Subscribes to the signals matching any of the rules, returning a channel
+/// receiving them in order. The channel disconnects once the connection closes.
+pub fn follow(
+    conn: &Connection,
+    rules: Vec<MatchRule<'static>>,
+) -> zbus::Result<mpsc::Receiver<zbus::Result<Message>>> {
+    // a single stream keeps the order of signals matching different rules
+    let messages = MessageIterator::from(conn);
+    let bus = DBusProxy::new(conn)?;
+    for rule in &rules {
+        bus.add_match_rule(rule.clone())?;
+    }
+
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        for message in messages {
+            // the stream has all messages of the connection, like replies
+            let matches = match &message {
+                Ok(message) => rules
+                    .iter()
+                    .any(|rule| rule.matches(message).unwrap_or(false)),
+                Err(_) => true,
+            };
+            if matches && sender.send(message).is_err() {
+                break;
+            }
+        }
+    });
+    Ok(receiver)
+}
+
+/// A private bus for tests, stopped when dropped.
+#[cfg(test)]
+pub(crate) struct TestBus {
+    daemon: std::process::Child,
+    dir: std::path::PathBuf,
+    pub address: String,
+}
+
+#[cfg(test)]
+impl TestBus {
+    /// Starts `dbus-daemon` with a socket in a new temporary directory.
+    pub fn start(name: &str) -> Self {
+        use std::io::{BufRead, BufReader};
+        use std::process::{Command, Stdio};
+
+        let dir = std::env::temp_dir().join(format!("dot-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = dir.join("bus.conf");
+        let socket = dir.join("bus");
+        std::fs::write(
+            &config,
+            format!(
+                r#"<busconfig>
+  <listen>unix:path={}</listen>
+  <auth>EXTERNAL</auth>
+  <policy context="default">
+    <allow send_destination="*" eavesdrop="true"/>
+    <allow eavesdrop="true"/>
+    <allow own="*"/>
+  </policy>
+</busconfig>"#,
+                socket.display()
+            ),
+        )
+        .unwrap();
+
+        let mut daemon = Command::new("dbus-daemon")
+            .arg(format!("--config-file={}", config.display()))
+            .args(["--nofork", "--print-address"])
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("dbus-daemon runs");
+        let mut address = String::new();
+        BufReader::new(daemon.stdout.take().unwrap())
+            .read_line(&mut address)
+            .unwrap();
+        TestBus {
+            daemon,
+            dir,
+            address: address.trim().to_string(),
+        }
+    }
+
+    /// Connects to the bus.
+    pub fn connect(&self) -> Connection {
+        zbus::blocking::connection::Builder::address(self.address.as_str())
+            .unwrap()
+            .build()
+            .unwrap()
+    }
+
+    /// Connects to the bus, owning a well-known name.
+    pub fn connect_as(&self, name: &'static str) -> Connection {
+        let conn = self.connect();
+        conn.request_name(name).unwrap();
+        conn
+    }
+}
+
+#[cfg(test)]
+impl Drop for TestBus {
+    fn drop(&mut self) {
+        let _ = self.daemon.kill();
+        let _ = self.daemon.wait();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use zbus::message::Type;
+
+    use super::*;
+
+    #[test]
+    #[ignore = "needs dbus-daemon"]
+    fn follows_signals_until_the_connection_closes() {
+        let bus = TestBus::start("follow");
+        let service = bus.connect_as("org.example.Service");
+        let client = bus.connect();
+        let rule = |member| {
+            MatchRule::builder()
+                .msg_type(Type::Signal)
+                .interface("org.example.Service")
+                .unwrap()
+                .member(member)
+                .unwrap()
+                .build()
+        };
+        let signals = follow(&client, vec![rule("First"), rule("Second")]).unwrap();
+
+        let emit = |member| {
+            service
+                .emit_signal(None::<()>, "/", "org.example.Service", member, &())
+                .unwrap()
+        };
+        emit("Ignored");
+        emit("Second");
+        emit("First");
+        let timeout = Duration::from_secs(5);
+        let members: Vec<_> = (0..2)
+            .map(|_| {
+                let signal = signals.recv_timeout(timeout).unwrap().unwrap();
+                signal.header().member().unwrap().to_string()
+            })
+            .collect();
+        assert_eq!(members, ["Second", "First"]);
+
+        client.close().unwrap();
+        loop {
+            match signals.recv_timeout(timeout) {
+                Ok(message) => assert!(message.is_err(), "only errors follow closing"),
+                Err(err) => break assert_eq!(err, mpsc::RecvTimeoutError::Disconnected),
+            }
+        }
+    }
+}
@@ -0,0 +1,335 @@
+//! Decodes the events of evdev devices, as read from `/dev/input/event*` or
+//! replayed from a dump of such a device (for example, made with `cat`).
+//!
+//! Devices report changes in frames which end with a `SYN_REPORT` event.
+//! Key and button changes are reported right away, relative and absolute motion
+//! and scrolling are summed up and reported once per frame.
+
+use std::io::{self, Read};
+use std::mem::size_of;
+
+use chrono::{DateTime, Utc};
+
+use crate::event::EventData;
+
+/// The size of a `struct input_event`: a `struct timeval` of two longs,
+/// followed by the type, the code and the value.
+pub const EVENT_SIZE: usize = 2 * size_of::<libc::c_long>() + 8;
+
+pub const EV_SYN: u16 = 0x00;
+pub const EV_KEY: u16 = 0x01;
+pub const EV_REL: u16 = 0x02;
+pub const EV_ABS: u16 = 0x03;
+
+const SYN_REPORT: u16 = 0;
+const SYN_DROPPED: u16 = 3;
+
+const REL_X: u16 = 0x00;
+const REL_Y: u16 = 0x01;
+const REL_HWHEEL: u16 = 0x06;
+const REL_WHEEL: u16 = 0x08;
+
+const ABS_X: u16 = 0x00;
+const ABS_Y: u16 = 0x01;
+
+pub const KEY_A: u16 = 30;
+pub const BTN_LEFT: u16 = 0x110;
+const BTN_RIGHT: u16 = 0x111;
+const BTN_MIDDLE: u16 = 0x112;
+const BTN_SIDE: u16 = 0x113;
+const BTN_EXTRA: u16 = 0x114;
+const BTN_FORWARD: u16 = 0x115;
+const BTN_BACK: u16 = 0x116;
+pub const BTN_TOOL_FINGER: u16 = 0x145;
+pub const BTN_TOUCH: u16 = 0x14a;
+
+/// Buttons are in this range, keys below and above it.
+const BUTTONS: std::ops::Range<u16> = 0x100..0x160;
+
+/// X11 keycodes are evdev keycodes offset by 8, which we follow to report the
+/// same keycodes as the X11 backend.
+const X11_KEYCODE_OFFSET: u32 = 8;
+
+/// A `struct input_event`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct InputEvent {
+    /// seconds and microseconds since the epoch
+    pub time: (i64, i64),
+    pub event_type: u16,
+    pub code: u16,
+    pub value: i32,
+}
+
+impl InputEvent {
+    /// Decodes an event in the host byte order.
+    pub fn parse(data: &[u8; EVENT_SIZE]) -> Self {
+        const LONG: usize = size_of::<libc::c_long>();
+        let long = |offset: usize| {
+            let mut bytes = [0; LONG];
+            bytes.copy_from_slice(&data[offset..offset + LONG]);
+            // c_long is 32 bits wide on 32-bit targets
+            #[allow(clippy::useless_conversion)]
+            i64::from(libc::c_long::from_ne_bytes(bytes))
+        };
+        let word = |offset: usize| u16::from_ne_bytes([data[offset], data[offset + 1]]);
+        let [v0, v1, v2, v3] = [4, 5, 6, 7].map(|i| data[2 * LONG + i]);
+        InputEvent {
+            time: (long(0), long(LONG)),
+            event_type: word(2 * LONG),
+            code: word(2 * LONG + 2),
+            value: i32::from_ne_bytes([v0, v1, v2, v3]),
+        }
+    }
+
+    /// Encodes the event in the host byte order, the inverse of [`InputEvent::parse`].
+    pub fn encode(&self) -> [u8; EVENT_SIZE] {
+        let mut data = [0; EVENT_SIZE];
+        let (seconds, micros) = self.time;
+        let long = size_of::<libc::c_long>();
+        data[..long].copy_from_slice(&(seconds as libc::c_long).to_ne_bytes());
+        data[long..2 * long].copy_from_slice(&(micros as libc::c_long).to_ne_bytes());
+        data[2 * long..2 * long + 2].copy_from_slice(&self.event_type.to_ne_bytes());
+        data[2 * long + 2..2 * long + 4].copy_from_slice(&self.code.to_ne_bytes());
+        data[2 * long + 4..].copy_from_slice(&self.value.to_ne_bytes());
+        data
+    }
+
+    /// Returns when the event occurred, by the realtime clock evdev uses by default.
+    pub fn timestamp(&self) -> DateTime<Utc> {
+        let (seconds, micros) = self.time;
+        DateTime::from_timestamp(seconds, (micros.clamp(0, 999_999) * 1000) as u32)
+            .unwrap_or_default()
+    }
+}
+
+/// Turns the events of a device into event data.
+#[derive(Debug, Default)]
+pub struct Decoder {
+    /// relative motion of the current frame
+    motion: (i32, i32),
+    /// scroll steps of the current frame
+    scroll: (i32, i32),
+    /// the absolute position, as far as reported
+    position: (Option<i32>, Option<i32>),
+    /// the absolute position at the end of the previous frame, while touching
+    reported: Option<(i32, i32)>,
+    /// the finger was lifted, devices without touch are always touched
+    lifted: bool,
+    /// events were dropped, everything up to the next frame is discarded
+    dropped: bool,
+}
+
+impl Decoder {
+    /// Decodes an event, reporting the event data it completes.
+    pub fn feed(&mut self, event: &InputEvent, mut report: impl FnMut(EventData)) {
+        if self.dropped {
+            // the state is unknown until the next complete frame
+            if (event.event_type, event.code) == (EV_SYN, SYN_REPORT) {
+                *self = Decoder::default();
+            }
+            return;
+        }
+
+        match (event.event_type, event.code) {
+            (EV_SYN, SYN_REPORT) => self.flush(&mut report),
+            (EV_SYN, SYN_DROPPED) => self.dropped = true,
+            (EV_KEY, BTN_TOUCH) => self.lifted = event.value == 0,
+            (EV_KEY, code) if !BUTTONS.contains(&code) => {
+                let keycode = u32::from(code) + X11_KEYCODE_OFFSET;
+                // repeats are reported as presses, like X11 does
+                report(match event.value {
+                    0 => EventData::KeyRelease(keycode),
+                    _ => EventData::KeyPress(keycode),
+                });
+            }
+            (EV_KEY, code) => {
+                if let Some(button) = button_number(code) {
+                    report(match event.value {
+                        0 => EventData::PointerRelease(button),
+                        _ => EventData::PointerPress(button),
+                    });
+                }
+            }
+            (EV_REL, REL_X) => self.motion.0 = self.motion.0.saturating_add(event.value),
+            (EV_REL, REL_Y) => self.motion.1 = self.motion.1.saturating_add(event.value),
+            // wheels report positive values for scrolling up and right
+            (EV_REL, REL_WHEEL) => self.scroll.1 = self.scroll.1.saturating_sub(event.value),
+            (EV_REL, REL_HWHEEL) => self.scroll.0 = self.scroll.0.saturating_add(event.value),
+            (EV_ABS, ABS_X) => self.position.0 = Some(event.value),
+            (EV_ABS, ABS_Y) => self.position.1 = Some(event.value),
+            _ => {}
+        }
+    }
+
+    /// Reports the motion and scrolling of the frame that just ended.
+    fn flush(&mut self, report: &mut impl FnMut(EventData)) {
+        if self.lifted {
+            self.reported = None;
+        } else if let (Some(x), Some(y)) = self.position {
+            // a new touch starts where the finger is, without moving the pointer
+            if let Some((last_x, last_y)) = self.reported.replace((x, y)) {
+                self.motion.0 = self.motion.0.saturating_add(x.saturating_sub(last_x));
+                self.motion.1 = self.motion.1.saturating_add(y.saturating_sub(last_y));
+            }
+        }
+
+        let (dx, dy) = std::mem::take(&mut self.motion);
+        if (dx, dy) != (0, 0) {
+            let (dx, dy) = (f64::from(dx), f64::from(dy));
+            report(EventData::PointerMotion { dx, dy });
+        }
+        let (dx, dy) = std::mem::take(&mut self.scroll);
+        if (dx, dy) != (0, 0) {
+            let (dx, dy) = (f64::from(dx), f64::from(dy));
+            report(EventData::Scroll { dx, dy });
+        }
+    }
+}
+
+/// Returns the X11 button number of a pointer button, `None` for other buttons.
+fn button_number(code: u16) -> Option<u8> {
+    match code {
+        BTN_LEFT => Some(1),
+        BTN_MIDDLE => Some(2),
+        BTN_RIGHT => Some(3),
+        BTN_SIDE | BTN_BACK => Some(8),
+        BTN_EXTRA | BTN_FORWARD => Some(9),
+        _ => None,
+    }
+}
+
+/// Reads the events of a dump until its end, for replaying a recorded device.
+pub fn read_events(mut dump: impl Read) -> impl Iterator<Item = io::Result<InputEvent>> {
+    std::iter::from_fn(move || {
+        let mut data = [0; EVENT_SIZE];
+        match dump.read_exact(&mut data) {
+            Ok(()) => Some(Ok(InputEvent::parse(&data))),
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => None,
+            Err(err) => Some(Err(err)),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(event_type: u16, code: u16, value: i32) -> InputEvent {
+        InputEvent {
+            time: (1_700_000_000, 250_000),
+            event_type,
+            code,
+            value,
+        }
+    }
+
+    const SYN: (u16, u16, i32) = (EV_SYN, SYN_REPORT, 0);
+
+    /// Replays a dump made of the events, returning the reported data.
+    fn replay(events: &[(u16, u16, i32)]) -> Vec<EventData> {
+        let dump: Vec<u8> = events
+            .iter()
+            .flat_map(|&(event_type, code, value)| event(event_type, code, value).encode())
+            .collect();
+        let mut decoder = Decoder::default();
+        let mut reported = Vec::new();
+        for event in read_events(dump.as_slice()) {
+            decoder.feed(&event.unwrap(), |data| reported.push(data));
+        }
+        reported
+    }
+
+    #[test]
+    fn round_trips_events() {
+        let event = event(EV_KEY, KEY_A, 1);
+        assert_eq!(InputEvent::parse(&event.encode()), event);
+        assert_eq!(event.timestamp().timestamp_micros(), 1_700_000_000_250_000);
+    }
+
+    #[test]
+    fn reports_keys_with_x11_keycodes() {
+        let reported = replay(&[
+            (EV_KEY, KEY_A, 1),
+            SYN,
+            (EV_KEY, KEY_A, 2),
+            SYN,
+            (EV_KEY, KEY_A, 0),
+            SYN,
+        ]);
+        let expected = [
+            EventData::KeyPress(38),
+            EventData::KeyPress(38),
+            EventData::KeyRelease(38),
+        ];
+        assert_eq!(reported, expected);
+    }
+
+    #[test]
+    fn reports_mouse_frames() {
+        let reported = replay(&[
+            (EV_REL, REL_X, 3),
+            (EV_REL, REL_Y, -2),
+            SYN,
+            (EV_KEY, BTN_RIGHT, 1),
+            SYN,
+            (EV_REL, REL_WHEEL, 1),
+            (EV_REL, REL_HWHEEL, -1),
+            SYN,
+            // buttons of other tools are not pointer buttons
+            (EV_KEY, 0x120, 1),
+            SYN,
+        ]);
+        let expected = [
+            EventData::PointerMotion { dx: 3.0, dy: -2.0 },
+            EventData::PointerPress(3),
+            EventData::Scroll { dx: -1.0, dy: -1.0 },
+        ];
+        assert_eq!(reported, expected);
+    }
+
+    #[test]
+    fn reports_touchpad_motion_between_touches() {
+        let reported = replay(&[
+            (EV_KEY, BTN_TOUCH, 1),
+            (EV_ABS, ABS_X, 100),
+            (EV_ABS, ABS_Y, 200),
+            SYN,
+            (EV_ABS, ABS_X, 110),
+            SYN,
+            (EV_KEY, BTN_TOUCH, 0),
+            SYN,
+            // a new touch elsewhere does not move the pointer
+            (EV_KEY, BTN_TOUCH, 1),
+            (EV_ABS, ABS_X, 500),
+            (EV_ABS, ABS_Y, 500),
+            SYN,
+            (EV_ABS, ABS_Y, 490),
+            SYN,
+        ]);
+        let expected = [
+            EventData::PointerMotion { dx: 10.0, dy: 0.0 },
+            EventData::PointerMotion { dx: 0.0, dy: -10.0 },
+        ];
+        assert_eq!(reported, expected);
+    }
+
+    #[test]
+    fn discards_frames_after_dropped_events() {
+        let reported = replay(&[
+            (EV_REL, REL_X, 5),
+            (EV_SYN, SYN_DROPPED, 0),
+            (EV_KEY, KEY_A, 0),
+            SYN,
+            (EV_REL, REL_X, 1),
+            SYN,
+        ]);
+        assert_eq!(reported, [EventData::PointerMotion { dx: 1.0, dy: 0.0 }]);
+    }
+
+    #[test]
+    fn stops_at_a_truncated_event() {
+        let mut dump = event(EV_KEY, KEY_A, 1).encode().to_vec();
+        dump.extend_from_slice(&[0; EVENT_SIZE - 1]);
+        assert_eq!(read_events(dump.as_slice()).count(), 1);
+    }
+}
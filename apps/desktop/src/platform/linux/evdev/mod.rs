@@ -0,0 +1,433 @@
+//! The evdev backend, reading the input devices in `/dev/input` directly.
+//!
+//! This works the same in X11, Wayland and console sessions, but needs read
+//! access to the devices, usually through membership in the `input` group.
+//! The application the input goes to is looked up through the focus backend of
+//! the session, if there is one. Devices plugged in after starting are not read.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::fd::AsRawFd;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+use std::time::Instant;
+
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+
+use crate::event::{self, Device, DeviceKind};
+use crate::platform::{
+    self, Backend, Capabilities, FocusTracker, Recorder, Settings, UNKNOWN_APP, WindowProvider,
+};
+
+pub mod decode;
+
+use decode::{Decoder, EVENT_SIZE, InputEvent};
+
+/// How often the worker checks whether it was stopped, in milliseconds.
+const POLL_TIMEOUT: libc::c_int = 250;
+
+/// Records input from evdev devices, attributing it to the focused window.
+#[derive(Default)]
+pub struct EvdevRecorder {
+    /// the settings of the recorder following the focused window
+    settings: Settings,
+    stopped: Arc<AtomicBool>,
+    /// the recorder following the focused window
+    focus: Option<Box<dyn Recorder>>,
+    worker: Option<JoinHandle<io::Result<()>>>,
+}
+
+impl EvdevRecorder {
+    pub fn new(settings: &Settings) -> Self {
+        EvdevRecorder {
+            settings: settings.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+impl Recorder for EvdevRecorder {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            titles: true,
+            input: true,
+            idle: false,
+        }
+    }
+
+    fn start(&mut self, callback: platform::Callback) -> Result<(), platform::Error> {
+        if self.worker.is_some() {
+            return Err("the evdev recorder is already started".into());
+        }
+
+        let sources = open_devices(Path::new("/dev/input"))?;
+        if sources.is_empty() {
+            return Err(
+                "no input device could be read, this needs membership in the input group".into(),
+            );
+        }
+
+        let callback: Arc<platform::Callback> = Arc::new(callback);
+        let windows = self.windows(&callback)?;
+        self.stopped = Arc::default();
+        let stopped = Arc::clone(&self.stopped);
+        self.worker = Some(std::thread::spawn(move || {
+            run(sources, windows, &*callback, &stopped)
+        }));
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<(), platform::Error> {
+        self.stopped.store(true, Ordering::Relaxed);
+        if let Some(mut focus) = self.focus.take() {
+            focus.stop()?;
+        }
+        self.wait()
+    }
+
+    fn wait(&mut self) -> Result<(), platform::Error> {
+        match self.worker.take() {
+            Some(worker) => match worker.join() {
+                Ok(result) => Ok(result?),
+                Err(panic) => std::panic::resume_unwind(panic),
+            },
+            None => Ok(()),
+        }
+    }
+}
+
+impl EvdevRecorder {
+    /// Returns a provider of the focused window for the session. Recorders that
+    /// report focus changes are started along, and their events reported as well.
+    fn windows(
+        &mut self,
+        callback: &Arc<platform::Callback>,
+    ) -> Result<Option<Box<dyn WindowProvider>>, platform::Error> {
+        match Backend::detect() {
+            Ok(Backend::X11 | Backend::XInput | Backend::Poll) => {
+                match super::x11::active::ActiveWindow::connect() {
+                    Ok(windows) => Ok(Some(Box::new(windows))),
+                    Err(err) => {
+                        warn!(
+                            "Input is not attributed to applications, the X server is not available: {err}"
+                        );
+                        Ok(None)
+                    }
+                }
+            }
+            Ok(Backend::Evdev) | Err(_) => {
+                warn!("Input is not attributed to applications, no display server found");
+                Ok(None)
+            }
+            Ok(backend) => {
+                let tracker = FocusTracker::default();
+                let (tracked, callback) = (tracker.clone(), Arc::clone(callback));
+                let mut focus = backend.recorder(&self.settings)?;
+                focus.start(Box::new(move |event| {
+                    tracked.track(event);
+                    callback(event);
+                }))?;
+                info!("Attributing input to applications through the {backend} backend");
+                self.focus = Some(focus);
+                Ok(Some(Box::new(tracker)))
+            }
+        }
+    }
+}
+
+/// An opened input device.
+struct Source {
+    file: File,
+    device: Arc<Device>,
+    decoder: Decoder,
+}
+
+/// Opens the event devices in the directory which can be read.
+fn open_devices(dir: &Path) -> io::Result<Vec<Source>> {
+    let mut sources = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        // the devices are named eventN, next to the legacy mouseN and jsN
+        let Some(id) = path
+            .file_name()
+            .and_then(|name| name.to_str()?.strip_prefix("event")?.parse().ok())
+        else {
+            continue;
+        };
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(err) => {
+                warn!("Skipping input device {}: {err}", path.display());
+                continue;
+            }
+        };
+        let device = Device {
+            id: Some(id),
+            name: device_name(&file).unwrap_or_default(),
+            kind: device_kind(&file).unwrap_or(DeviceKind::Other),
+        };
+        info!(
+            "Reading {:?} ({:?}) at {}",
+            device.name,
+            device.kind,
+            path.display()
+        );
+        sources.push(Source {
+            file,
+            device: Arc::new(device),
+            decoder: Decoder::default(),
+        });
+    }
+    Ok(sources)
+}
+
+/// Reads the devices until they are gone or the recorder is stopped.
+fn run(
+    mut sources: Vec<Source>,
+    mut windows: Option<Box<dyn WindowProvider>>,
+    callback: &impl Fn(&event::Event),
+    stopped: &AtomicBool,
+) -> io::Result<()> {
+    let mut buffer = [0; EVENT_SIZE * 64];
+    while !stopped.load(Ordering::Relaxed) && !sources.is_empty() {
+        let mut fds: Vec<libc::pollfd> = sources
+            .iter()
+            .map(|source| libc::pollfd {
+                fd: source.file.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            })
+            .collect();
+        // SAFETY: the pointer and length describe the vector of pollfd structs
+        let ready =
+            unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, POLL_TIMEOUT) };
+        if ready < 0 {
+            match io::Error::last_os_error() {
+                err if err.kind() == io::ErrorKind::Interrupted => continue,
+                err => return Err(err),
+            }
+        }
+
+        // walk backwards, so removing a device keeps the indices of the others
+        for (index, fd) in fds.iter().enumerate().rev() {
+            if fd.revents == 0 {
+                continue;
+            }
+            let Source {
+                file,
+                device,
+                decoder,
+            } = &mut sources[index];
+            match file.read(&mut buffer) {
+                Ok(len) => {
+                    for data in buffer[..len].chunks_exact(EVENT_SIZE) {
+                        let Ok(data) = data.try_into() else { continue };
+                        let event = InputEvent::parse(data);
+                        let timestamp = event.timestamp();
+                        decoder.feed(&event, |data| {
+                            report(callback, &mut windows, device, timestamp, data)
+                        });
+                    }
+                }
+                Err(err)
+                    if matches!(
+                        err.kind(),
+                        io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock
+                    ) => {}
+                // the device was unplugged
+                Err(err) if err.raw_os_error() == Some(libc::ENODEV) => {
+                    info!("Input device {:?} was removed", device.name);
+                    sources.remove(index);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Reports input of a device, attributed to the focused window if known, and
+/// to [`UNKNOWN_APP`] otherwise.
+fn report(
+    callback: &impl Fn(&event::Event),
+    windows: &mut Option<Box<dyn WindowProvider>>,
+    device: &Arc<Device>,
+    timestamp: DateTime<Utc>,
+    data: event::EventData,
+) {
+    let window = windows.as_mut().and_then(|windows| {
+        windows.active().unwrap_or_else(|err| {
+            warn!("Could not look up the focused window: {err}");
+            None
+        })
+    });
+    let window = window.unwrap_or_else(|| platform::Window {
+        app: UNKNOWN_APP.to_string(),
+        title: None,
+        workspace: None,
+        process: None,
+    });
+    callback(&event::Event {
+        timestamp,
+        instant: Instant::now(),
+        app: window.app,
+        title: window.title,
+        workspace: window.workspace,
+        device: Some(Arc::clone(device)),
+        process: window.process,
+        output: None,
+        data,
+    });
+}
+
+/// Reports the input of a dump of a device, as made by reading its event file,
+/// without attributing it to applications.
+pub fn replay(
+    dump: impl Read,
+    device: Device,
+    callback: &impl Fn(&event::Event),
+) -> io::Result<()> {
+    let device = Arc::new(device);
+    let mut decoder = Decoder::default();
+    for event in decode::read_events(dump) {
+        let event = event?;
+        let timestamp = event.timestamp();
+        decoder.feed(&event, |data| {
+            report(callback, &mut None, &device, timestamp, data)
+        });
+    }
+    Ok(())
+}
+
+/// Returns the name of a device.
+fn device_name(file: &File) -> io::Result<String> {
+    let mut name = [0; 256];
+    let len = ioctl_read(file, EVIOCGNAME, &mut name)?;
+    let name = &name[..len.min(name.len())];
+    let name = name.split(|&byte| byte == 0).next().unwrap_or_default();
+    Ok(String::from_utf8_lossy(name).into_owned())
+}
+
+/// Classifies a device by the events and keys it supports.
+fn device_kind(file: &File) -> io::Result<DeviceKind> {
+    let mut events = [0; 4];
+    ioctl_read(file, EVIOCGBIT, &mut events)?;
+    let mut keys = [0; 96];
+    ioctl_read(file, EVIOCGBIT + decode::EV_KEY as u8, &mut keys)?;
+    Ok(classify(&events, &keys))
+}
+
+fn classify(events: &[u8], keys: &[u8]) -> DeviceKind {
+    let has = |bits: &[u8], bit: u16| {
+        let bit = usize::from(bit);
+        bits.get(bit / 8)
+            .is_some_and(|byte| byte >> (bit % 8) & 1 == 1)
+    };
+    let touch = has(events, decode::EV_ABS) && has(keys, decode::BTN_TOUCH);
+    if has(keys, decode::KEY_A) {
+        DeviceKind::Keyboard
+    } else if touch && has(keys, decode::BTN_TOOL_FINGER) {
+        DeviceKind::Touchpad
+    } else if touch {
+        DeviceKind::Touchscreen
+    } else if has(events, decode::EV_REL) && has(keys, decode::BTN_LEFT) {
+        DeviceKind::Mouse
+    } else {
+        DeviceKind::Other
+    }
+}
+
+/// The number of the `EVIOCGNAME` ioctl.
+const EVIOCGNAME: u8 = 0x06;
+/// The number of the `EVIOCGBIT` ioctl for the supported event types, add the
+/// type to get the supported codes of a type.
+const EVIOCGBIT: u8 = 0x20;
+
+/// Runs an evdev ioctl that reads into the buffer, returning the length read.
+fn ioctl_read(file: &File, number: u8, buffer: &mut [u8]) -> io::Result<usize> {
+    // _IOC(_IOC_READ, 'E', number, size)
+    let request = (2 << 30)
+        | ((buffer.len() as libc::c_ulong) << 16)
+        | ((b'E' as libc::c_ulong) << 8)
+        | libc::c_ulong::from(number);
+    // SAFETY: the request encodes the size of the buffer, which the kernel does not write past
+    let len = unsafe { libc::ioctl(file.as_raw_fd(), request as _, buffer.as_mut_ptr()) };
+    match len {
+        0.. => Ok(len as usize),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+
+    fn bits(bits: &[u16], len: usize) -> Vec<u8> {
+        let mut bytes = vec![0; len];
+        for &bit in bits {
+            bytes[usize::from(bit) / 8] |= 1 << (bit % 8);
+        }
+        bytes
+    }
+
+    #[test]
+    fn classifies_devices() {
+        use decode::{BTN_LEFT, BTN_TOOL_FINGER, BTN_TOUCH, EV_ABS, EV_KEY, EV_REL, KEY_A};
+        let kind = |events: &[u16], keys: &[u16]| classify(&bits(events, 4), &bits(keys, 96));
+        assert_eq!(kind(&[EV_KEY], &[KEY_A]), DeviceKind::Keyboard);
+        assert_eq!(kind(&[EV_KEY, EV_REL], &[BTN_LEFT]), DeviceKind::Mouse);
+        let touchpad = kind(&[EV_KEY, EV_ABS], &[BTN_LEFT, BTN_TOUCH, BTN_TOOL_FINGER]);
+        assert_eq!(touchpad, DeviceKind::Touchpad);
+        assert_eq!(
+            kind(&[EV_KEY, EV_ABS], &[BTN_TOUCH]),
+            DeviceKind::Touchscreen
+        );
+        assert_eq!(kind(&[EV_KEY], &[116]), DeviceKind::Other);
+    }
+
+    #[test]
+    fn replays_dumps_with_device_metadata() {
+        let dump: Vec<u8> = [(decode::EV_KEY, decode::KEY_A, 1), (decode::EV_SYN, 0, 0)]
+            .iter()
+            .flat_map(|&(event_type, code, value)| {
+                let event = InputEvent {
+                    time: (1_700_000_000, 0),
+                    event_type,
+                    code,
+                    value,
+                };
+                event.encode()
+            })
+            .collect();
+        let device = Device {
+            id: Some(3),
+            name: "AT Translated Set 2 keyboard".into(),
+            kind: DeviceKind::Keyboard,
+        };
+
+        let reported = RefCell::new(Vec::new());
+        let callback = |event: &event::Event| {
+            let device = event.device.as_deref().cloned();
+            reported.borrow_mut().push((
+                event.data,
+                event.timestamp.timestamp(),
+                device,
+                event.app.clone(),
+            ));
+        };
+        replay(dump.as_slice(), device.clone(), &callback).unwrap();
+
+        let expected = (
+            event::EventData::KeyPress(38),
+            1_700_000_000,
+            Some(device),
+            UNKNOWN_APP.to_string(),
+        );
+        assert_eq!(reported.into_inner(), [expected]);
+    }
+}
@@ -0,0 +1,266 @@
+//! The IPC of Hyprland.
+//!
+//! Hyprland answers requests on `.socket.sock` and writes events to every
+//! client of `.socket2.sock`, one per line as `name>>data`.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+
+use log::{info, warn};
+use serde_json::Value;
+
+use crate::event;
+use crate::platform::{self, Capabilities, Recorder};
+
+use super::{Error, Focus, Window, Worker};
+
+/// Follows the focused window through the IPC of Hyprland.
+#[derive(Default)]
+pub struct HyprlandRecorder {
+    worker: Worker,
+}
+
+impl Recorder for HyprlandRecorder {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            titles: true,
+            input: false,
+            idle: false,
+        }
+    }
+
+    fn start(&mut self, callback: platform::Callback) -> Result<(), platform::Error> {
+        if self.worker.is_started() {
+            return Err("the Hyprland recorder is already started".into());
+        }
+
+        let dir = socket_dir(
+            |name| std::env::var(name).ok().filter(|value| !value.is_empty()),
+            Path::exists,
+        )?;
+        let stream = UnixStream::connect(dir.join(".socket2.sock"))?;
+        let socket = stream.try_clone()?;
+        info!("Connected to Hyprland at {}", dir.display());
+
+        // Subscribe first, so no change between the query and the first event is lost
+        let mut events = Events::default();
+        match query(&dir.join(".socket.sock"), "j/activewindow") {
+            Ok(active) => events.init(&active, &callback),
+            Err(err) => warn!("Could not query the active window: {err}"),
+        }
+
+        self.worker.start(socket, move || {
+            events.run(BufReader::new(stream), &callback)
+        });
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<(), platform::Error> {
+        self.worker.stop()
+    }
+
+    fn wait(&mut self) -> Result<(), platform::Error> {
+        self.worker.wait()
+    }
+}
+
+/// Returns the directory of the sockets of the running instance, as found
+/// through `var`. Hyprland moved them from `/tmp` to the runtime directory.
+fn socket_dir(
+    var: impl Fn(&str) -> Option<String>,
+    exists: impl Fn(&Path) -> bool,
+) -> Result<PathBuf, Error> {
+    let instance = var("HYPRLAND_INSTANCE_SIGNATURE")
+        .ok_or(Error::NoSocket("HYPRLAND_INSTANCE_SIGNATURE is not set"))?;
+    let runtime_dir =
+        var("XDG_RUNTIME_DIR").map(|dir| Path::new(&dir).join("hypr").join(&instance));
+    match runtime_dir {
+        Some(dir) if exists(&dir) => Ok(dir),
+        _ => Ok(Path::new("/tmp/hypr").join(instance)),
+    }
+}
+
+/// Sends a request and returns the parsed reply.
+fn query(socket: &Path, request: &str) -> Result<Value, Error> {
+    let mut stream = UnixStream::connect(socket)?;
+    stream.write_all(request.as_bytes())?;
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply)?;
+    Ok(serde_json::from_str(&reply)?)
+}
+
+/// Addresses are formatted with a `0x` prefix in replies, but without in events.
+fn address(address: &str) -> &str {
+    address.strip_prefix("0x").unwrap_or(address)
+}
+
+/// Applies the events to the focus.
+#[derive(Default)]
+struct Events {
+    focus: Focus,
+    /// the class and title of an `activewindow` event, which is followed by an
+    /// `activewindowv2` event with the address of the window
+    active: Option<(String, String)>,
+}
+
+impl Events {
+    /// Takes the focus from the reply to `j/activewindow`, which is empty if no window is focused.
+    fn init(&mut self, active: &Value, callback: &impl Fn(&event::Event)) {
+        let field = |name| active.get(name).and_then(Value::as_str);
+        let Some(id) = field("address") else {
+            return;
+        };
+        let workspace = active
+            .get("workspace")
+            .and_then(|workspace| workspace.get("name"));
+        self.focus.workspace = workspace.and_then(Value::as_str).map(str::to_string);
+        let window = Window {
+            id: address(id).to_string(),
+            app: field("class")
+                .filter(|class| !class.is_empty())
+                .map(str::to_string),
+            title: field("title").map(str::to_string),
+        };
+        self.focus.focus(Some(window), callback);
+    }
+
+    /// Reports events until Hyprland closes the connection.
+    fn run(
+        &mut self,
+        reader: impl BufRead,
+        callback: &impl Fn(&event::Event),
+    ) -> Result<(), Error> {
+        for line in reader.lines() {
+            self.handle(&line?, callback);
+        }
+        Ok(())
+    }
+
+    fn handle(&mut self, line: &str, callback: &impl Fn(&event::Event)) {
+        let Some((name, data)) = line.split_once(">>") else {
+            return;
+        };
+        match name {
+            // the title may contain commas, the class does not
+            "activewindow" => {
+                self.active = data
+                    .split_once(',')
+                    .map(|(class, title)| (class.to_string(), title.to_string()));
+            }
+            "activewindowv2" => {
+                let (app, title) = self.active.take().unzip();
+                let window = Some(data).filter(|id| !id.is_empty()).map(|id| Window {
+                    id: address(id).to_string(),
+                    app: app.filter(|class| !class.is_empty()),
+                    title,
+                });
+                self.focus.focus(window, callback);
+            }
+            "windowtitlev2" => {
+                if let Some((id, title)) = data.split_once(',') {
+                    self.focus
+                        .title(address(id), Some(title.to_string()), callback);
+                }
+            }
+            "closewindow" => self.focus.close(address(data), callback),
+            // the focused monitor changes along with its active workspace
+            "workspace" => self.focus.workspace = Some(data.to_string()),
+            "focusedmon" => {
+                if let Some((_, workspace)) = data.split_once(',') {
+                    self.focus.workspace = Some(workspace.to_string());
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+
+    /// Events as written by Hyprland, while switching from kitty to firefox and
+    /// to an empty workspace.
+    const EVENTS: &str = "\
+activewindow>>kitty,~
+activewindowv2>>55d1c0a0e2b0
+windowtitle>>55d1c0a0e2b0
+windowtitlev2>>55d1c0a0e2b0,vim
+openwindow>>55d1c0b4c5a0,2,firefox,Mozilla Firefox
+activewindow>>firefox,Mozilla Firefox, the web
+activewindowv2>>55d1c0b4c5a0
+workspace>>3
+workspacev2>>3,3
+activewindow>>,
+activewindowv2>>
+closewindow>>55d1c0a0e2b0
+";
+
+    #[test]
+    fn reports_focus_changes_with_workspaces() {
+        let reported = RefCell::new(Vec::new());
+        let callback = |event: &event::Event| {
+            let title = event.title.clone().unwrap_or_default();
+            let workspace = event.workspace.clone().unwrap_or_default();
+            reported
+                .borrow_mut()
+                .push((event.data, event.app.clone(), title, workspace));
+        };
+
+        let mut events = Events::default();
+        let active = serde_json::from_str::<Value>(
+            r#"{"address": "0x55d1c0a0e2b0", "class": "kitty", "title": "~",
+                "workspace": {"id": 2, "name": "2"}}"#,
+        );
+        events.init(&active.unwrap(), &callback);
+        events.run(EVENTS.as_bytes(), &callback).unwrap();
+
+        use event::EventData::{FocusIn, FocusOut, TitleChange};
+        let expected = [
+            (FocusIn, "kitty", "~", "2"),
+            (TitleChange, "kitty", "vim", "2"),
+            (FocusOut, "kitty", "vim", "2"),
+            (FocusIn, "firefox", "Mozilla Firefox, the web", "2"),
+            (FocusOut, "firefox", "Mozilla Firefox, the web", "2"),
+        ];
+        let expected: Vec<_> = expected
+            .iter()
+            .map(|&(data, app, title, workspace)| {
+                (
+                    data,
+                    app.to_string(),
+                    title.to_string(),
+                    workspace.to_string(),
+                )
+            })
+            .collect();
+        assert_eq!(reported.into_inner(), expected);
+    }
+
+    #[test]
+    fn ignores_an_empty_active_window() {
+        let mut events = Events::default();
+        events.init(
+            &serde_json::from_str::<Value>("{}").unwrap(),
+            &|_: &event::Event| panic!("nothing is focused"),
+        );
+        assert_eq!(events.focus.window, None);
+    }
+
+    #[test]
+    fn locates_the_sockets_of_the_instance() {
+        let var = |name: &str| match name {
+            "HYPRLAND_INSTANCE_SIGNATURE" => Some("abc_123".to_string()),
+            "XDG_RUNTIME_DIR" => Some("/run/user/1000".to_string()),
+            _ => None,
+        };
+        let dir = socket_dir(var, |_| true).unwrap();
+        assert_eq!(dir, PathBuf::from("/run/user/1000/hypr/abc_123"));
+        let dir = socket_dir(var, |_| false).unwrap();
+        assert_eq!(dir, PathBuf::from("/tmp/hypr/abc_123"));
+        assert!(socket_dir(|_| None, |_| true).is_err());
+    }
+}
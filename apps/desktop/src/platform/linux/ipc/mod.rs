@@ -0,0 +1,236 @@
+//! Backends that follow the focused window through the IPC of the window manager,
+//! which also tells which workspace the user is on.
+
+use std::fmt;
+use std::io;
+use std::net::Shutdown;
+use std::os::unix::net::UnixStream;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+use std::time::Instant;
+
+use crate::event;
+use crate::platform;
+
+pub mod hyprland;
+pub mod sway;
+
+/// Errors that stop an IPC recorder.
+#[derive(Debug)]
+pub enum Error {
+    /// the IPC socket could not be located
+    NoSocket(&'static str),
+    /// reading from or writing to the socket failed
+    Io(io::Error),
+    /// a message could not be parsed
+    Json(serde_json::Error),
+    /// the window manager sent something we did not expect
+    Protocol(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NoSocket(reason) => write!(f, "cannot find the IPC socket: {reason}"),
+            Error::Io(err) => write!(f, "IPC connection failed: {err}"),
+            Error::Json(err) => write!(f, "malformed IPC message: {err}"),
+            Error::Protocol(reason) => write!(f, "unexpected IPC message: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            Error::Json(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::Json(err)
+    }
+}
+
+/// A window as reported by the window manager.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Window {
+    /// identifies the window for later events
+    id: String,
+    /// the app id of Wayland clients, the class of X11 clients
+    app: Option<String>,
+    title: Option<String>,
+}
+
+/// Follows the focused window and workspace, reporting the focus changes.
+#[derive(Default)]
+struct Focus {
+    window: Option<Window>,
+    /// the workspace the focused window was focused on, which its events are reported with
+    focused_on: Option<String>,
+    /// the focused workspace
+    workspace: Option<String>,
+}
+
+impl Focus {
+    /// Moves the focus to a window, or away from all windows.
+    /// A new title for the focused window is reported as a title change.
+    fn focus(&mut self, window: Option<Window>, callback: &impl Fn(&event::Event)) {
+        match (&self.window, window) {
+            (Some(focused), Some(window)) if focused.id == window.id => {
+                self.title(&window.id, window.title, callback);
+            }
+            (_, window) => {
+                if let Some(focused) = self.window.take() {
+                    self.report(&focused, event::EventData::FocusOut, callback);
+                }
+                if let Some(window) = &window {
+                    self.focused_on = self.workspace.clone();
+                    self.report(window, event::EventData::FocusIn, callback);
+                }
+                self.window = window;
+            }
+        }
+    }
+
+    /// Updates the title of a window, reporting it if the window is focused.
+    fn title(&mut self, id: &str, title: Option<String>, callback: &impl Fn(&event::Event)) {
+        match &mut self.window {
+            Some(focused) if focused.id == id && focused.title != title => {
+                focused.title = title;
+                let focused = focused.clone();
+                self.report(&focused, event::EventData::TitleChange, callback);
+            }
+            _ => {}
+        }
+    }
+
+    /// Forgets a closed window.
+    fn close(&mut self, id: &str, callback: &impl Fn(&event::Event)) {
+        if self.window.as_ref().is_some_and(|focused| focused.id == id) {
+            self.focus(None, callback);
+        }
+    }
+
+    /// Reports an event for a window, if it has an app.
+    fn report(&self, window: &Window, data: event::EventData, callback: &impl Fn(&event::Event)) {
+        if let Some(app) = &window.app {
+            callback(&event::Event {
+                timestamp: chrono::Utc::now(),
+                instant: Instant::now(),
+                app: app.clone(),
+                title: window.title.clone(),
+                workspace: self.focused_on.clone(),
+                device: None,
+                process: None,
+                output: None,
+                data,
+            });
+        }
+    }
+}
+
+/// A worker reading from an IPC socket, which is stopped by shutting the socket down.
+#[derive(Default)]
+struct Worker {
+    stopped: Arc<AtomicBool>,
+    running: Option<(UnixStream, JoinHandle<Result<(), Error>>)>,
+}
+
+impl Worker {
+    fn is_started(&self) -> bool {
+        self.running.is_some()
+    }
+
+    /// Runs `work` on a thread until it finishes or the socket is shut down.
+    fn start(
+        &mut self,
+        socket: UnixStream,
+        work: impl FnOnce() -> Result<(), Error> + Send + 'static,
+    ) {
+        self.stopped = Arc::default();
+        let stopped = Arc::clone(&self.stopped);
+        let worker = std::thread::spawn(move || {
+            // shutting down the socket on stop makes reading fail as well
+            work().or_else(|err| match stopped.load(Ordering::Relaxed) {
+                true => Ok(()),
+                false => Err(err),
+            })
+        });
+        self.running = Some((socket, worker));
+    }
+
+    fn stop(&mut self) -> Result<(), platform::Error> {
+        if let Some((socket, _)) = &self.running {
+            self.stopped.store(true, Ordering::Relaxed);
+            socket.shutdown(Shutdown::Both)?;
+        }
+        self.wait()
+    }
+
+    fn wait(&mut self) -> Result<(), platform::Error> {
+        match self.running.take() {
+            Some((_, worker)) => match worker.join() {
+                Ok(result) => Ok(result?),
+                Err(panic) => std::panic::resume_unwind(panic),
+            },
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+
+    fn window(id: &str, app: &str, title: &str) -> Option<Window> {
+        Some(Window {
+            id: id.to_string(),
+            app: Some(app.to_string()),
+            title: Some(title.to_string()),
+        })
+    }
+
+    #[test]
+    fn reports_focus_and_title_changes() {
+        let reported = RefCell::new(Vec::new());
+        let callback = |event: &event::Event| {
+            let event = (event.data, event.app.clone(), event.title.clone());
+            reported.borrow_mut().push(event);
+        };
+
+        let mut focus = Focus::default();
+        focus.focus(window("1", "firefox", "Start"), &callback);
+        focus.focus(window("1", "firefox", "Docs"), &callback);
+        focus.title("2", Some("Unfocused".into()), &callback);
+        focus.focus(window("2", "kitty", "~"), &callback);
+        focus.close("2", &callback);
+        focus.close("1", &callback);
+
+        let reported = reported.into_inner();
+        let data: Vec<_> = reported.iter().map(|(data, _, _)| *data).collect();
+        assert_eq!(
+            data,
+            [
+                event::EventData::FocusIn,
+                event::EventData::TitleChange,
+                event::EventData::FocusOut,
+                event::EventData::FocusIn,
+                event::EventData::FocusOut,
+            ]
+        );
+        assert_eq!(reported[2].2.as_deref(), Some("Docs"));
+    }
+}
@@ -0,0 +1,428 @@
+//! The IPC of sway and i3, see sway-ipc(7).
+//!
+//! Messages start with the `i3-ipc` magic, followed by the payload length and
+//! the message type as 32 bit integers in the host byte order, and a JSON payload.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+
+use log::info;
+use serde_json::Value;
+
+use crate::event;
+use crate::platform::{self, Capabilities, Recorder};
+
+use super::{Error, Focus, Window, Worker};
+
+const MAGIC: &[u8; 6] = b"i3-ipc";
+
+/// The size of the message header.
+const HEADER_SIZE: usize = MAGIC.len() + 8;
+
+/// Message types of requests, replies use the type of their request.
+mod message_type {
+    pub const SUBSCRIBE: u32 = 2;
+    pub const GET_TREE: u32 = 4;
+
+    /// events have the highest bit set
+    pub const EVENT_WORKSPACE: u32 = 0x8000_0000;
+    pub const EVENT_WINDOW: u32 = 0x8000_0003;
+}
+
+/// Follows the focused window through the IPC of sway or i3.
+#[derive(Default)]
+pub struct SwayRecorder {
+    worker: Worker,
+}
+
+impl Recorder for SwayRecorder {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            titles: true,
+            input: false,
+            idle: false,
+        }
+    }
+
+    fn start(&mut self, callback: platform::Callback) -> Result<(), platform::Error> {
+        if self.worker.is_started() {
+            return Err("the sway recorder is already started".into());
+        }
+
+        let path = socket_path(|name| std::env::var(name).ok().filter(|value| !value.is_empty()))?;
+        let stream = UnixStream::connect(&path)?;
+        let socket = stream.try_clone()?;
+        let mut ipc = Ipc::new(stream)?;
+        let mut focus = Focus::default();
+        ipc.setup(&mut focus, &callback)?;
+        info!("Connected to the window manager at {}", path.display());
+
+        self.worker
+            .start(socket, move || ipc.run(&mut focus, &callback));
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<(), platform::Error> {
+        self.worker.stop()
+    }
+
+    fn wait(&mut self) -> Result<(), platform::Error> {
+        self.worker.wait()
+    }
+}
+
+/// Returns the path of the IPC socket, as found through `var`.
+fn socket_path(var: impl Fn(&str) -> Option<String>) -> Result<PathBuf, Error> {
+    var("SWAYSOCK")
+        .or_else(|| var("I3SOCK"))
+        .map(PathBuf::from)
+        .ok_or(Error::NoSocket("neither SWAYSOCK nor I3SOCK is set"))
+}
+
+/// A connection to the IPC socket.
+struct Ipc {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+}
+
+impl Ipc {
+    fn new(stream: UnixStream) -> Result<Self, Error> {
+        Ok(Ipc {
+            writer: stream.try_clone()?,
+            reader: BufReader::new(stream),
+        })
+    }
+
+    fn send(&mut self, message_type: u32, payload: &str) -> Result<(), Error> {
+        let mut message = Vec::with_capacity(HEADER_SIZE + payload.len());
+        message.extend_from_slice(MAGIC);
+        message.extend_from_slice(&(payload.len() as u32).to_ne_bytes());
+        message.extend_from_slice(&message_type.to_ne_bytes());
+        message.extend_from_slice(payload.as_bytes());
+        Ok(self.writer.write_all(&message)?)
+    }
+
+    /// Reads the next reply or event, `None` once the window manager closed the connection.
+    fn read(&mut self) -> Result<Option<(u32, Value)>, Error> {
+        if self.reader.fill_buf()?.is_empty() {
+            return Ok(None);
+        }
+        let mut header = [0; HEADER_SIZE];
+        self.reader.read_exact(&mut header)?;
+        let (magic, header) = header.split_at(MAGIC.len());
+        if magic != MAGIC {
+            return Err(Error::Protocol("missing the i3-ipc magic".into()));
+        }
+        let [l0, l1, l2, l3, t0, t1, t2, t3] = header else {
+            unreachable!("the header has a fixed size");
+        };
+        let len = u32::from_ne_bytes([*l0, *l1, *l2, *l3]);
+        let message_type = u32::from_ne_bytes([*t0, *t1, *t2, *t3]);
+
+        let mut payload = String::new();
+        (&mut self.reader)
+            .take(len.into())
+            .read_to_string(&mut payload)?;
+        if payload.len() != len as usize {
+            return Err(Error::Io(std::io::ErrorKind::UnexpectedEof.into()));
+        }
+        Ok(Some((message_type, serde_json::from_str(&payload)?)))
+    }
+
+    /// Reads messages until the reply of a request, handling the events in between.
+    fn reply(
+        &mut self,
+        request: u32,
+        focus: &mut Focus,
+        callback: &impl Fn(&event::Event),
+    ) -> Result<Value, Error> {
+        loop {
+            match self.read()? {
+                Some((message_type, reply)) if message_type == request => return Ok(reply),
+                Some((message_type, event)) => handle(message_type, &event, focus, callback),
+                None => return Err(Error::Io(std::io::ErrorKind::UnexpectedEof.into())),
+            }
+        }
+    }
+
+    /// Looks up the focused window and subscribes to the events changing it.
+    fn setup(&mut self, focus: &mut Focus, callback: &impl Fn(&event::Event)) -> Result<(), Error> {
+        self.send(message_type::GET_TREE, "")?;
+        let tree = self.reply(message_type::GET_TREE, focus, callback)?;
+        if let Some((window, workspace)) = find_focused(&tree, None) {
+            focus.workspace = workspace;
+            focus.focus(window, callback);
+        }
+
+        self.send(message_type::SUBSCRIBE, r#"["window","workspace"]"#)?;
+        let reply = self.reply(message_type::SUBSCRIBE, focus, callback)?;
+        match reply.get("success").and_then(Value::as_bool) {
+            Some(true) => Ok(()),
+            _ => Err(Error::Protocol(format!("subscribing failed: {reply:?}"))),
+        }
+    }
+
+    /// Reports events until the window manager closes the connection.
+    fn run(&mut self, focus: &mut Focus, callback: &impl Fn(&event::Event)) -> Result<(), Error> {
+        while let Some((message_type, event)) = self.read()? {
+            handle(message_type, &event, focus, callback);
+        }
+        Ok(())
+    }
+}
+
+/// Applies a window or workspace event.
+fn handle(message_type: u32, event: &Value, focus: &mut Focus, callback: &impl Fn(&event::Event)) {
+    let change = event.get("change").and_then(Value::as_str);
+    match (message_type, change) {
+        (message_type::EVENT_WINDOW, Some(change)) => {
+            let Some(window) = event.get("container").and_then(window) else {
+                return;
+            };
+            match change {
+                "focus" => focus.focus(Some(window), callback),
+                "title" => focus.title(&window.id, window.title, callback),
+                "close" => focus.close(&window.id, callback),
+                _ => {}
+            }
+        }
+        (message_type::EVENT_WORKSPACE, Some("focus")) => {
+            let Some(current) = event.get("current") else {
+                return;
+            };
+            focus.workspace = current
+                .get("name")
+                .and_then(Value::as_str)
+                .map(str::to_string);
+            // no window focus event follows when switching to an empty workspace
+            if !has_children(current) {
+                focus.focus(None, callback);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Returns the window of a container.
+fn window(container: &Value) -> Option<Window> {
+    let id = container.get("id").and_then(Value::as_i64)?;
+    // X11 clients have no app id, but a class
+    let app = container.get("app_id").and_then(Value::as_str).or_else(|| {
+        let properties = container.get("window_properties")?;
+        properties.get("class").and_then(Value::as_str)
+    });
+    Some(Window {
+        id: id.to_string(),
+        app: app.map(str::to_string),
+        title: container
+            .get("name")
+            .and_then(Value::as_str)
+            .map(str::to_string),
+    })
+}
+
+fn has_children(node: &Value) -> bool {
+    ["nodes", "floating_nodes"].iter().any(|children| {
+        node.get(children)
+            .and_then(Value::as_array)
+            .is_some_and(|children| !children.is_empty())
+    })
+}
+
+/// Returns the focused window of a tree, `None` if an empty workspace is focused,
+/// and the name of the focused workspace.
+fn find_focused(node: &Value, workspace: Option<&str>) -> Option<(Option<Window>, Option<String>)> {
+    let node_type = node.get("type").and_then(Value::as_str);
+    let workspace = match node_type {
+        Some("workspace") => node.get("name").and_then(Value::as_str),
+        _ => workspace,
+    };
+
+    if node.get("focused").and_then(Value::as_bool) == Some(true) {
+        match node_type {
+            Some("con" | "floating_con") => {
+                return Some((window(node), workspace.map(str::to_string)));
+            }
+            Some("workspace") => return Some((None, workspace.map(str::to_string))),
+            _ => {}
+        }
+    }
+
+    ["nodes", "floating_nodes"]
+        .iter()
+        .filter_map(|children| node.get(children).and_then(Value::as_array))
+        .flatten()
+        .find_map(|child| find_focused(child, workspace))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use std::thread;
+
+    use super::*;
+
+    /// The relevant parts of a `GET_TREE` reply, with firefox focused on workspace 1.
+    const TREE: &str = r#"{
+      "id": 1, "type": "root", "name": "root", "focused": false,
+      "nodes": [{
+        "id": 3, "type": "output", "name": "eDP-1", "focused": false,
+        "nodes": [{
+          "id": 4, "type": "workspace", "name": "1", "focused": false,
+          "nodes": [{
+            "id": 5, "type": "con", "name": "Mozilla Firefox", "focused": true,
+            "app_id": "firefox", "nodes": [], "floating_nodes": []
+          }],
+          "floating_nodes": []
+        }],
+        "floating_nodes": []
+      }],
+      "floating_nodes": []
+    }"#;
+
+    /// Events as sent by sway, trimmed to the fields that are read.
+    const EVENTS: &[(u32, &str)] = &[
+        (
+            message_type::EVENT_WINDOW,
+            r#"{"change": "new", "container": {"id": 6, "type": "con", "name": null, "app_id": "foot", "focused": false}}"#,
+        ),
+        (
+            message_type::EVENT_WINDOW,
+            r#"{"change": "focus", "container": {"id": 6, "type": "con", "name": "~", "app_id": "foot", "focused": true}}"#,
+        ),
+        (
+            message_type::EVENT_WINDOW,
+            r#"{"change": "title", "container": {"id": 6, "type": "con", "name": "vim", "app_id": "foot", "focused": true}}"#,
+        ),
+        (
+            message_type::EVENT_WORKSPACE,
+            r#"{"change": "focus", "old": {"id": 4, "type": "workspace", "name": "1"}, "current": {"id": 7, "type": "workspace", "name": "2", "nodes": [], "floating_nodes": []}}"#,
+        ),
+        (
+            message_type::EVENT_WORKSPACE,
+            r#"{"change": "focus", "old": {"id": 7, "type": "workspace", "name": "2"}, "current": {"id": 4, "type": "workspace", "name": "1", "nodes": [{"id": 5}], "floating_nodes": []}}"#,
+        ),
+        (
+            message_type::EVENT_WINDOW,
+            r#"{"change": "focus", "container": {"id": 8, "type": "con", "name": "Steam", "app_id": null, "window_properties": {"class": "steam", "title": "Steam"}, "focused": true}}"#,
+        ),
+        (
+            message_type::EVENT_WINDOW,
+            r#"{"change": "close", "container": {"id": 8, "type": "con", "name": "Steam", "app_id": null, "focused": false}}"#,
+        ),
+    ];
+
+    /// Reads a request of the given type, returning its payload.
+    fn expect(sway: &mut Ipc, message_type: u32) -> String {
+        let mut header = [0; HEADER_SIZE];
+        sway.reader.read_exact(&mut header).unwrap();
+        assert_eq!(&header[..6], MAGIC);
+        let len = u32::from_ne_bytes(header[6..10].try_into().unwrap());
+        assert_eq!(
+            u32::from_ne_bytes(header[10..].try_into().unwrap()),
+            message_type
+        );
+        let mut payload = vec![0; len as usize];
+        sway.reader.read_exact(&mut payload).unwrap();
+        String::from_utf8(payload).unwrap()
+    }
+
+    /// Plays sway: answers the setup requests and replays the events.
+    fn serve(stream: UnixStream) {
+        let mut sway = Ipc::new(stream).unwrap();
+        expect(&mut sway, message_type::GET_TREE);
+        sway.send(message_type::GET_TREE, TREE).unwrap();
+
+        let subscribed = expect(&mut sway, message_type::SUBSCRIBE);
+        assert_eq!(
+            serde_json::from_str::<Value>(&subscribed)
+                .unwrap()
+                .as_array()
+                .unwrap()
+                .len(),
+            2
+        );
+        sway.send(message_type::SUBSCRIBE, r#"{"success": true}"#)
+            .unwrap();
+
+        for (message_type, event) in EVENTS {
+            sway.send(*message_type, event).unwrap();
+        }
+    }
+
+    #[test]
+    fn reports_focus_changes_with_workspaces() {
+        let (client, server) = UnixStream::pair().unwrap();
+        let server = thread::spawn(move || serve(server));
+
+        let reported = Mutex::new(Vec::new());
+        let callback = |event: &event::Event| {
+            let title = event.title.clone().unwrap_or_default();
+            let workspace = event.workspace.clone().unwrap_or_default();
+            let event = (event.data, event.app.clone(), title, workspace);
+            reported.lock().unwrap().push(event);
+        };
+        let mut ipc = Ipc::new(client).unwrap();
+        let mut focus = Focus::default();
+        ipc.setup(&mut focus, &callback).unwrap();
+        ipc.run(&mut focus, &callback).unwrap();
+        server.join().unwrap();
+
+        use event::EventData::{FocusIn, FocusOut, TitleChange};
+        let expected = [
+            (FocusIn, "firefox", "Mozilla Firefox", "1"),
+            (FocusOut, "firefox", "Mozilla Firefox", "1"),
+            (FocusIn, "foot", "~", "1"),
+            (TitleChange, "foot", "vim", "1"),
+            (FocusOut, "foot", "vim", "1"),
+            (FocusIn, "steam", "Steam", "1"),
+            (FocusOut, "steam", "Steam", "1"),
+        ];
+        let expected: Vec<_> = expected
+            .iter()
+            .map(|&(data, app, title, workspace)| {
+                (
+                    data,
+                    app.to_string(),
+                    title.to_string(),
+                    workspace.to_string(),
+                )
+            })
+            .collect();
+        assert_eq!(reported.into_inner().unwrap(), expected);
+    }
+
+    #[test]
+    fn fails_when_subscribing_is_rejected() {
+        let (client, server) = UnixStream::pair().unwrap();
+        let server = thread::spawn(move || {
+            let mut sway = Ipc::new(server).unwrap();
+            expect(&mut sway, message_type::GET_TREE);
+            let tree = r#"{"type": "root", "nodes": []}"#;
+            sway.send(message_type::GET_TREE, tree).unwrap();
+            expect(&mut sway, message_type::SUBSCRIBE);
+            sway.send(message_type::SUBSCRIBE, r#"{"success": false}"#)
+                .unwrap();
+        });
+
+        let mut ipc = Ipc::new(client).unwrap();
+        let result = ipc.setup(&mut Focus::default(), &|_: &event::Event| {});
+        server.join().unwrap();
+        assert!(matches!(result, Err(Error::Protocol(_))));
+    }
+
+    #[test]
+    fn prefers_the_sway_socket() {
+        let var = |name: &str| match name {
+            "SWAYSOCK" => Some("/run/user/1000/sway-ipc.sock".to_string()),
+            "I3SOCK" => Some("/run/user/1000/i3/ipc-socket".to_string()),
+            _ => None,
+        };
+        assert_eq!(
+            socket_path(var).unwrap(),
+            PathBuf::from("/run/user/1000/sway-ipc.sock")
+        );
+        assert!(socket_path(|_| None).is_err());
+    }
+}
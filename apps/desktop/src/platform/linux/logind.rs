@@ -0,0 +1,288 @@
+//! Follows screen locks and system sleep through the signals of systemd-logind.
+//!
+//! Neither shows up in the input or focus the other backends observe: a locked
+//! screen or a suspended laptop otherwise looks like a long focus period on the
+//! last active window. logind emits `Lock` and `Unlock` on the session object
+//! when the session is locked, and `PrepareForSleep` on the manager before
+//! suspending and after resuming.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::thread::JoinHandle;
+use std::time::Instant;
+
+use log::{info, warn};
+use zbus::blocking::Connection;
+use zbus::message::Type;
+use zbus::zvariant::OwnedObjectPath;
+use zbus::{MatchRule, Message};
+
+use crate::event;
+use crate::platform::{self, Capabilities, Recorder};
+
+use super::dbus;
+
+const LOGIND: &str = "org.freedesktop.login1";
+const MANAGER_PATH: &str = "/org/freedesktop/login1";
+const MANAGER: &str = "org.freedesktop.login1.Manager";
+const SESSION: &str = "org.freedesktop.login1.Session";
+
+/// Reports when the session is locked and when the system sleeps, to run along
+/// the recorder of a backend.
+#[derive(Default)]
+pub struct LogindRecorder {
+    stopped: Arc<AtomicBool>,
+    /// the bus connection and the worker following it
+    worker: Option<(Connection, JoinHandle<zbus::Result<()>>)>,
+}
+
+impl Recorder for LogindRecorder {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
+
+    fn start(&mut self, callback: platform::Callback) -> Result<(), platform::Error> {
+        if self.worker.is_some() {
+            return Err("the logind recorder is already started".into());
+        }
+
+        let conn = Connection::system()?;
+        // the id is set by pam_systemd, "auto" is the session of the caller
+        let id = std::env::var("XDG_SESSION_ID").unwrap_or_else(|_| "auto".into());
+        let (signals, session) = subscribe(&conn, &id)?;
+        match &session {
+            Some(session) => info!("Following logind session {session}"),
+            None => warn!("Not running in a logind session, screen locks are not reported"),
+        }
+
+        self.stopped = Arc::default();
+        let stopped = Arc::clone(&self.stopped);
+        let worker = std::thread::spawn(move || {
+            // closing the connection on stop makes reading fail as well
+            run(
+                signals,
+                session.as_ref().map(|path| path.as_str()),
+                &callback,
+            )
+            .or_else(|err| match stopped.load(Ordering::Relaxed) {
+                true => Ok(()),
+                false => Err(err),
+            })
+        });
+        self.worker = Some((conn, worker));
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<(), platform::Error> {
+        if let Some((conn, _)) = &self.worker {
+            self.stopped.store(true, Ordering::Relaxed);
+            conn.clone().close()?;
+        }
+        self.wait()
+    }
+
+    fn wait(&mut self) -> Result<(), platform::Error> {
+        match self.worker.take() {
+            Some((_, worker)) => match worker.join() {
+                Ok(result) => Ok(result?),
+                Err(panic) => std::panic::resume_unwind(panic),
+            },
+            None => Ok(()),
+        }
+    }
+}
+
+/// The signals of logind, as they are received.
+type Signals = mpsc::Receiver<zbus::Result<Message>>;
+
+/// Subscribes to the sleep signals, and to the lock signals of the session with
+/// the id if logind knows it, returning the object path of the session.
+fn subscribe(conn: &Connection, id: &str) -> zbus::Result<(Signals, Option<OwnedObjectPath>)> {
+    let signal = || MatchRule::builder().msg_type(Type::Signal).sender(LOGIND);
+    let sleep = signal()?
+        .path(MANAGER_PATH)?
+        .interface(MANAGER)?
+        .member("PrepareForSleep")?
+        .build();
+
+    let session =
+        match conn.call_method(Some(LOGIND), MANAGER_PATH, Some(MANAGER), "GetSession", &id) {
+            Ok(reply) => reply.body().deserialize::<OwnedObjectPath>()?,
+            // logind answers with NoSuchSession, for processes outside of a session
+            Err(zbus::Error::MethodError(name, message, _)) => {
+                let message = message.unwrap_or_default();
+                warn!("Cannot find logind session {id:?}: {name}: {message}");
+                return Ok((dbus::follow(conn, vec![sleep])?, None));
+            }
+            Err(err) => return Err(err),
+        };
+    let lock = signal()?.path(session.clone())?.interface(SESSION)?.build();
+    Ok((dbus::follow(conn, vec![sleep, lock])?, Some(session)))
+}
+
+/// Reports the signals until the connection closes.
+fn run(
+    signals: Signals,
+    session: Option<&str>,
+    callback: &impl Fn(&event::Event),
+) -> zbus::Result<()> {
+    for signal in signals {
+        if let Some(data) = decode(&signal?, session)? {
+            callback(&event::Event {
+                timestamp: chrono::Utc::now(),
+                instant: Instant::now(),
+                app: String::new(),
+                title: None,
+                workspace: None,
+                device: None,
+                process: None,
+                output: None,
+                data,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Returns the event for a signal of logind, if it is one we follow.
+fn decode(message: &Message, session: Option<&str>) -> zbus::Result<Option<event::EventData>> {
+    let header = message.header();
+    if message.message_type() != Type::Signal {
+        return Ok(None);
+    }
+    let interface = header.interface().map(|interface| interface.as_str());
+    let member = header.member().map(|member| member.as_str());
+    if interface == Some(MANAGER) && member == Some("PrepareForSleep") {
+        return Ok(Some(match message.body().deserialize::<bool>()? {
+            true => event::EventData::Suspend,
+            false => event::EventData::Resume,
+        }));
+    }
+    // locks of other sessions, like another user at the same seat, are not ours
+    let ours = session.is_some() && header.path().map(|path| path.as_str()) == session;
+    if !ours || interface != Some(SESSION) {
+        return Ok(None);
+    }
+    Ok(match member {
+        Some("Lock") => Some(event::EventData::Lock),
+        Some("Unlock") => Some(event::EventData::Unlock),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use zbus::zvariant::ObjectPath;
+
+    use super::*;
+
+    const SESSION_PATH: &str = "/org/freedesktop/login1/session/_32";
+
+    fn signal(path: &str, interface: &str, member: &str) -> zbus::message::Builder<'static> {
+        let path = ObjectPath::try_from(path.to_string()).unwrap();
+        let interface = interface.to_string();
+        let member = member.to_string();
+        Message::signal(path, interface, member).unwrap()
+    }
+
+    fn decoded(message: Message, session: Option<&str>) -> Option<event::EventData> {
+        decode(&message, session).unwrap()
+    }
+
+    #[test]
+    fn decodes_the_signals_of_the_session() {
+        use event::EventData::{Lock, Resume, Suspend, Unlock};
+        let sleep = |sleeping: bool| {
+            let message = signal(MANAGER_PATH, MANAGER, "PrepareForSleep");
+            message.build(&sleeping).unwrap()
+        };
+        assert_eq!(decoded(sleep(true), None), Some(Suspend));
+        assert_eq!(decoded(sleep(false), None), Some(Resume));
+
+        let session = Some(SESSION_PATH);
+        let event = |path, member| signal(path, SESSION, member).build(&()).unwrap();
+        assert_eq!(decoded(event(SESSION_PATH, "Lock"), session), Some(Lock));
+        assert_eq!(
+            decoded(event(SESSION_PATH, "Unlock"), session),
+            Some(Unlock)
+        );
+        let other = event("/org/freedesktop/login1/session/c1", "Lock");
+        assert_eq!(decoded(other, session), None);
+        assert_eq!(decoded(event(SESSION_PATH, "Lock"), None), None);
+    }
+
+    /// Answers `GetSession` for the session with the id "2", like logind does.
+    struct Manager;
+
+    #[zbus::interface(name = "org.freedesktop.login1.Manager")]
+    impl Manager {
+        fn get_session(&self, id: &str) -> zbus::fdo::Result<OwnedObjectPath> {
+            match id {
+                "2" => Ok(ObjectPath::try_from(SESSION_PATH).unwrap().into()),
+                _ => Err(zbus::fdo::Error::Failed("No session found".into())),
+            }
+        }
+    }
+
+    #[test]
+    #[ignore = "needs dbus-daemon"]
+    fn follows_a_logind_service() {
+        let bus = dbus::TestBus::start("logind");
+        let logind = bus.connect_as(LOGIND);
+        logind.object_server().at(MANAGER_PATH, Manager).unwrap();
+
+        // outside of a session only sleep is followed
+        let client = bus.connect();
+        let (_, session) = subscribe(&client, "c7").unwrap();
+        assert_eq!(session, None);
+        let client = bus.connect();
+        let (signals, session) = subscribe(&client, "2").unwrap();
+        assert_eq!(
+            session.as_ref().map(|path| path.as_str()),
+            Some(SESSION_PATH)
+        );
+
+        let (sender, events) = mpsc::channel();
+        let worker = thread::spawn(move || {
+            run(
+                signals,
+                session.as_ref().map(|path| path.as_str()),
+                &|event: &event::Event| {
+                    sender.send(event.data).unwrap();
+                },
+            )
+        });
+        let emit = |path, interface, member, body: &dyn Fn(_) -> Message| {
+            logind.send(&body(signal(path, interface, member))).unwrap();
+        };
+        let empty = |signal: zbus::message::Builder<'_>| signal.build(&()).unwrap();
+        emit(
+            "/org/freedesktop/login1/session/c1",
+            SESSION,
+            "Lock",
+            &empty,
+        );
+        emit(SESSION_PATH, SESSION, "Lock", &empty);
+        emit(MANAGER_PATH, MANAGER, "PrepareForSleep", &|signal| {
+            signal.build(&true).unwrap()
+        });
+        emit(MANAGER_PATH, MANAGER, "PrepareForSleep", &|signal| {
+            signal.build(&false).unwrap()
+        });
+        emit(SESSION_PATH, SESSION, "Unlock", &empty);
+
+        let timeout = Duration::from_secs(5);
+        let reported: Vec<_> = (0..4)
+            .map(|_| events.recv_timeout(timeout).unwrap())
+            .collect();
+        use event::EventData::{Lock, Resume, Suspend, Unlock};
+        assert_eq!(reported, [Lock, Suspend, Resume, Unlock]);
+
+        client.close().unwrap();
+        let _ = worker.join().unwrap();
+    }
+}
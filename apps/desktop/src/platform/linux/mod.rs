@@ -1 +1,9 @@
+pub mod dbus;
+pub mod evdev;
+pub mod ipc;
+pub mod logind;
+pub mod mpris;
+pub mod proc_events;
+pub mod process;
+pub mod wayland;
 pub mod x11;
@@ -0,0 +1,517 @@
+//! Follows media players through MPRIS, the D-Bus interface that browsers and
+//! players like mpv, VLC and Spotify implement on the session bus.
+//!
+//! Watching a video or listening to music shows up as a long period without
+//! input, which is otherwise taken for being away. Players own a name under
+//! `org.mpris.MediaPlayer2.` and emit `PropertiesChanged` when their playback
+//! status or track changes. Players coming and going are followed through
+//! `NameOwnerChanged`, keyed by the unique name of their connection, which is
+//! the sender of their signals.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::thread::JoinHandle;
+use std::time::Instant;
+
+use log::{info, warn};
+use zbus::blocking::Connection;
+use zbus::blocking::fdo::DBusProxy;
+use zbus::message::Type;
+use zbus::zvariant::{Dict, OwnedValue};
+use zbus::{MatchRule, Message};
+
+use crate::event;
+use crate::platform::{self, Capabilities, Recorder};
+
+use super::dbus;
+
+/// The prefix of the names players own, followed by the name of the player.
+const PLAYER_PREFIX: &str = "org.mpris.MediaPlayer2.";
+const MPRIS_PATH: &str = "/org/mpris/MediaPlayer2";
+const ROOT: &str = "org.mpris.MediaPlayer2";
+const PLAYER: &str = "org.mpris.MediaPlayer2.Player";
+const PROPERTIES: &str = "org.freedesktop.DBus.Properties";
+const BUS: &str = "org.freedesktop.DBus";
+
+/// Reports when media players start and stop playing, to run along the
+/// recorder of a backend.
+#[derive(Default)]
+pub struct MprisRecorder {
+    stopped: Arc<AtomicBool>,
+    /// the bus connection and the worker following it
+    worker: Option<(Connection, JoinHandle<zbus::Result<()>>)>,
+}
+
+impl Recorder for MprisRecorder {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
+
+    fn start(&mut self, callback: platform::Callback) -> Result<(), platform::Error> {
+        if self.worker.is_some() {
+            return Err("the MPRIS recorder is already started".into());
+        }
+
+        let conn = Connection::session()?;
+        let (signals, mut players) = subscribe(&conn, &callback)?;
+        info!("Following {} media players", players.0.len());
+
+        self.stopped = Arc::default();
+        let stopped = Arc::clone(&self.stopped);
+        let worker = {
+            let conn = conn.clone();
+            std::thread::spawn(move || {
+                // closing the connection on stop makes reading fail as well
+                run(&conn, signals, &mut players, &callback).or_else(|err| {
+                    match stopped.load(Ordering::Relaxed) {
+                        true => Ok(()),
+                        false => Err(err),
+                    }
+                })
+            })
+        };
+        self.worker = Some((conn, worker));
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<(), platform::Error> {
+        if let Some((conn, _)) = &self.worker {
+            self.stopped.store(true, Ordering::Relaxed);
+            conn.clone().close()?;
+        }
+        self.wait()
+    }
+
+    fn wait(&mut self) -> Result<(), platform::Error> {
+        match self.worker.take() {
+            Some((_, worker)) => match worker.join() {
+                Ok(result) => Ok(result?),
+                Err(panic) => std::panic::resume_unwind(panic),
+            },
+            None => Ok(()),
+        }
+    }
+}
+
+/// The properties of an interface, by their name.
+type Properties = HashMap<String, OwnedValue>;
+
+/// A media player, as last seen.
+#[derive(Debug, Clone, PartialEq)]
+struct Player {
+    /// the name of the player for humans, like "Mozilla Firefox"
+    identity: String,
+    /// the title of the current track
+    title: Option<String>,
+    playing: bool,
+}
+
+impl Player {
+    /// Creates a player from the properties of its player interface.
+    fn new(identity: String, properties: &Properties) -> Self {
+        let mut player = Player {
+            identity,
+            title: None,
+            playing: false,
+        };
+        player.apply(properties);
+        player
+    }
+
+    /// Applies changed properties of the player interface.
+    fn apply(&mut self, properties: &Properties) {
+        let status = properties.get("PlaybackStatus");
+        if let Some(status) = status.and_then(|status| status.downcast_ref::<&str>().ok()) {
+            // the other states are "Paused" and "Stopped"
+            self.playing = status == "Playing";
+        }
+        if let Some(metadata) = properties.get("Metadata") {
+            let metadata = metadata.downcast_ref::<&Dict>().ok();
+            let title =
+                metadata.and_then(|metadata| metadata.get::<&str, &str>(&"xesam:title").ok());
+            self.title = title.flatten().map(str::to_string);
+        }
+    }
+}
+
+/// The players on the bus, by the unique name of their connection.
+#[derive(Debug, Default)]
+struct Players(HashMap<String, Player>);
+
+impl Players {
+    /// Adds a player that appeared on the bus.
+    fn insert(&mut self, owner: String, player: Player, callback: &impl Fn(&event::Event)) {
+        self.remove(&owner, callback);
+        if player.playing {
+            report(&player, event::EventData::PlaybackStart, callback);
+        }
+        self.0.insert(owner, player);
+    }
+
+    /// Removes a player that left the bus, which ends what it was playing.
+    fn remove(&mut self, owner: &str, callback: &impl Fn(&event::Event)) {
+        if let Some(player) = self.0.remove(owner).filter(|player| player.playing) {
+            report(&player, event::EventData::PlaybackEnd, callback);
+        }
+    }
+
+    /// Applies changed properties of a player. Switching tracks while playing
+    /// ends the playback of one track and starts the next.
+    fn change(&mut self, owner: &str, changed: &Properties, callback: &impl Fn(&event::Event)) {
+        let Some(player) = self.0.get_mut(owner) else {
+            return;
+        };
+        let previous = player.clone();
+        player.apply(changed);
+        let switched = previous.title != player.title;
+        if previous.playing && (!player.playing || switched) {
+            report(&previous, event::EventData::PlaybackEnd, callback);
+        }
+        if player.playing && (!previous.playing || switched) {
+            report(player, event::EventData::PlaybackStart, callback);
+        }
+    }
+}
+
+/// The signals about players, as they are received.
+type Signals = mpsc::Receiver<zbus::Result<Message>>;
+
+/// Subscribes to the changes of players, returning the players already on the
+/// bus after reporting those that are playing.
+fn subscribe(
+    conn: &Connection,
+    callback: &impl Fn(&event::Event),
+) -> zbus::Result<(Signals, Players)> {
+    let changes = MatchRule::builder()
+        .msg_type(Type::Signal)
+        .interface(PROPERTIES)?
+        .member("PropertiesChanged")?
+        .path(MPRIS_PATH)?
+        .arg(0, PLAYER)?
+        .build();
+    let owners = MatchRule::builder()
+        .msg_type(Type::Signal)
+        .sender(BUS)?
+        .interface(BUS)?
+        .member("NameOwnerChanged")?
+        .arg0ns(ROOT)?
+        .build();
+    let signals = dbus::follow(conn, vec![changes, owners])?;
+
+    let bus = DBusProxy::new(conn)?;
+    let mut players = Players::default();
+    for name in bus.list_names()? {
+        if !name.starts_with(PLAYER_PREFIX) {
+            continue;
+        }
+        let owner = match bus.get_name_owner(name.as_ref()) {
+            Ok(owner) => owner.to_string(),
+            // the player left since the names were listed
+            Err(zbus::fdo::Error::NameHasNoOwner(_)) => continue,
+            Err(err) => return Err(err.into()),
+        };
+        if let Some(player) = query(conn, &name)? {
+            players.insert(owner, player, callback);
+        }
+    }
+    Ok((signals, players))
+}
+
+/// Asks a player for its identity and state, `None` if it does not answer
+/// like an MPRIS player.
+fn query(conn: &Connection, name: &str) -> zbus::Result<Option<Player>> {
+    let result = conn
+        .call_method(
+            Some(name),
+            MPRIS_PATH,
+            Some(PROPERTIES),
+            "Get",
+            &(ROOT, "Identity"),
+        )
+        .and_then(|identity| {
+            let identity = identity.body().deserialize::<OwnedValue>()?;
+            let properties =
+                conn.call_method(Some(name), MPRIS_PATH, Some(PROPERTIES), "GetAll", &PLAYER)?;
+            Ok((identity, properties.body().deserialize::<Properties>()?))
+        });
+    let (identity, properties) = match result {
+        Ok(result) => result,
+        Err(err @ (zbus::Error::MethodError(..) | zbus::Error::Variant(_))) => {
+            warn!("Skipping media player {name}: {err}");
+            return Ok(None);
+        }
+        Err(err) => return Err(err),
+    };
+    let identity = match identity.downcast_ref::<&str>() {
+        Ok(identity) if !identity.is_empty() => identity.to_string(),
+        // the name of the player is usually its executable
+        _ => name[PLAYER_PREFIX.len()..].to_string(),
+    };
+    Ok(Some(Player::new(identity, &properties)))
+}
+
+/// Reports the changes of players until the connection closes.
+fn run(
+    conn: &Connection,
+    signals: Signals,
+    players: &mut Players,
+    callback: &impl Fn(&event::Event),
+) -> zbus::Result<()> {
+    for signal in signals {
+        handle(conn, players, &signal?, callback)?;
+    }
+    Ok(())
+}
+
+/// Follows a signal about players.
+fn handle(
+    conn: &Connection,
+    players: &mut Players,
+    message: &Message,
+    callback: &impl Fn(&event::Event),
+) -> zbus::Result<()> {
+    let header = message.header();
+    let interface = header.interface().map(|interface| interface.as_str());
+    let member = header.member().map(|member| member.as_str());
+    if interface == Some(BUS) && member == Some("NameOwnerChanged") {
+        let (name, old, new) = message.body().deserialize::<(String, String, String)>()?;
+        if !name.starts_with(PLAYER_PREFIX) {
+            return Ok(());
+        }
+        if !old.is_empty() {
+            players.remove(&old, callback);
+        }
+        if !new.is_empty()
+            && let Some(player) = query(conn, &name)?
+        {
+            players.insert(new, player, callback);
+        }
+    } else if interface == Some(PROPERTIES)
+        && member == Some("PropertiesChanged")
+        && header.path().map(|path| path.as_str()) == Some(MPRIS_PATH)
+        && let Some(sender) = header.sender()
+    {
+        let (interface, changed, _) = message
+            .body()
+            .deserialize::<(String, Properties, Vec<String>)>()?;
+        if interface == PLAYER {
+            players.change(sender, &changed, callback);
+        }
+    }
+    Ok(())
+}
+
+/// Reports an event for a player.
+fn report(player: &Player, data: event::EventData, callback: &impl Fn(&event::Event)) {
+    callback(&event::Event {
+        timestamp: chrono::Utc::now(),
+        instant: Instant::now(),
+        app: player.identity.clone(),
+        title: player.title.clone(),
+        workspace: None,
+        device: None,
+        process: None,
+        output: None,
+        data,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::time::Duration;
+
+    use zbus::zvariant::Value;
+
+    use super::*;
+
+    fn metadata(title: &str) -> HashMap<&'static str, Value<'static>> {
+        HashMap::from([
+            (
+                "mpris:trackid",
+                Value::from("/org/mpris/MediaPlayer2/Track/1"),
+            ),
+            ("xesam:title", Value::from(title.to_string())),
+        ])
+    }
+
+    /// Returns the changed properties of the player interface.
+    fn properties(status: Option<&str>, title: Option<&str>) -> Properties {
+        let mut properties = Properties::new();
+        if let Some(status) = status {
+            let status = Value::from(status.to_string());
+            properties.insert("PlaybackStatus".into(), status.try_into().unwrap());
+        }
+        if let Some(title) = title {
+            let metadata = Value::from(metadata(title));
+            properties.insert("Metadata".into(), metadata.try_into().unwrap());
+        }
+        properties
+    }
+
+    type Reported = Vec<(event::EventData, String, String)>;
+
+    fn recorded(reported: &RefCell<Reported>) -> impl Fn(&event::Event) + '_ {
+        move |event| {
+            let title = event.title.clone().unwrap_or_default();
+            reported
+                .borrow_mut()
+                .push((event.data, event.app.clone(), title));
+        }
+    }
+
+    fn expected(events: &[(event::EventData, &str, &str)]) -> Reported {
+        events
+            .iter()
+            .map(|&(data, app, title)| (data, app.to_string(), title.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn reports_playback_of_players() {
+        let reported = RefCell::new(Vec::new());
+        let callback = recorded(&reported);
+
+        let mut players = Players::default();
+        let paused = properties(Some("Paused"), Some("Trailer"));
+        let player = Player::new("mpv".to_string(), &paused);
+        players.insert(":1.7".to_string(), player, &callback);
+        let playing = Player::new("Spotify".to_string(), &properties(Some("Playing"), None));
+        players.insert(":1.9".to_string(), playing, &callback);
+
+        players.change(":1.7", &properties(Some("Playing"), None), &callback);
+        // the position and volume change while playing
+        players.change(":1.7", &properties(None, None), &callback);
+        players.change(":1.7", &properties(None, Some("Credits")), &callback);
+        players.change(":1.7", &properties(Some("Stopped"), None), &callback);
+        players.change(":1.8", &properties(Some("Playing"), None), &callback);
+        players.remove(":1.9", &callback);
+        players.remove(":1.7", &callback);
+
+        use event::EventData::{PlaybackEnd, PlaybackStart};
+        let events = [
+            (PlaybackStart, "Spotify", ""),
+            (PlaybackStart, "mpv", "Trailer"),
+            (PlaybackEnd, "mpv", "Trailer"),
+            (PlaybackStart, "mpv", "Credits"),
+            (PlaybackEnd, "mpv", "Credits"),
+            (PlaybackEnd, "Spotify", ""),
+        ];
+        assert_eq!(reported.take(), expected(&events));
+    }
+
+    /// The root interface of a mock player.
+    struct Root(&'static str);
+
+    #[zbus::interface(name = "org.mpris.MediaPlayer2")]
+    impl Root {
+        #[zbus(property)]
+        fn identity(&self) -> String {
+            self.0.to_string()
+        }
+    }
+
+    /// The player interface of a mock player, with its status and title.
+    struct Playback(&'static str, &'static str);
+
+    #[zbus::interface(name = "org.mpris.MediaPlayer2.Player")]
+    impl Playback {
+        #[zbus(property)]
+        fn playback_status(&self) -> String {
+            self.0.to_string()
+        }
+
+        #[zbus(property)]
+        fn metadata(&self) -> HashMap<&'static str, Value<'static>> {
+            metadata(self.1)
+        }
+    }
+
+    /// Connects a mock player to the bus, owning the name once it is served.
+    fn player(
+        bus: &dbus::TestBus,
+        name: &'static str,
+        root: Root,
+        playback: Playback,
+    ) -> Connection {
+        let conn = bus.connect();
+        conn.object_server().at(MPRIS_PATH, root).unwrap();
+        conn.object_server().at(MPRIS_PATH, playback).unwrap();
+        conn.request_name(name).unwrap();
+        conn
+    }
+
+    fn changed(conn: &Connection, properties: Properties) {
+        let body = (PLAYER, properties, vec!["Position"]);
+        conn.emit_signal(
+            None::<()>,
+            MPRIS_PATH,
+            PROPERTIES,
+            "PropertiesChanged",
+            &body,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    #[ignore = "needs dbus-daemon"]
+    fn follows_mock_players() {
+        let bus = dbus::TestBus::start("mpris");
+        let movie = Playback("Paused", "Trailer");
+        let movie = player(
+            &bus,
+            "org.mpris.MediaPlayer2.movie",
+            Root("Movie Player"),
+            movie,
+        );
+
+        let (sender, events) = mpsc::channel();
+        let callback = move |event: &event::Event| {
+            let title = event.title.clone().unwrap_or_default();
+            sender.send((event.data, event.app.clone(), title)).unwrap();
+        };
+        let client = bus.connect();
+        let (signals, mut players) = subscribe(&client, &callback).unwrap();
+        assert_eq!(players.0.len(), 1);
+
+        let worker = {
+            let client = client.clone();
+            std::thread::spawn(move || run(&client, signals, &mut players, &callback))
+        };
+        let timeout = Duration::from_secs(5);
+        let next = |count| -> Reported {
+            (0..count)
+                .map(|_| events.recv_timeout(timeout).unwrap())
+                .collect()
+        };
+
+        use event::EventData::{PlaybackEnd, PlaybackStart};
+        changed(&movie, properties(Some("Playing"), None));
+        changed(&movie, properties(None, Some("Credits")));
+        let events = [
+            (PlaybackStart, "Movie Player", "Trailer"),
+            (PlaybackEnd, "Movie Player", "Trailer"),
+            (PlaybackStart, "Movie Player", "Credits"),
+        ];
+        assert_eq!(next(3), expected(&events));
+
+        // a player appearing while playing
+        let radio = Playback("Playing", "News");
+        let radio = player(&bus, "org.mpris.MediaPlayer2.radio", Root("Radio"), radio);
+        assert_eq!(next(1), expected(&[(PlaybackStart, "Radio", "News")]));
+
+        // players going away while playing
+        movie.close().unwrap();
+        assert_eq!(
+            next(1),
+            expected(&[(PlaybackEnd, "Movie Player", "Credits")])
+        );
+        radio.close().unwrap();
+        assert_eq!(next(1), expected(&[(PlaybackEnd, "Radio", "News")]));
+
+        client.close().unwrap();
+        let _ = worker.join().unwrap();
+    }
+}
@@ -0,0 +1,425 @@
+//! Follows applications starting and exiting, even if they never get the focus,
+//! like sync clients in the background and build tools.
+//!
+//! The kernel notifies about processes running a new program and exiting
+//! through the proc connector, which needs `CAP_NET_ADMIN`. Otherwise `/proc`
+//! is sampled every poll interval, which misses processes shorter than that.
+//! Only processes running one of the configured programs are followed, and
+//! those started by another followed process are taken as part of the same
+//! application, like the helper processes of browsers.
+
+use std::collections::{BTreeSet, HashMap};
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+
+use crate::event::{self, Process, Sandbox};
+use crate::platform::{self, Capabilities, Recorder, Settings};
+
+use super::process;
+
+pub mod netlink;
+
+use netlink::{Connector, ProcEvent};
+
+/// How often the worker checks whether it was stopped, in milliseconds.
+const POLL_TIMEOUT: libc::c_int = 250;
+
+/// Reports when processes running the configured programs start and exit, to
+/// run along the recorder of a backend.
+#[derive(Default)]
+pub struct ProcessRecorder {
+    /// the names of the programs, or the ids of the sandboxed apps, to follow
+    include: Vec<String>,
+    /// how often `/proc` is sampled without the proc connector
+    interval: Duration,
+    stopped: Arc<AtomicBool>,
+    worker: Option<JoinHandle<io::Result<()>>>,
+}
+
+impl ProcessRecorder {
+    pub fn new(settings: &Settings) -> Self {
+        ProcessRecorder {
+            include: settings.processes.clone(),
+            interval: settings.poll_interval,
+            ..Default::default()
+        }
+    }
+}
+
+impl Recorder for ProcessRecorder {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
+
+    fn start(&mut self, callback: platform::Callback) -> Result<(), platform::Error> {
+        if self.worker.is_some() {
+            return Err("the process recorder is already started".into());
+        }
+
+        // subscribe first, so no process starts unnoticed while the others are listed
+        let connector = Connector::open();
+        let running = pids(Path::new("/proc"))?;
+        let mut processes = Processes::new(self.include.clone());
+        processes.seed(running.iter().filter_map(|&pid| process::lookup(pid)));
+
+        self.stopped = Arc::default();
+        let stopped = Arc::clone(&self.stopped);
+        let worker = match connector {
+            Ok(connector) => {
+                info!("Following processes through the proc connector");
+                std::thread::spawn(move || listen(&connector, processes, &callback, &stopped))
+            }
+            Err(err) => {
+                let interval = self.interval;
+                info!(
+                    "Sampling processes every {interval:?}, the proc connector is not available: {err}"
+                );
+                std::thread::spawn(move || {
+                    sample(running, interval, processes, &callback, &stopped)
+                })
+            }
+        };
+        self.worker = Some(worker);
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<(), platform::Error> {
+        self.stopped.store(true, Ordering::Relaxed);
+        if let Some(worker) = &self.worker {
+            worker.thread().unpark();
+        }
+        self.wait()
+    }
+
+    fn wait(&mut self) -> Result<(), platform::Error> {
+        match self.worker.take() {
+            Some(worker) => match worker.join() {
+                Ok(result) => Ok(result?),
+                Err(panic) => std::panic::resume_unwind(panic),
+            },
+            None => Ok(()),
+        }
+    }
+}
+
+/// Follows the events of the proc connector until the recorder is stopped.
+fn listen(
+    connector: &Connector,
+    mut processes: Processes,
+    callback: &impl Fn(&event::Event),
+    stopped: &AtomicBool,
+) -> io::Result<()> {
+    let mut buffer = [0; 4096];
+    while !stopped.load(Ordering::Relaxed) {
+        let events = match connector.read(&mut buffer, POLL_TIMEOUT) {
+            Ok(events) => events,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            // the socket buffer overflowed, when many processes start at once
+            Err(err) if err.raw_os_error() == Some(libc::ENOBUFS) => {
+                warn!("Missed events of processes, looking up which are gone: {err}");
+                processes.reconcile(&pids(Path::new("/proc"))?, callback);
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+        for event in events {
+            match event {
+                ProcEvent::Exec { pid } => {
+                    // the process may already be gone
+                    if let Some(process) = process::lookup(pid) {
+                        processes.exec(process, callback);
+                    }
+                }
+                // only the exit of the main thread ends the process
+                ProcEvent::Exit { pid, tgid } if pid == tgid => processes.exit(pid, callback),
+                ProcEvent::Exit { .. } => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Compares the processes in `/proc` every interval until the recorder is stopped.
+fn sample(
+    mut running: BTreeSet<u32>,
+    interval: Duration,
+    mut processes: Processes,
+    callback: &impl Fn(&event::Event),
+    stopped: &AtomicBool,
+) -> io::Result<()> {
+    loop {
+        // stopping unparks the worker
+        let deadline = Instant::now() + interval;
+        while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+            if stopped.load(Ordering::Relaxed) {
+                return Ok(());
+            }
+            std::thread::park_timeout(remaining);
+        }
+
+        let current = pids(Path::new("/proc"))?;
+        for &pid in current.difference(&running) {
+            if let Some(process) = process::lookup(pid) {
+                processes.exec(process, callback);
+            }
+        }
+        for &pid in running.difference(&current) {
+            processes.exit(pid, callback);
+        }
+        running = current;
+    }
+}
+
+/// Returns the ids of the processes in a mount of procfs.
+fn pids(proc: &Path) -> io::Result<BTreeSet<u32>> {
+    let mut pids = BTreeSet::new();
+    for entry in std::fs::read_dir(proc)? {
+        if let Some(pid) = entry?
+            .file_name()
+            .to_str()
+            .and_then(|name| name.parse().ok())
+        {
+            pids.insert(pid);
+        }
+    }
+    Ok(pids)
+}
+
+/// The processes running the programs to follow.
+struct Processes {
+    include: Vec<String>,
+    /// the followed processes by id, and whether they were reported, which
+    /// processes started by another followed process are not
+    followed: HashMap<u32, (Arc<Process>, bool)>,
+}
+
+impl Processes {
+    fn new(include: Vec<String>) -> Self {
+        Processes {
+            include,
+            followed: HashMap::new(),
+        }
+    }
+
+    /// Whether the process runs one of the programs to follow, named by its
+    /// executable or the id of its sandbox.
+    fn includes(&self, process: &Process) -> bool {
+        let name = process::name(process);
+        let sandbox = match &process.sandbox {
+            Some(Sandbox::Flatpak(id) | Sandbox::Snap(id)) => Some(id),
+            None => None,
+        };
+        self.include
+            .iter()
+            .any(|include| name.as_ref() == Some(include) || sandbox == Some(include))
+    }
+
+    /// Follows the processes that were already running, without reporting them.
+    fn seed(&mut self, running: impl IntoIterator<Item = Process>) {
+        for process in running {
+            if self.includes(&process) {
+                self.followed.insert(process.pid, (Arc::new(process), true));
+            }
+        }
+        // the processes started by other followed processes are part of those
+        let children: Vec<_> = self
+            .followed
+            .iter()
+            .filter(|(_, (process, _))| self.has_followed_parent(process))
+            .map(|(&pid, _)| pid)
+            .collect();
+        for pid in children {
+            if let Some((_, reported)) = self.followed.get_mut(&pid) {
+                *reported = false;
+            }
+        }
+    }
+
+    fn has_followed_parent(&self, process: &Process) -> bool {
+        process
+            .parent
+            .is_some_and(|parent| self.followed.contains_key(&parent))
+    }
+
+    /// Follows a process that started running a program.
+    fn exec(&mut self, process: Process, callback: &impl Fn(&event::Event)) {
+        if !self.includes(&process) {
+            // a followed process running another program ends
+            self.exit(process.pid, callback);
+            return;
+        }
+        // wrappers run the actual program in their own process
+        if let Some((followed, _)) = self.followed.get_mut(&process.pid) {
+            *followed = Arc::new(process);
+            return;
+        }
+        let reported = !self.has_followed_parent(&process);
+        let process = Arc::new(process);
+        if reported {
+            report(&process, event::EventData::ProcessStarted, callback);
+        }
+        self.followed.insert(process.pid, (process, reported));
+    }
+
+    /// Stops following a process that exited.
+    fn exit(&mut self, pid: u32, callback: &impl Fn(&event::Event)) {
+        if let Some((process, true)) = self.followed.remove(&pid) {
+            report(&process, event::EventData::ProcessExited, callback);
+        }
+    }
+
+    /// Stops following the processes that are no longer running, whose exits
+    /// were missed. Otherwise their ids would be followed forever, and taken
+    /// for wrappers once they are reused.
+    fn reconcile(&mut self, running: &BTreeSet<u32>, callback: &impl Fn(&event::Event)) {
+        let mut gone: Vec<_> = self
+            .followed
+            .keys()
+            .filter(|pid| !running.contains(pid))
+            .copied()
+            .collect();
+        gone.sort_unstable();
+        for pid in gone {
+            self.exit(pid, callback);
+        }
+    }
+}
+
+/// Reports an event for a process, attributed to its program.
+fn report(process: &Arc<Process>, data: event::EventData, callback: &impl Fn(&event::Event)) {
+    callback(&event::Event {
+        timestamp: chrono::Utc::now(),
+        instant: Instant::now(),
+        app: process::name(process).unwrap_or_default(),
+        title: None,
+        workspace: None,
+        device: None,
+        process: Some(Arc::clone(process)),
+        output: None,
+        data,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn process(pid: u32, parent: u32, exe: &str) -> Process {
+        Process {
+            pid,
+            parent: Some(parent),
+            exe: Some(PathBuf::from(exe)),
+            cmdline: vec![exe.to_string()],
+            sandbox: None,
+        }
+    }
+
+    #[test]
+    fn reports_the_included_programs() {
+        let reported = RefCell::new(Vec::new());
+        let callback = |event: &event::Event| {
+            let pid = event.process.as_ref().unwrap().pid;
+            reported
+                .borrow_mut()
+                .push((event.data, event.app.clone(), pid));
+        };
+
+        let include = ["syncthing", "cargo", "com.spotify.Client"].map(String::from);
+        let mut processes = Processes::new(include.to_vec());
+        processes.seed([
+            process(10, 1, "/usr/bin/syncthing"),
+            // a child of syncthing, listed first
+            process(8, 10, "/usr/bin/syncthing"),
+            process(11, 1, "/usr/bin/bash"),
+        ]);
+
+        processes.exec(process(20, 11, "/usr/bin/cargo"), &callback);
+        processes.exec(process(21, 20, "/usr/bin/cargo"), &callback);
+        processes.exec(process(22, 20, "/usr/bin/rustc"), &callback);
+        let mut spotify = process(30, 1, "/app/extra/spotify");
+        spotify.sandbox = Some(Sandbox::Flatpak("com.spotify.Client".into()));
+        processes.exec(spotify, &callback);
+        for pid in [8, 22, 21, 20, 10, 11] {
+            processes.exit(pid, &callback);
+        }
+        // a process running another program
+        processes.exec(process(30, 1, "/usr/bin/true"), &callback);
+
+        use event::EventData::{ProcessExited, ProcessStarted};
+        let expected = [
+            (ProcessStarted, "cargo", 20),
+            (ProcessStarted, "spotify", 30),
+            (ProcessExited, "cargo", 20),
+            (ProcessExited, "syncthing", 10),
+            (ProcessExited, "spotify", 30),
+        ];
+        let expected: Vec<_> = expected
+            .iter()
+            .map(|&(data, app, pid)| (data, app.to_string(), pid))
+            .collect();
+        assert_eq!(reported.into_inner(), expected);
+    }
+
+    #[test]
+    fn follows_wrappers_running_the_program() {
+        let reported = RefCell::new(Vec::new());
+        let callback = |event: &event::Event| {
+            let exe = event.process.as_ref().unwrap().exe.clone().unwrap();
+            reported.borrow_mut().push((event.data, exe));
+        };
+
+        let mut processes = Processes::new(vec!["firefox".to_string()]);
+        processes.exec(process(40, 1, "/usr/bin/firefox"), &callback);
+        processes.exec(process(40, 1, "/usr/lib/firefox/firefox"), &callback);
+        processes.exit(40, &callback);
+
+        use event::EventData::{ProcessExited, ProcessStarted};
+        let expected = [
+            (ProcessStarted, PathBuf::from("/usr/bin/firefox")),
+            (ProcessExited, PathBuf::from("/usr/lib/firefox/firefox")),
+        ];
+        assert_eq!(reported.into_inner(), expected);
+    }
+
+    #[test]
+    fn reports_the_exits_missed_in_an_overflow() {
+        let reported = RefCell::new(Vec::new());
+        let callback = |event: &event::Event| {
+            let pid = event.process.as_ref().unwrap().pid;
+            reported.borrow_mut().push((event.data, pid));
+        };
+
+        let mut processes = Processes::new(vec!["cargo".to_string()]);
+        processes.exec(process(20, 1, "/usr/bin/cargo"), &callback);
+        processes.exec(process(21, 20, "/usr/bin/cargo"), &callback);
+        processes.exec(process(30, 1, "/usr/bin/cargo"), &callback);
+        processes.reconcile(&BTreeSet::from([1, 30]), &callback);
+        // a reused id runs a new program, rather than wrapping the one that exited
+        processes.exec(process(20, 1, "/usr/bin/cargo"), &callback);
+
+        use event::EventData::{ProcessExited, ProcessStarted};
+        let expected = [
+            (ProcessStarted, 20),
+            (ProcessStarted, 30),
+            (ProcessExited, 20),
+            (ProcessStarted, 20),
+        ];
+        assert_eq!(reported.into_inner(), expected);
+        assert_eq!(processes.followed.len(), 2);
+    }
+
+    #[test]
+    fn lists_processes() {
+        let pids = pids(Path::new("/proc")).unwrap();
+        assert!(pids.contains(&std::process::id()));
+    }
+}
@@ -0,0 +1,258 @@
+//! The proc connector, through which the kernel notifies about processes over
+//! netlink.
+//!
+//! Listening needs `CAP_NET_ADMIN`, the kernel refuses to join unprivileged
+//! sockets to the multicast group of the connector. The messages are in the
+//! byte order of the host.
+
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+/// The size of the header of netlink messages.
+const NLMSG_HEADER_SIZE: usize = 16;
+/// The size of the header of connector messages, following the netlink header.
+const CN_MSG_SIZE: usize = 20;
+/// The size of the header of process events, following the connector header.
+const PROC_EVENT_HEADER_SIZE: usize = 16;
+
+/// The type of netlink messages that are complete in a single message.
+const NLMSG_DONE: u16 = 3;
+/// The id, and the multicast group, of the proc connector.
+const CN_IDX_PROC: u32 = 1;
+const CN_VAL_PROC: u32 = 1;
+const PROC_CN_MCAST_LISTEN: u32 = 1;
+
+const PROC_EVENT_EXEC: u32 = 0x2;
+const PROC_EVENT_EXIT: u32 = 0x8000_0000;
+
+/// An event of the proc connector that we follow.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ProcEvent {
+    /// a process ran a new program
+    Exec { pid: u32 },
+    /// a thread exited, the process exited if it was the thread group leader
+    Exit { pid: u32, tgid: u32 },
+}
+
+/// A netlink socket listening to the proc connector.
+pub struct Connector {
+    socket: OwnedFd,
+}
+
+impl Connector {
+    /// Opens a socket and subscribes it to the events of processes.
+    pub fn open() -> io::Result<Self> {
+        // SAFETY: socket has no preconditions, the result is checked
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_DGRAM | libc::SOCK_CLOEXEC,
+                libc::NETLINK_CONNECTOR,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: the descriptor was just opened and is owned by nothing else
+        let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        // SAFETY: sockaddr_nl is plain data, all zeroes is a valid value
+        let mut addr: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        addr.nl_groups = CN_IDX_PROC;
+        // SAFETY: the pointer and length describe the address
+        let bound = unsafe {
+            libc::bind(
+                socket.as_raw_fd(),
+                (&raw const addr).cast(),
+                size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if bound < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let connector = Connector { socket };
+        connector.send(&listen_message())?;
+        Ok(connector)
+    }
+
+    fn send(&self, message: &[u8]) -> io::Result<()> {
+        // SAFETY: the pointer and length describe the message
+        let sent = unsafe {
+            libc::send(
+                self.socket.as_raw_fd(),
+                message.as_ptr().cast(),
+                message.len(),
+                0,
+            )
+        };
+        if sent < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Waits up to `timeout` milliseconds for events, returning the ones that arrived.
+    pub fn read(&self, buffer: &mut [u8], timeout: libc::c_int) -> io::Result<Vec<ProcEvent>> {
+        let mut fd = libc::pollfd {
+            fd: self.socket.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // SAFETY: the pointer describes a single pollfd struct
+        let ready = unsafe { libc::poll(&mut fd, 1, timeout) };
+        if ready <= 0 {
+            return match ready {
+                0 => Ok(Vec::new()),
+                _ => Err(io::Error::last_os_error()),
+            };
+        }
+        // SAFETY: the pointer and length describe the buffer
+        let len = unsafe {
+            libc::recv(
+                self.socket.as_raw_fd(),
+                buffer.as_mut_ptr().cast(),
+                buffer.len(),
+                0,
+            )
+        };
+        if len < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(decode(&buffer[..len as usize]))
+    }
+}
+
+/// Returns the message subscribing to the events of processes.
+fn listen_message() -> Vec<u8> {
+    let len = NLMSG_HEADER_SIZE + CN_MSG_SIZE + 4;
+    let mut message = Vec::with_capacity(len);
+    // the netlink header: length, type, flags, sequence and port
+    message.extend_from_slice(&(len as u32).to_ne_bytes());
+    message.extend_from_slice(&NLMSG_DONE.to_ne_bytes());
+    message.extend_from_slice(&[0; 10]);
+    // the connector header: id, sequence, acknowledgement, length and flags
+    message.extend_from_slice(&CN_IDX_PROC.to_ne_bytes());
+    message.extend_from_slice(&CN_VAL_PROC.to_ne_bytes());
+    message.extend_from_slice(&[0; 8]);
+    message.extend_from_slice(&4u16.to_ne_bytes());
+    message.extend_from_slice(&[0; 2]);
+    message.extend_from_slice(&PROC_CN_MCAST_LISTEN.to_ne_bytes());
+    message
+}
+
+/// Decodes the events we follow in a datagram of netlink messages. A message
+/// with an invalid length ends the datagram, other events are skipped.
+pub fn decode(mut data: &[u8]) -> Vec<ProcEvent> {
+    let u32_at = |data: &[u8], offset: usize| {
+        let bytes = data.get(offset..offset + 4)?;
+        Some(u32::from_ne_bytes(
+            bytes.try_into().expect("4 bytes were taken"),
+        ))
+    };
+
+    let mut events = Vec::new();
+    while let Some(len) = u32_at(data, 0) {
+        let len = len as usize;
+        if len < NLMSG_HEADER_SIZE || len > data.len() {
+            break;
+        }
+        let (message, rest) = data.split_at(len);
+        // messages are aligned to 4 bytes
+        data = rest
+            .get(len.next_multiple_of(4) - len..)
+            .unwrap_or_default();
+
+        let connector = &message[NLMSG_HEADER_SIZE..];
+        if (u32_at(connector, 0), u32_at(connector, 4)) != (Some(CN_IDX_PROC), Some(CN_VAL_PROC)) {
+            continue;
+        }
+        let event = connector.get(CN_MSG_SIZE..).unwrap_or_default();
+        let data_at = |offset| u32_at(event, PROC_EVENT_HEADER_SIZE + offset);
+        let decoded = match u32_at(event, 0) {
+            Some(PROC_EVENT_EXEC) => data_at(4).map(|pid| ProcEvent::Exec { pid }),
+            Some(PROC_EVENT_EXIT) => data_at(0)
+                .zip(data_at(4))
+                .map(|(pid, tgid)| ProcEvent::Exit { pid, tgid }),
+            _ => None,
+        };
+        events.extend(decoded);
+    }
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+
+    /// Returns a message of the proc connector with an event.
+    fn message(what: u32, data: &[u32]) -> Vec<u8> {
+        let mut event = Vec::new();
+        event.extend_from_slice(&what.to_ne_bytes());
+        event.extend_from_slice(&[0; 12]);
+        for value in data {
+            event.extend_from_slice(&value.to_ne_bytes());
+        }
+        let mut message = listen_message();
+        message.truncate(NLMSG_HEADER_SIZE + CN_MSG_SIZE);
+        message[NLMSG_HEADER_SIZE + 16..][..2].copy_from_slice(&(event.len() as u16).to_ne_bytes());
+        message.extend_from_slice(&event);
+        let len = message.len() as u32;
+        message[..4].copy_from_slice(&len.to_ne_bytes());
+        message
+    }
+
+    #[test]
+    fn decodes_exec_and_exit_events() {
+        let fork = message(0x1, &[100, 100, 4242, 4242]);
+        let exec = message(PROC_EVENT_EXEC, &[4242, 4242]);
+        let exit = message(PROC_EVENT_EXIT, &[4243, 4242, 0, 17, 100, 100]);
+        let data = [fork, exec, exit].concat();
+        assert_eq!(
+            decode(&data),
+            [
+                ProcEvent::Exec { pid: 4242 },
+                ProcEvent::Exit {
+                    pid: 4243,
+                    tgid: 4242
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_malformed_messages() {
+        let exec = message(PROC_EVENT_EXEC, &[4242, 4242]);
+        let short = message(PROC_EVENT_EXEC, &[4241]);
+        assert_eq!(
+            decode(&[&short[..], &exec].concat()),
+            [ProcEvent::Exec { pid: 4242 }]
+        );
+        // the length of a message cut short, or too short for a message
+        assert_eq!(decode(&exec[..exec.len() - 1]), []);
+        let mut broken = exec.clone();
+        broken[..4].copy_from_slice(&2u32.to_ne_bytes());
+        assert_eq!(decode(&[&broken[..], &exec].concat()), []);
+    }
+
+    #[test]
+    #[ignore = "needs CAP_NET_ADMIN"]
+    fn reports_processes_running_programs() {
+        let connector = Connector::open().unwrap();
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        let pid = child.id();
+        child.wait().unwrap();
+
+        let mut buffer = [0; 4096];
+        let mut events = Vec::new();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !events.contains(&ProcEvent::Exit { pid, tgid: pid }) {
+            assert!(Instant::now() < deadline, "events of {pid}: {events:?}");
+            events.extend(connector.read(&mut buffer, 100).unwrap());
+        }
+        assert!(events.contains(&ProcEvent::Exec { pid }));
+    }
+}
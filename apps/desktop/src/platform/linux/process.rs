@@ -0,0 +1,229 @@
+//! Identifies the process behind a window through `/proc`.
+//!
+//! Window systems only tell the process id, so the details are read while the
+//! process runs. The executable and environment of processes of other users
+//! cannot be read, which leaves them unknown.
+
+use std::path::Path;
+use std::sync::OnceLock;
+
+use crate::event::{Process, Sandbox};
+
+/// Looks up a running process, or returns `None` if it is gone.
+pub fn lookup(pid: u32) -> Option<Process> {
+    lookup_in(Path::new("/proc"), pid)
+}
+
+/// Returns the name of the executable of a process.
+pub fn name(process: &Process) -> Option<String> {
+    let exe = process
+        .exe
+        .as_deref()
+        .or_else(|| process.cmdline.first().map(Path::new))?;
+    Some(exe.file_name()?.to_string_lossy().into_owned())
+}
+
+/// Whether a client on the named machine runs on this host, so its process id
+/// refers to a local process. Either name may be qualified with the domain.
+pub fn is_local(machine: &str) -> bool {
+    static HOSTNAME: OnceLock<Option<String>> = OnceLock::new();
+    let hostname = HOSTNAME.get_or_init(|| {
+        let name = std::fs::read_to_string("/proc/sys/kernel/hostname").ok()?;
+        Some(name.trim_end().to_string())
+    });
+    hostname
+        .as_deref()
+        .is_some_and(|hostname| same_host(machine, hostname))
+}
+
+fn same_host(machine: &str, hostname: &str) -> bool {
+    let short = |name: &str| {
+        name.split('.')
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase()
+    };
+    machine.eq_ignore_ascii_case(hostname) || short(machine) == short(hostname)
+}
+
+/// Looks up a process in a mount of procfs.
+fn lookup_in(proc: &Path, pid: u32) -> Option<Process> {
+    let dir = proc.join(pid.to_string());
+    // the command line is readable for every process, but empty for kernel threads
+    let cmdline = std::fs::read(dir.join("cmdline")).ok()?;
+    let cmdline = cmdline
+        .split(|&byte| byte == 0)
+        .filter(|arg| !arg.is_empty())
+        .map(|arg| String::from_utf8_lossy(arg).into_owned())
+        .collect();
+
+    let stat = std::fs::read_to_string(dir.join("stat")).unwrap_or_default();
+    let environ = std::fs::read(dir.join("environ")).unwrap_or_default();
+    let cgroup = std::fs::read_to_string(dir.join("cgroup")).unwrap_or_default();
+    Some(Process {
+        pid,
+        parent: parent_from_stat(&stat),
+        exe: std::fs::read_link(dir.join("exe")).ok(),
+        cmdline,
+        sandbox: sandbox_from_environ(&environ).or_else(|| sandbox_from_cgroup(&cgroup)),
+    })
+}
+
+/// Finds the parent process in the status line of `stat`, which starts like
+/// `4242 (slack) S 1234`. The name in parentheses can contain anything, even
+/// parentheses and spaces.
+fn parent_from_stat(stat: &str) -> Option<u32> {
+    let (_, fields) = stat.rsplit_once(')')?;
+    let mut fields = fields.split_whitespace();
+    let _state = fields.next()?;
+    // the processes the kernel starts itself have the parent 0
+    fields.next()?.parse().ok().filter(|&parent| parent != 0)
+}
+
+/// Finds the sandbox in the variables flatpak and snap set for their apps.
+fn sandbox_from_environ(environ: &[u8]) -> Option<Sandbox> {
+    let var = |name: &str| {
+        environ.split(|&byte| byte == 0).find_map(|entry| {
+            let value = entry.strip_prefix(name.as_bytes())?.strip_prefix(b"=")?;
+            Some(String::from_utf8_lossy(value).into_owned()).filter(|value| !value.is_empty())
+        })
+    };
+    if let Some(id) = var("FLATPAK_ID") {
+        return Some(Sandbox::Flatpak(id));
+    }
+    // the instance name tells parallel installs apart, like "firefox_beta"
+    var("SNAP_INSTANCE_NAME")
+        .or_else(|| var("SNAP_NAME"))
+        .map(Sandbox::Snap)
+}
+
+/// Finds the sandbox in the scope systemd runs the app in, which is named like
+/// `app-flatpak-org.mozilla.firefox-2345.scope` or `snap.firefox.firefox-<uuid>.scope`.
+fn sandbox_from_cgroup(cgroup: &str) -> Option<Sandbox> {
+    cgroup.lines().find_map(|line| {
+        let (_, path) = line.rsplit_once(':')?;
+        let scope = path.rsplit('/').next()?.strip_suffix(".scope")?;
+        if let Some(scope) = scope.strip_prefix("app-flatpak-") {
+            let (id, _) = scope.rsplit_once('-')?;
+            Some(Sandbox::Flatpak(id.to_string()))
+        } else if let Some(scope) = scope.strip_prefix("snap.") {
+            let (name, _) = scope.split_once('.')?;
+            Some(Sandbox::Snap(name.to_string()))
+        } else {
+            None
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::*;
+
+    /// A directory laid out like procfs, removed when dropped.
+    struct FakeProc(PathBuf);
+
+    impl FakeProc {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("dot-proc-{name}-{}", std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            FakeProc(dir)
+        }
+
+        fn process(&self, pid: u32, files: &[(&str, &[u8])], exe: Option<&str>) {
+            let dir = self.0.join(pid.to_string());
+            fs::create_dir(&dir).unwrap();
+            for (name, content) in files {
+                fs::write(dir.join(name), content).unwrap();
+            }
+            if let Some(exe) = exe {
+                std::os::unix::fs::symlink(exe, dir.join("exe")).unwrap();
+            }
+        }
+    }
+
+    impl Drop for FakeProc {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn reads_the_executable_and_command_line() {
+        let proc = FakeProc::new("exe");
+        let cmdline: &[u8] = b"/usr/lib/slack/slack\0--enable-crashpad\0";
+        let cgroup: &[u8] = b"0::/user.slice/user-1000.slice/session-2.scope\n";
+        let stat: &[u8] = b"4242 (slack (main)) S 1234 4242 4242 0 -1 4194560 1500\n";
+        proc.process(
+            4242,
+            &[("cmdline", cmdline), ("cgroup", cgroup), ("stat", stat)],
+            Some("/usr/lib/slack/slack"),
+        );
+
+        let process = lookup_in(&proc.0, 4242).unwrap();
+        assert_eq!(process.pid, 4242);
+        assert_eq!(process.parent, Some(1234));
+        assert_eq!(process.exe, Some(PathBuf::from("/usr/lib/slack/slack")));
+        assert_eq!(
+            process.cmdline,
+            ["/usr/lib/slack/slack", "--enable-crashpad"]
+        );
+        assert_eq!(process.sandbox, None);
+        assert_eq!(lookup_in(&proc.0, 4243), None);
+    }
+
+    #[test]
+    fn identifies_sandboxed_apps() {
+        let proc = FakeProc::new("sandbox");
+        let environ: &[u8] = b"HOME=/home/user\0FLATPAK_ID=com.spotify.Client\0";
+        proc.process(10, &[("cmdline", b"spotify\0"), ("environ", environ)], None);
+        // the environment of processes of other users cannot be read
+        let cgroup: &[u8] = b"0::/user.slice/user-1000.slice/user@1000.service/app.slice/snap.firefox.firefox-6c2d0a3e-3a4b.scope\n";
+        proc.process(11, &[("cmdline", b"firefox\0"), ("cgroup", cgroup)], None);
+
+        let sandbox = |pid| lookup_in(&proc.0, pid).unwrap().sandbox;
+        assert_eq!(
+            sandbox(10),
+            Some(Sandbox::Flatpak("com.spotify.Client".into()))
+        );
+        assert_eq!(sandbox(11), Some(Sandbox::Snap("firefox".into())));
+
+        let cgroup = "0::/user.slice/user-1000.slice/user@1000.service/app.slice/app-flatpak-org.gimp.GIMP-2345.scope";
+        assert_eq!(
+            sandbox_from_cgroup(cgroup),
+            Some(Sandbox::Flatpak("org.gimp.GIMP".into()))
+        );
+        let environ = b"SNAP_NAME=code\0SNAP_INSTANCE_NAME=code_insiders\0";
+        assert_eq!(
+            sandbox_from_environ(environ),
+            Some(Sandbox::Snap("code_insiders".into()))
+        );
+    }
+
+    #[test]
+    fn names_processes_by_their_executable() {
+        let mut process = Process {
+            pid: 1234,
+            parent: None,
+            exe: Some(PathBuf::from("/opt/idea/jbr/bin/java")),
+            cmdline: vec!["java".to_string(), "-jar".to_string()],
+            sandbox: None,
+        };
+        assert_eq!(name(&process).as_deref(), Some("java"));
+        process.exe = None;
+        process.cmdline = vec!["/usr/games/nethack".to_string()];
+        assert_eq!(name(&process).as_deref(), Some("nethack"));
+        process.cmdline.clear();
+        assert_eq!(name(&process), None);
+    }
+
+    #[test]
+    fn compares_host_names_without_domains() {
+        assert!(same_host("laptop", "laptop"));
+        assert!(same_host("Laptop.example.org", "laptop"));
+        assert!(!same_host("build-server", "laptop"));
+    }
+}
@@ -0,0 +1,836 @@
+//! The Wayland backend.
+//!
+//! Wayland does not let clients observe the input of others, so the backend only
+//! follows which toplevel is activated, through `zwlr_foreign_toplevel_manager_v1`
+//! of wlroots based compositors, and when the user is idle, through
+//! `ext_idle_notifier_v1`.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::net::Shutdown;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use chrono::TimeDelta;
+use log::{info, warn};
+
+use wayland_client::backend::{ObjectId, WaylandError};
+use wayland_client::globals::{GlobalError, GlobalListContents, registry_queue_init};
+use wayland_client::protocol::{wl_registry, wl_seat};
+use wayland_client::{
+    ConnectError, Connection, Dispatch, DispatchError, EventQueue, Proxy, QueueHandle,
+    delegate_noop, event_created_child,
+};
+use wayland_protocols::ext::idle_notify::v1::client::ext_idle_notification_v1::{
+    self, ExtIdleNotificationV1,
+};
+use wayland_protocols::ext::idle_notify::v1::client::ext_idle_notifier_v1::ExtIdleNotifierV1;
+use wayland_protocols_wlr::foreign_toplevel::v1::client::zwlr_foreign_toplevel_handle_v1::{
+    self, ZwlrForeignToplevelHandleV1,
+};
+use wayland_protocols_wlr::foreign_toplevel::v1::client::zwlr_foreign_toplevel_manager_v1::{
+    self, ZwlrForeignToplevelManagerV1,
+};
+
+use crate::event;
+use crate::platform::{self, Capabilities, Recorder};
+
+/// Errors that stop the Wayland recorder.
+#[derive(Debug)]
+pub enum Error {
+    /// the compositor socket could not be located
+    NoDisplay(&'static str),
+    /// reading from or writing to the compositor failed
+    Io(io::Error),
+    /// the compositor sent a message that could not be decoded
+    Protocol {
+        interface: &'static str,
+        opcode: u16,
+    },
+    /// the compositor does not support a required protocol
+    MissingGlobal(&'static str),
+    /// the compositor reported a fatal error on one of our objects
+    Compositor {
+        object: u32,
+        interface: String,
+        code: u32,
+        message: String,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NoDisplay(reason) => write!(f, "cannot find the Wayland compositor: {reason}"),
+            Error::Io(err) => write!(f, "Wayland connection failed: {err}"),
+            Error::Protocol { interface, opcode } => {
+                write!(
+                    f,
+                    "malformed Wayland message: event {opcode} of {interface}"
+                )
+            }
+            Error::MissingGlobal(interface) => {
+                write!(f, "the Wayland compositor does not support {interface}")
+            }
+            Error::Compositor {
+                object,
+                interface,
+                code,
+                message,
+            } => write!(
+                f,
+                "the Wayland compositor reported error {code} on {interface}@{object}: {message}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<ConnectError> for Error {
+    fn from(err: ConnectError) -> Self {
+        Error::Io(io::Error::other(err))
+    }
+}
+
+impl From<WaylandError> for Error {
+    fn from(err: WaylandError) -> Self {
+        match err {
+            WaylandError::Io(err) => Error::Io(err),
+            WaylandError::Protocol(err) => Error::Compositor {
+                object: err.object_id,
+                interface: err.object_interface,
+                code: err.code,
+                message: err.message,
+            },
+        }
+    }
+}
+
+impl From<DispatchError> for Error {
+    fn from(err: DispatchError) -> Self {
+        match err {
+            DispatchError::BadMessage {
+                interface, opcode, ..
+            } => Error::Protocol { interface, opcode },
+            DispatchError::Backend(err) => err.into(),
+        }
+    }
+}
+
+impl From<GlobalError> for Error {
+    fn from(err: GlobalError) -> Self {
+        match err {
+            GlobalError::Backend(err) => err.into(),
+            // only happens once the connection is gone
+            GlobalError::InvalidId(err) => Error::Io(io::Error::other(err)),
+        }
+    }
+}
+
+/// The Wayland backend, following the activated toplevel and idle state.
+pub struct WaylandRecorder {
+    /// how long the user has to be inactive to be reported as idle
+    idle_timeout: Duration,
+    stopped: Arc<AtomicBool>,
+    /// the compositor socket and the worker reading from it
+    worker: Option<(UnixStream, JoinHandle<Result<(), Error>>)>,
+}
+
+impl WaylandRecorder {
+    pub fn new(settings: &platform::Settings) -> Self {
+        WaylandRecorder {
+            idle_timeout: settings.idle_threshold,
+            stopped: Arc::default(),
+            worker: None,
+        }
+    }
+}
+
+impl Default for WaylandRecorder {
+    fn default() -> Self {
+        WaylandRecorder::new(&platform::Settings::default())
+    }
+}
+
+impl Recorder for WaylandRecorder {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            titles: true,
+            input: false,
+            idle: true,
+        }
+    }
+
+    fn start(&mut self, callback: platform::Callback) -> Result<(), platform::Error> {
+        if self.worker.is_some() {
+            return Err("the Wayland recorder is already started".into());
+        }
+
+        let path = socket_path(|name| std::env::var(name).ok().filter(|value| !value.is_empty()))?;
+        let socket = UnixStream::connect(&path)?;
+        let shutdown = socket.try_clone()?;
+        let (mut queue, mut client) = Client::connect(socket, self.idle_timeout, callback)?;
+        info!("Connected to the Wayland compositor at {}", path.display());
+
+        self.stopped = Arc::default();
+        let stopped = Arc::clone(&self.stopped);
+        let worker = std::thread::spawn(move || {
+            // shutting down the socket on stop makes reading fail as well
+            client
+                .run(&mut queue)
+                .or_else(|err| match stopped.load(Ordering::Relaxed) {
+                    true => Ok(()),
+                    false => Err(err),
+                })
+        });
+        self.worker = Some((shutdown, worker));
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<(), platform::Error> {
+        if let Some((socket, _)) = &self.worker {
+            self.stopped.store(true, Ordering::Relaxed);
+            socket.shutdown(Shutdown::Both)?;
+        }
+        self.wait()
+    }
+
+    fn wait(&mut self) -> Result<(), platform::Error> {
+        match self.worker.take() {
+            Some((_, worker)) => match worker.join() {
+                Ok(result) => Ok(result?),
+                Err(panic) => std::panic::resume_unwind(panic),
+            },
+            None => Ok(()),
+        }
+    }
+}
+
+/// Returns the path of the compositor socket, as found through `var`.
+fn socket_path(var: impl Fn(&str) -> Option<String>) -> Result<PathBuf, Error> {
+    let display = PathBuf::from(var("WAYLAND_DISPLAY").unwrap_or_else(|| "wayland-0".into()));
+    if display.is_absolute() {
+        return Ok(display);
+    }
+    let runtime_dir =
+        var("XDG_RUNTIME_DIR").ok_or(Error::NoDisplay("XDG_RUNTIME_DIR is not set"))?;
+    Ok(PathBuf::from(runtime_dir).join(display))
+}
+
+const TOPLEVEL_MANAGER: &str = "zwlr_foreign_toplevel_manager_v1";
+const IDLE_NOTIFIER: &str = "ext_idle_notifier_v1";
+
+/// A toplevel of another client. Its properties are double-buffered, changes
+/// only apply with the next `done` event.
+#[derive(Default)]
+struct Toplevel {
+    current: ToplevelInfo,
+    pending: ToplevelInfo,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+struct ToplevelInfo {
+    app_id: Option<String>,
+    title: Option<String>,
+    activated: bool,
+}
+
+/// The state of the connection, tracking the toplevels.
+struct Client {
+    callback: platform::Callback,
+    toplevels: HashMap<ObjectId, Toplevel>,
+    /// the toplevel last reported as focused
+    activated: Option<ObjectId>,
+    /// whether the compositor stopped sending toplevel events
+    finished: bool,
+}
+
+impl Client {
+    /// Binds the globals we need over the socket, failing if the compositor
+    /// cannot report toplevels. Idle reporting is optional.
+    fn connect(
+        socket: UnixStream,
+        idle_timeout: Duration,
+        callback: platform::Callback,
+    ) -> Result<(EventQueue<Client>, Client), Error> {
+        let conn = Connection::from_socket(socket)?;
+        let (globals, mut queue) = registry_queue_init::<Client>(&conn)?;
+        let qh = queue.handle();
+
+        globals
+            .bind::<ZwlrForeignToplevelManagerV1, _, _>(&qh, 1..=3, ())
+            .map_err(|_| Error::MissingGlobal(TOPLEVEL_MANAGER))?;
+
+        let notifier = globals.bind::<ExtIdleNotifierV1, _, _>(&qh, 1..=1, ());
+        let seat = globals.bind::<wl_seat::WlSeat, _, _>(&qh, 1..=1, ());
+        match (notifier, seat) {
+            (Ok(notifier), Ok(seat)) => {
+                let timeout = u32::try_from(idle_timeout.as_millis()).unwrap_or(u32::MAX);
+                notifier.get_idle_notification(timeout, &seat, &qh, idle_timeout);
+            }
+            _ => warn!(
+                "The Wayland compositor does not support {IDLE_NOTIFIER}, idle periods are not reported"
+            ),
+        }
+
+        // receive the toplevels that already exist
+        let mut client = Client {
+            callback,
+            toplevels: HashMap::new(),
+            activated: None,
+            finished: false,
+        };
+        queue.roundtrip(&mut client)?;
+        Ok((queue, client))
+    }
+
+    /// Reports events until the compositor stops sending toplevel events.
+    fn run(&mut self, queue: &mut EventQueue<Client>) -> Result<(), Error> {
+        while !self.finished {
+            queue.blocking_dispatch(self)?;
+        }
+        Ok(())
+    }
+
+    /// Reports the focus changes caused by an update of a toplevel.
+    fn apply(&mut self, id: ObjectId, previous: &ToplevelInfo, current: &ToplevelInfo) {
+        let callback = &self.callback;
+        match (previous.activated, current.activated) {
+            (false, true) => {
+                // the previously activated toplevel may only be updated afterwards
+                if let Some(old) = self.activated.replace(id.clone()).filter(|old| *old != id)
+                    && let Some(old) = self.toplevels.get(&old)
+                {
+                    report(callback, &old.current, event::EventData::FocusOut);
+                }
+                report(callback, current, event::EventData::FocusIn);
+            }
+            (true, false) if self.activated.as_ref() == Some(&id) => {
+                self.activated = None;
+                report(callback, current, event::EventData::FocusOut);
+            }
+            (true, true) if previous.title != current.title && self.activated == Some(id) => {
+                report(callback, current, event::EventData::TitleChange);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<wl_registry::WlRegistry, GlobalListContents> for Client {
+    fn event(
+        _: &mut Self,
+        _: &wl_registry::WlRegistry,
+        _: wl_registry::Event,
+        _: &GlobalListContents,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        // globals announced later are of no use, everything is bound up front
+    }
+}
+
+delegate_noop!(Client: ignore wl_seat::WlSeat);
+delegate_noop!(Client: ExtIdleNotifierV1);
+
+impl Dispatch<ZwlrForeignToplevelManagerV1, ()> for Client {
+    fn event(
+        client: &mut Self,
+        _: &ZwlrForeignToplevelManagerV1,
+        event: zwlr_foreign_toplevel_manager_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        match event {
+            zwlr_foreign_toplevel_manager_v1::Event::Toplevel { toplevel } => {
+                client.toplevels.insert(toplevel.id(), Toplevel::default());
+            }
+            zwlr_foreign_toplevel_manager_v1::Event::Finished => client.finished = true,
+            _ => {}
+        }
+    }
+
+    event_created_child!(Client, ZwlrForeignToplevelManagerV1, [
+        zwlr_foreign_toplevel_manager_v1::EVT_TOPLEVEL_OPCODE => (ZwlrForeignToplevelHandleV1, ()),
+    ]);
+}
+
+impl Dispatch<ZwlrForeignToplevelHandleV1, ()> for Client {
+    fn event(
+        client: &mut Self,
+        handle: &ZwlrForeignToplevelHandleV1,
+        event: zwlr_foreign_toplevel_handle_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        use zwlr_foreign_toplevel_handle_v1::Event;
+
+        let id = handle.id();
+        let Some(toplevel) = client.toplevels.get_mut(&id) else {
+            return;
+        };
+        match event {
+            Event::Title { title } => toplevel.pending.title = Some(title),
+            Event::AppId { app_id } => toplevel.pending.app_id = Some(app_id),
+            Event::State { state } => {
+                let activated = zwlr_foreign_toplevel_handle_v1::State::Activated as u32;
+                toplevel.pending.activated = state
+                    .chunks_exact(4)
+                    .map(|state| u32::from_ne_bytes([state[0], state[1], state[2], state[3]]))
+                    .any(|state| state == activated);
+            }
+            Event::Done => {
+                let previous = std::mem::replace(&mut toplevel.current, toplevel.pending.clone());
+                let current = toplevel.current.clone();
+                client.apply(id, &previous, &current);
+            }
+            Event::Closed => {
+                if let Some(toplevel) = client.toplevels.remove(&id)
+                    && client.activated == Some(id)
+                {
+                    client.activated = None;
+                    let data = event::EventData::FocusOut;
+                    report(&client.callback, &toplevel.current, data);
+                }
+                handle.destroy();
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The notification carries the idle timeout it was requested with.
+impl Dispatch<ExtIdleNotificationV1, Duration> for Client {
+    fn event(
+        client: &mut Self,
+        _: &ExtIdleNotificationV1,
+        event: ext_idle_notification_v1::Event,
+        idle_timeout: &Duration,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        match event {
+            // the user became idle with the last input, a timeout ago
+            ext_idle_notification_v1::Event::Idled => {
+                report_idle(&client.callback, event::EventData::IdleStart, *idle_timeout);
+            }
+            ext_idle_notification_v1::Event::Resumed => {
+                report_idle(&client.callback, event::EventData::IdleEnd, Duration::ZERO);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Reports an event for a toplevel, if it has an app id.
+fn report(callback: &impl Fn(&event::Event), toplevel: &ToplevelInfo, data: event::EventData) {
+    if let Some(app) = &toplevel.app_id {
+        callback(&event::Event {
+            timestamp: chrono::Utc::now(),
+            instant: Instant::now(),
+            app: app.clone(),
+            title: toplevel.title.clone(),
+            workspace: None,
+            device: None,
+            process: None,
+            output: None,
+            data,
+        });
+    }
+}
+
+/// Reports a change of the idle state `ago`, which is not tied to an application.
+fn report_idle(callback: &impl Fn(&event::Event), data: event::EventData, ago: Duration) {
+    let now = Instant::now();
+    callback(&event::Event {
+        timestamp: chrono::Utc::now() - TimeDelta::from_std(ago).unwrap_or_default(),
+        instant: now.checked_sub(ago).unwrap_or(now),
+        app: String::new(),
+        title: None,
+        workspace: None,
+        device: None,
+        process: None,
+        output: None,
+        data,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+    use std::thread;
+
+    use wayland_protocols::ext::idle_notify::v1::server::{
+        ext_idle_notification_v1 as server_notification, ext_idle_notifier_v1 as server_notifier,
+    };
+    use wayland_protocols_wlr::foreign_toplevel::v1::server::{
+        zwlr_foreign_toplevel_handle_v1 as server_handle,
+        zwlr_foreign_toplevel_manager_v1 as server_manager,
+    };
+    use wayland_server::protocol::wl_seat as server_seat;
+    use wayland_server::{DataInit, DisplayHandle, GlobalDispatch, New, Resource};
+
+    use super::*;
+
+    /// What the compositor saw of the client.
+    #[derive(Default)]
+    struct Server {
+        manager: Option<server_manager::ZwlrForeignToplevelManagerV1>,
+        /// the idle notification, with its timeout
+        notification: Option<(server_notification::ExtIdleNotificationV1, u32)>,
+        /// how many toplevel handles the client destroyed
+        destroyed: usize,
+    }
+
+    impl GlobalDispatch<server_manager::ZwlrForeignToplevelManagerV1, ()> for Server {
+        fn bind(
+            server: &mut Self,
+            _: &DisplayHandle,
+            _: &wayland_server::Client,
+            resource: New<server_manager::ZwlrForeignToplevelManagerV1>,
+            _: &(),
+            data_init: &mut DataInit<'_, Self>,
+        ) {
+            server.manager = Some(data_init.init(resource, ()));
+        }
+    }
+
+    impl GlobalDispatch<server_seat::WlSeat, ()> for Server {
+        fn bind(
+            _: &mut Self,
+            _: &DisplayHandle,
+            _: &wayland_server::Client,
+            resource: New<server_seat::WlSeat>,
+            _: &(),
+            data_init: &mut DataInit<'_, Self>,
+        ) {
+            data_init.init(resource, ());
+        }
+    }
+
+    impl GlobalDispatch<server_notifier::ExtIdleNotifierV1, ()> for Server {
+        fn bind(
+            _: &mut Self,
+            _: &DisplayHandle,
+            _: &wayland_server::Client,
+            resource: New<server_notifier::ExtIdleNotifierV1>,
+            _: &(),
+            data_init: &mut DataInit<'_, Self>,
+        ) {
+            data_init.init(resource, ());
+        }
+    }
+
+    impl wayland_server::Dispatch<server_manager::ZwlrForeignToplevelManagerV1, ()> for Server {
+        fn request(
+            _: &mut Self,
+            _: &wayland_server::Client,
+            _: &server_manager::ZwlrForeignToplevelManagerV1,
+            _: server_manager::Request,
+            _: &(),
+            _: &DisplayHandle,
+            _: &mut DataInit<'_, Self>,
+        ) {
+        }
+    }
+
+    impl wayland_server::Dispatch<server_handle::ZwlrForeignToplevelHandleV1, ()> for Server {
+        fn request(
+            server: &mut Self,
+            _: &wayland_server::Client,
+            _: &server_handle::ZwlrForeignToplevelHandleV1,
+            request: server_handle::Request,
+            _: &(),
+            _: &DisplayHandle,
+            _: &mut DataInit<'_, Self>,
+        ) {
+            if let server_handle::Request::Destroy = request {
+                server.destroyed += 1;
+            }
+        }
+    }
+
+    impl wayland_server::Dispatch<server_seat::WlSeat, ()> for Server {
+        fn request(
+            _: &mut Self,
+            _: &wayland_server::Client,
+            _: &server_seat::WlSeat,
+            _: server_seat::Request,
+            _: &(),
+            _: &DisplayHandle,
+            _: &mut DataInit<'_, Self>,
+        ) {
+        }
+    }
+
+    impl wayland_server::Dispatch<server_notifier::ExtIdleNotifierV1, ()> for Server {
+        fn request(
+            server: &mut Self,
+            _: &wayland_server::Client,
+            _: &server_notifier::ExtIdleNotifierV1,
+            request: server_notifier::Request,
+            _: &(),
+            _: &DisplayHandle,
+            data_init: &mut DataInit<'_, Self>,
+        ) {
+            if let server_notifier::Request::GetIdleNotification { id, timeout, .. } = request {
+                server.notification = Some((data_init.init(id, ()), timeout));
+            }
+        }
+    }
+
+    impl wayland_server::Dispatch<server_notification::ExtIdleNotificationV1, ()> for Server {
+        fn request(
+            _: &mut Self,
+            _: &wayland_server::Client,
+            _: &server_notification::ExtIdleNotificationV1,
+            _: server_notification::Request,
+            _: &(),
+            _: &DisplayHandle,
+            _: &mut DataInit<'_, Self>,
+        ) {
+        }
+    }
+
+    struct TestClient;
+
+    impl wayland_server::backend::ClientData for TestClient {}
+
+    /// A compositor with a single client, dispatching its requests when waiting.
+    struct Compositor {
+        display: wayland_server::Display<Server>,
+        server: Server,
+        client: wayland_server::Client,
+    }
+
+    impl Compositor {
+        /// Announces the globals with the interfaces to the client at the socket.
+        fn new(socket: UnixStream, globals: &[&str]) -> Self {
+            let display = wayland_server::Display::new().unwrap();
+            let handle = display.handle();
+            for &global in globals {
+                match global {
+                    TOPLEVEL_MANAGER => {
+                        handle.create_global::<Server, server_manager::ZwlrForeignToplevelManagerV1, ()>(3, ());
+                    }
+                    IDLE_NOTIFIER => {
+                        handle
+                            .create_global::<Server, server_notifier::ExtIdleNotifierV1, ()>(1, ());
+                    }
+                    "wl_seat" => {
+                        handle.create_global::<Server, server_seat::WlSeat, ()>(1, ());
+                    }
+                    _ => panic!("unknown global {global}"),
+                }
+            }
+            let client = display
+                .handle()
+                .insert_client(socket, Arc::new(TestClient))
+                .unwrap();
+            Compositor {
+                display,
+                server: Server::default(),
+                client,
+            }
+        }
+
+        /// Handles the requests of the client and sends it the events, until
+        /// `done` holds.
+        fn until(&mut self, done: impl Fn(&Server) -> bool) {
+            let deadline = Instant::now() + Duration::from_secs(5);
+            loop {
+                self.display.dispatch_clients(&mut self.server).unwrap();
+                // the client may already be gone
+                let _ = self.display.flush_clients();
+                if done(&self.server) {
+                    return;
+                }
+                assert!(Instant::now() < deadline, "the client did not respond");
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+
+        fn manager(&mut self) -> server_manager::ZwlrForeignToplevelManagerV1 {
+            self.until(|server| server.manager.is_some());
+            self.server.manager.clone().unwrap()
+        }
+
+        /// Announces a new toplevel.
+        fn toplevel(&mut self) -> server_handle::ZwlrForeignToplevelHandleV1 {
+            let manager = self.manager();
+            let handle = self
+                .client
+                .create_resource::<_, _, Server>(&self.display.handle(), 3, ())
+                .unwrap();
+            manager.toplevel(&handle);
+            handle
+        }
+
+        fn update(
+            &mut self,
+            toplevel: &server_handle::ZwlrForeignToplevelHandleV1,
+            app_id: &str,
+            title: &str,
+            activated: bool,
+        ) {
+            toplevel.app_id(app_id.to_string());
+            toplevel.title(title.to_string());
+            let states: &[u32] = if activated {
+                &[
+                    server_handle::State::Maximized as u32,
+                    server_handle::State::Activated as u32,
+                ]
+            } else {
+                &[server_handle::State::Maximized as u32]
+            };
+            toplevel.state(
+                states
+                    .iter()
+                    .flat_map(|state| state.to_ne_bytes())
+                    .collect(),
+            );
+            toplevel.done();
+        }
+    }
+
+    type Reported = Vec<(event::EventData, String, Option<String>)>;
+
+    /// Runs a client against a compositor with the globals, played by `play`.
+    /// The toplevel manager finishes afterwards, which ends the client.
+    fn run(globals: &[&str], play: impl FnOnce(&mut Compositor)) -> (Result<(), Error>, Reported) {
+        let (client, server) = UnixStream::pair().unwrap();
+        let (sender, events) = mpsc::channel();
+        let client = thread::spawn(move || {
+            let callback: platform::Callback = Box::new(move |event: &event::Event| {
+                let event = (event.data, event.app.clone(), event.title.clone());
+                sender.send(event).unwrap();
+            });
+            let (mut queue, mut client) =
+                Client::connect(client, Duration::from_secs(1), callback)?;
+            client.run(&mut queue)
+        });
+
+        let mut compositor = Compositor::new(server, globals);
+        play(&mut compositor);
+        if let Some(manager) = &compositor.server.manager {
+            manager.finished();
+        }
+        compositor.until(|_| client.is_finished());
+        (client.join().unwrap(), events.try_iter().collect())
+    }
+
+    fn reported(data: event::EventData, app: &str, title: Option<&str>) -> Reported {
+        vec![(data, app.to_string(), title.map(str::to_string))]
+    }
+
+    #[test]
+    fn reports_focus_changes_of_toplevels() {
+        let (result, events) = run(&["wl_seat", TOPLEVEL_MANAGER], |compositor| {
+            let firefox = compositor.toplevel();
+            compositor.update(&firefox, "firefox", "Mozilla Firefox", true);
+
+            // the new toplevel is activated before the old one is deactivated
+            let kitty = compositor.toplevel();
+            compositor.update(&kitty, "kitty", "~", true);
+            compositor.update(&firefox, "firefox", "Mozilla Firefox", false);
+            compositor.update(&kitty, "kitty", "vim", true);
+
+            kitty.closed();
+            compositor.until(|server| server.destroyed == 1);
+        });
+
+        result.unwrap();
+        let expected: Reported = [
+            reported(
+                event::EventData::FocusIn,
+                "firefox",
+                Some("Mozilla Firefox"),
+            ),
+            reported(
+                event::EventData::FocusOut,
+                "firefox",
+                Some("Mozilla Firefox"),
+            ),
+            reported(event::EventData::FocusIn, "kitty", Some("~")),
+            reported(event::EventData::TitleChange, "kitty", Some("vim")),
+            reported(event::EventData::FocusOut, "kitty", Some("vim")),
+        ]
+        .concat();
+        assert_eq!(events, expected);
+    }
+
+    #[test]
+    fn reports_idle_periods() {
+        let globals = ["wl_seat", TOPLEVEL_MANAGER, IDLE_NOTIFIER];
+        let (result, events) = run(&globals, |compositor| {
+            compositor.until(|server| server.notification.is_some());
+            let (notification, timeout) = compositor.server.notification.clone().unwrap();
+            assert_eq!(timeout, 1000);
+            notification.idled();
+            notification.resumed();
+        });
+
+        result.unwrap();
+        let expected = [
+            reported(event::EventData::IdleStart, "", None),
+            reported(event::EventData::IdleEnd, "", None),
+        ]
+        .concat();
+        assert_eq!(events, expected);
+    }
+
+    #[test]
+    fn requires_the_toplevel_manager() {
+        let (result, _) = run(&["wl_seat", IDLE_NOTIFIER], |_| {});
+        assert!(matches!(
+            result,
+            Err(Error::MissingGlobal(TOPLEVEL_MANAGER))
+        ));
+    }
+
+    #[test]
+    fn fails_on_compositor_errors() {
+        let (result, _) = run(&[TOPLEVEL_MANAGER], |compositor| {
+            let manager = compositor.manager();
+            manager.post_error(1u32, "invalid method");
+        });
+        assert!(matches!(result, Err(Error::Compositor { code: 1, .. })));
+    }
+
+    #[test]
+    fn locates_the_compositor_socket() {
+        let var = |vars: &'static [(&str, &str)]| {
+            move |name: &str| {
+                vars.iter()
+                    .find(|(var, _)| *var == name)
+                    .map(|(_, value)| value.to_string())
+            }
+        };
+        let path = socket_path(var(&[("XDG_RUNTIME_DIR", "/run/user/1000")]));
+        assert_eq!(path.unwrap(), PathBuf::from("/run/user/1000/wayland-0"));
+        let path = socket_path(var(&[("WAYLAND_DISPLAY", "/tmp/wayland-1")]));
+        assert_eq!(path.unwrap(), PathBuf::from("/tmp/wayland-1"));
+        assert!(socket_path(var(&[("WAYLAND_DISPLAY", "wayland-1")])).is_err());
+    }
+}
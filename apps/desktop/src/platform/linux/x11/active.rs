@@ -0,0 +1,92 @@
+//! Looks up the active window on request, for recorders that observe input
+//! through other means than the RECORD extension.
+
+use x11rb::connection::Connection;
+use x11rb::protocol::Event;
+use x11rb::protocol::xproto::{self, ChangeWindowAttributesAux, ConnectionExt as _, EventMask};
+use x11rb::rust_connection::RustConnection;
+
+use crate::platform::{self, UNKNOWN_APP, WindowProvider};
+
+use super::cache::{WindowCache, WindowInfo};
+use super::{Atoms, Error, Fallbacks, Windows};
+
+/// The active window of the default screen, kept up to date through property
+/// change notifications.
+pub struct ActiveWindow {
+    conn: RustConnection,
+    atoms: Atoms,
+    root: xproto::Window,
+    cache: WindowCache,
+}
+
+impl ActiveWindow {
+    /// Connects to the X server named by `DISPLAY`.
+    pub fn connect() -> Result<Self, Error> {
+        let (conn, screen) = x11rb::connect(None)?;
+        let root = conn.setup().roots[screen].root;
+        let atoms = Atoms::new(&conn)?.reply()?;
+
+        // get notified when the active window changes
+        conn.change_window_attributes(
+            root,
+            &ChangeWindowAttributesAux::new().event_mask(EventMask::PROPERTY_CHANGE),
+        )?
+        .check()?;
+
+        Ok(ActiveWindow {
+            conn,
+            atoms,
+            root,
+            cache: WindowCache::new(),
+        })
+    }
+
+    /// Returns the class and title of the active window, and the workspace.
+    fn lookup(&mut self) -> Result<Option<(WindowInfo, Option<String>)>, Error> {
+        let mut windows = Windows {
+            conn: &self.conn,
+            atoms: self.atoms,
+            root: self.root,
+            cache: &mut self.cache,
+            fallbacks: Fallbacks::default(),
+        };
+
+        // Drop what changed since the last lookup
+        while let Some(event) = self.conn.poll_for_event()? {
+            match event {
+                Event::PropertyNotify(event) if event.atom == windows.atoms._NET_ACTIVE_WINDOW => {
+                    windows.cache.invalidate_active(event.window);
+                }
+                Event::PropertyNotify(event) if windows.is_workspace(event.atom) => {
+                    windows.cache.invalidate_workspace(event.window);
+                }
+                Event::PropertyNotify(event) if windows.is_metadata(event.atom) => {
+                    windows.cache.invalidate(event.window);
+                }
+                Event::DestroyNotify(event) => windows.cache.remove(event.window),
+                _ => {}
+            }
+        }
+
+        let info = match windows.active(self.root)? {
+            Some(window) => windows.info(window)?,
+            None => None,
+        };
+        match info {
+            Some(info) => Ok(Some((info, windows.workspace()?))),
+            None => Ok(None),
+        }
+    }
+}
+
+impl WindowProvider for ActiveWindow {
+    fn active(&mut self) -> Result<Option<platform::Window>, platform::Error> {
+        Ok(self.lookup()?.map(|(info, workspace)| platform::Window {
+            app: info.app.unwrap_or_else(|| UNKNOWN_APP.to_string()),
+            title: info.title,
+            workspace,
+            process: info.process,
+        }))
+    }
+}
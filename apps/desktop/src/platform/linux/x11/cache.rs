@@ -0,0 +1,224 @@
+//! Caching of window metadata, so that reporting an event does not need a
+//! round trip to the X server.
+//!
+//! Entries are only valid for as long as the properties they were read from
+//! do not change, so the recorder invalidates them on `PropertyNotify` and
+//! drops them on `DestroyNotify`.
+
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::sync::Arc;
+
+use x11rb::protocol::xproto::Window;
+
+use crate::event;
+
+/// Windows that are destroyed before we get to watch them never send a
+/// `DestroyNotify`, the cache is cleared when it grows beyond this size.
+const CAPACITY: usize = 4096;
+
+/// What is known about a window.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WindowInfo {
+    /// the app the window belongs to, usually the `WM_CLASS` class of the window
+    pub app: Option<String>,
+    /// where the app was found
+    pub source: AppSource,
+    /// the title of the window
+    pub title: Option<String>,
+    /// the process owning the window, if it runs on this host
+    pub process: Option<Arc<event::Process>>,
+    /// whether the `_NET_WM_STATE` of the window has `_NET_WM_STATE_FULLSCREEN`
+    pub fullscreen: bool,
+}
+
+/// Where the app of a window was found, from the most to the least reliable.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum AppSource {
+    /// the `WM_CLASS` class of the window
+    Class,
+    /// the `WM_CLASS` class of a parent window, for sub-windows
+    Parent,
+    /// the name of the executable of the process owning the window
+    Process,
+    /// the title of the window
+    Title,
+    /// nothing identifies the app
+    #[default]
+    Unknown,
+}
+
+#[derive(Debug, Default)]
+pub struct WindowCache {
+    windows: HashMap<Window, WindowInfo>,
+    /// the `_NET_ACTIVE_WINDOW` of each root window
+    active: HashMap<Window, Option<Window>>,
+    /// the name of the current desktop of each root window
+    workspaces: HashMap<Window, Option<String>>,
+}
+
+impl WindowCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of cached windows.
+    pub fn len(&self) -> usize {
+        self.windows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.windows.is_empty()
+    }
+
+    /// Returns the cached information about the window, calling `fetch` on a miss.
+    /// Failed fetches are not cached.
+    pub fn get_or_fetch<E>(
+        &mut self,
+        window: Window,
+        fetch: impl FnOnce() -> Result<WindowInfo, E>,
+    ) -> Result<&WindowInfo, E> {
+        if self.windows.len() >= CAPACITY && !self.windows.contains_key(&window) {
+            self.windows.clear();
+        }
+        match self.windows.entry(window) {
+            Entry::Occupied(entry) => Ok(entry.into_mut()),
+            Entry::Vacant(entry) => Ok(entry.insert(fetch()?)),
+        }
+    }
+
+    /// Forgets the information about a window after one of its properties changed,
+    /// returning what was cached.
+    pub fn invalidate(&mut self, window: Window) -> Option<WindowInfo> {
+        self.windows.remove(&window)
+    }
+
+    /// Forgets everything about a destroyed window.
+    pub fn remove(&mut self, window: Window) {
+        self.windows.remove(&window);
+        self.active.remove(&window);
+    }
+
+    /// Returns the cached active window of the root window, calling `fetch` on a miss.
+    pub fn active_or_fetch<E>(
+        &mut self,
+        root: Window,
+        fetch: impl FnOnce() -> Result<Option<Window>, E>,
+    ) -> Result<Option<Window>, E> {
+        match self.active.entry(root) {
+            Entry::Occupied(entry) => Ok(*entry.get()),
+            Entry::Vacant(entry) => Ok(*entry.insert(fetch()?)),
+        }
+    }
+
+    /// Forgets the active window of the root window after `_NET_ACTIVE_WINDOW` changed.
+    pub fn invalidate_active(&mut self, root: Window) {
+        self.active.remove(&root);
+    }
+
+    /// Returns the cached workspace of the root window, calling `fetch` on a miss.
+    pub fn workspace_or_fetch<E>(
+        &mut self,
+        root: Window,
+        fetch: impl FnOnce() -> Result<Option<String>, E>,
+    ) -> Result<Option<String>, E> {
+        match self.workspaces.entry(root) {
+            Entry::Occupied(entry) => Ok(entry.get().clone()),
+            Entry::Vacant(entry) => Ok(entry.insert(fetch()?).clone()),
+        }
+    }
+
+    /// Forgets the workspace of the root window after `_NET_CURRENT_DESKTOP` or
+    /// `_NET_DESKTOP_NAMES` changed, returning what was cached.
+    pub fn invalidate_workspace(&mut self, root: Window) -> Option<Option<String>> {
+        self.workspaces.remove(&root)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(class: &str, title: &str) -> WindowInfo {
+        WindowInfo {
+            app: Some(class.to_string()),
+            source: AppSource::Class,
+            title: Some(title.to_string()),
+            process: None,
+            fullscreen: false,
+        }
+    }
+
+    #[test]
+    fn fetches_only_on_a_miss() {
+        let mut cache = WindowCache::new();
+        let mut fetches = 0;
+        for _ in 0..3 {
+            let cached = cache.get_or_fetch(1, || {
+                fetches += 1;
+                Ok::<_, ()>(info("firefox", "GitHub"))
+            });
+            assert_eq!(cached, Ok(&info("firefox", "GitHub")));
+        }
+        assert_eq!(fetches, 1);
+    }
+
+    #[test]
+    fn does_not_cache_failures() {
+        let mut cache = WindowCache::new();
+        assert_eq!(cache.get_or_fetch(1, || Err(())), Err(()));
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn refetches_after_invalidation() {
+        let mut cache = WindowCache::new();
+        cache
+            .get_or_fetch(1, || Ok::<_, ()>(info("firefox", "GitHub")))
+            .unwrap();
+        assert_eq!(cache.invalidate(1), Some(info("firefox", "GitHub")));
+        let cached = cache.get_or_fetch(1, || Ok::<_, ()>(info("firefox", "YouTube")));
+        assert_eq!(cached, Ok(&info("firefox", "YouTube")));
+    }
+
+    #[test]
+    fn caches_the_active_window_per_root() {
+        let mut cache = WindowCache::new();
+        assert_eq!(
+            cache.active_or_fetch(1, || Ok::<_, ()>(Some(7))),
+            Ok(Some(7))
+        );
+        assert_eq!(
+            cache.active_or_fetch(1, || Ok::<_, ()>(Some(8))),
+            Ok(Some(7))
+        );
+        cache.invalidate_active(1);
+        assert_eq!(
+            cache.active_or_fetch(1, || Ok::<_, ()>(Some(8))),
+            Ok(Some(8))
+        );
+    }
+
+    #[test]
+    fn caches_the_workspace_per_root() {
+        let mut cache = WindowCache::new();
+        let code = || Ok::<_, ()>(Some("code".to_string()));
+        let comms = || Ok::<_, ()>(Some("comms".to_string()));
+        assert_eq!(cache.workspace_or_fetch(1, code), code());
+        assert_eq!(cache.workspace_or_fetch(1, comms), code());
+        assert_eq!(cache.invalidate_workspace(1), Some(code().unwrap()));
+        assert_eq!(cache.invalidate_workspace(1), None);
+        assert_eq!(cache.workspace_or_fetch(1, comms), comms());
+    }
+
+    #[test]
+    fn stays_bounded() {
+        let mut cache = WindowCache::new();
+        for window in 0..(CAPACITY as Window * 2) {
+            cache
+                .get_or_fetch(window, || Ok::<_, ()>(WindowInfo::default()))
+                .unwrap();
+        }
+        assert!(cache.len() <= CAPACITY);
+    }
+}
@@ -0,0 +1,208 @@
+//! Conversion of X server timestamps into wall-clock and monotonic time.
+//!
+//! X timestamps count milliseconds since an arbitrary server epoch and wrap
+//! around every 2^32 ms (about 49.7 days). The first timestamp seen is anchored
+//! to the monotonic clock, and all later ones are placed relative to it, so the
+//! time between events is exactly what the server measured, regardless of when
+//! they were processed. Wall-clock times are derived from the monotonic clock,
+//! so small adjustments of the system clock (e.g. by NTP) do not skew durations.
+//! The monotonic clock stops while the system sleeps though, so the wall clock
+//! is re-anchored whenever the two disagree by more than [`RESYNC_TOLERANCE`],
+//! like after a resume or a correction of the system clock.
+
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use x11rb::protocol::xproto::Timestamp;
+
+/// Timestamps further apart than this are considered to have wrapped around
+const WRAP_THRESHOLD: u32 = u32::MAX / 2;
+
+/// How far the system clock may drift from the derived wall clock before the
+/// wall clock is re-anchored to it
+const RESYNC_TOLERANCE: TimeDelta = TimeDelta::seconds(1);
+
+#[derive(Debug, Clone)]
+pub struct Clock {
+    /// the wall-clock time at a monotonic instant, every wall-clock time is derived from it
+    origin: (Instant, DateTime<Utc>),
+    /// a server timestamp and the instant it was observed at
+    anchor: Option<(Timestamp, Instant)>,
+    /// the latest server timestamp seen, to detect wrap-arounds
+    latest: Option<Timestamp>,
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock {
+    pub fn new() -> Self {
+        Self::with_origin(Instant::now(), Utc::now())
+    }
+
+    fn with_origin(instant: Instant, wall: DateTime<Utc>) -> Self {
+        Self {
+            origin: (instant, wall),
+            anchor: None,
+            latest: None,
+        }
+    }
+
+    /// Returns the current time, for events that carry no server timestamp.
+    pub fn now(&mut self) -> (DateTime<Utc>, Instant) {
+        let now = self.observe();
+        (self.wall(now), now)
+    }
+
+    /// Returns the time `elapsed` before now, for what is noticed only later.
+    pub fn ago(&mut self, elapsed: Duration) -> (DateTime<Utc>, Instant) {
+        let now = self.observe();
+        let instant = now.checked_sub(elapsed).unwrap_or(now);
+        (self.wall(instant), instant)
+    }
+
+    /// Returns the time of an event with the given server timestamp.
+    pub fn at(&mut self, time: Timestamp) -> (DateTime<Utc>, Instant) {
+        let now = self.observe();
+        let instant = self.instant(time, now);
+        (self.wall(instant), instant)
+    }
+
+    /// Returns the current instant, after re-anchoring the wall clock if needed.
+    fn observe(&mut self) -> Instant {
+        let now = Instant::now();
+        self.resync(now, Utc::now());
+        now
+    }
+
+    /// Re-anchors the wall clock at `instant` if it is more than the tolerance
+    /// away from the system clock, which reads `system` at that instant.
+    fn resync(&mut self, instant: Instant, system: DateTime<Utc>) {
+        if (system - self.wall(instant)).abs() > RESYNC_TOLERANCE {
+            self.origin = (instant, system);
+        }
+    }
+
+    /// Places a server timestamp on the monotonic clock, anchoring the server
+    /// clock at `observed` if it is not anchored yet or has wrapped around.
+    fn instant(&mut self, time: Timestamp, observed: Instant) -> Instant {
+        let wrapped = self
+            .latest
+            .is_some_and(|latest| time < latest && latest - time > WRAP_THRESHOLD);
+        let (anchor_time, anchor_instant) = match self.anchor {
+            Some(anchor) if !wrapped => anchor,
+            _ => *self.anchor.insert((time, observed)),
+        };
+        // slightly delayed events must not move the latest timestamp backwards
+        if self
+            .latest
+            .is_none_or(|latest| time.wrapping_sub(latest) < WRAP_THRESHOLD)
+        {
+            self.latest = Some(time);
+        }
+
+        let offset = i64::from(time) - i64::from(anchor_time);
+        let delta = Duration::from_millis(offset.unsigned_abs());
+        if offset >= 0 {
+            anchor_instant + delta
+        } else {
+            anchor_instant.checked_sub(delta).unwrap_or(anchor_instant)
+        }
+    }
+
+    fn wall(&self, instant: Instant) -> DateTime<Utc> {
+        let (origin, wall) = self.origin;
+        match instant.checked_duration_since(origin) {
+            Some(elapsed) => wall + TimeDelta::from_std(elapsed).unwrap_or(TimeDelta::MAX),
+            None => wall - TimeDelta::from_std(origin - instant).unwrap_or(TimeDelta::MAX),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clock() -> (Clock, Instant, DateTime<Utc>) {
+        let origin = Instant::now();
+        let wall = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        (Clock::with_origin(origin, wall), origin, wall)
+    }
+
+    #[test]
+    fn uses_server_time_between_events() {
+        let (mut clock, origin, _) = clock();
+        let observed = origin + Duration::from_secs(1);
+        let first = clock.instant(10_000, observed);
+        // processed much later, but happened 250ms after the first event
+        let second = clock.instant(10_250, observed + Duration::from_secs(5));
+        assert_eq!(first, observed);
+        assert_eq!(second - first, Duration::from_millis(250));
+    }
+
+    #[test]
+    fn places_delayed_events_before_the_anchor() {
+        let (mut clock, origin, _) = clock();
+        let observed = origin + Duration::from_secs(1);
+        let anchor = clock.instant(10_000, observed);
+        let delayed = clock.instant(9_900, observed);
+        assert_eq!(anchor - delayed, Duration::from_millis(100));
+        // a later event is still measured from the original anchor
+        assert_eq!(
+            clock.instant(10_100, observed) - anchor,
+            Duration::from_millis(100)
+        );
+    }
+
+    #[test]
+    fn resyncs_on_wrap_around() {
+        let (mut clock, origin, _) = clock();
+        let observed = origin + Duration::from_secs(1);
+        clock.instant(u32::MAX - 100, observed);
+        let resynced = observed + Duration::from_millis(150);
+        let wrapped = clock.instant(50, resynced);
+        assert_eq!(wrapped, resynced);
+        assert_eq!(
+            clock.instant(80, resynced) - wrapped,
+            Duration::from_millis(30)
+        );
+    }
+
+    #[test]
+    fn derives_wall_clock_from_the_monotonic_clock() {
+        let (mut clock, origin, wall) = clock();
+        let instant = clock.instant(5_000, origin + Duration::from_millis(1500));
+        assert_eq!(clock.wall(instant), wall + TimeDelta::milliseconds(1500));
+        let later = clock.instant(5_500, origin);
+        assert_eq!(clock.wall(later), wall + TimeDelta::milliseconds(2000));
+    }
+
+    #[test]
+    fn resyncs_the_wall_clock_after_sleeping() {
+        let (mut clock, origin, wall) = clock();
+        let awake = origin + Duration::from_secs(10);
+        // jitter of the system clock is ignored
+        clock.resync(awake, wall + TimeDelta::milliseconds(10_200));
+        assert_eq!(clock.wall(awake), wall + TimeDelta::seconds(10));
+
+        // the monotonic clock stood still for the hour the system slept
+        let resumed = wall + TimeDelta::hours(1) + TimeDelta::seconds(10);
+        clock.resync(awake, resumed);
+        assert_eq!(clock.wall(awake), resumed);
+        let later = awake + Duration::from_secs(5);
+        assert_eq!(clock.wall(later), resumed + TimeDelta::seconds(5));
+    }
+
+    #[test]
+    fn follows_the_system_clock_from_a_skewed_origin() {
+        let skewed = Utc::now() - TimeDelta::hours(2);
+        let mut clock = Clock::with_origin(Instant::now(), skewed);
+        let (now, _) = clock.now();
+        assert!((Utc::now() - now).abs() <= RESYNC_TOLERANCE);
+        let (event, _) = clock.at(1_000);
+        assert!((Utc::now() - event).abs() <= RESYNC_TOLERANCE);
+    }
+}
@@ -0,0 +1,172 @@
+//! Detects idle periods through the MIT-SCREEN-SAVER extension, which tells how
+//! long ago the last input of the user was.
+//!
+//! The X server does not notify about input after an idle period, so the time
+//! since the last input is sampled: every second while idle, and otherwise not
+//! before the threshold could have passed.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::warn;
+
+use x11rb::connection::{Connection, RequestConnection};
+use x11rb::protocol::screensaver::{self, ConnectionExt as _};
+use x11rb::protocol::xproto;
+use x11rb::rust_connection::RustConnection;
+
+use crate::event;
+
+use super::clock::Clock;
+use super::recording::{Control, Recording, State};
+use super::{Error, reconnecting};
+
+/// How often the time since the last input is sampled while idle, which is how
+/// late the end of an idle period can be reported.
+const IDLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Starts reporting idle periods of at least `threshold` on a worker thread,
+/// returning a handle to pause, resume and stop it.
+///
+/// If the X server does not support MIT-SCREEN-SAVER, a warning is logged and
+/// the worker finishes without reporting anything.
+pub fn watch<C>(callback: C, threshold: Duration) -> Recording
+where
+    C: Fn(&event::Event) + Send + Sync + 'static,
+{
+    let control = Arc::new(Control::default());
+    let worker = {
+        let control = Arc::clone(&control);
+        std::thread::spawn(move || match run(&callback, &control, threshold) {
+            Err(Error::MissingExtension(name)) => {
+                warn!(
+                    "The X server does not support the {name} extension, idle periods are not reported"
+                );
+                Ok(())
+            }
+            result => result,
+        })
+    };
+    Recording::new(control, worker)
+}
+
+/// Samples the time since the last input until stopped or an unrecoverable
+/// error occurs, reconnecting after the connection to the X server is lost.
+fn run(
+    callback: &impl Fn(&event::Event),
+    control: &Control,
+    threshold: Duration,
+) -> Result<(), Error> {
+    let mut idle = Idle::new(threshold);
+    let mut clock = Clock::new();
+    let run = |(conn, root)| sample(&conn, root, &mut idle, &mut clock, callback, control);
+    // the recording reports losing the connection
+    reconnecting(control, connect, run, |_| {})
+}
+
+/// Connects to the X server and returns the root window to query.
+fn connect() -> Result<(RustConnection, xproto::Window), Error> {
+    let (conn, screen) = x11rb::connect(None)?;
+    let root = conn.setup().roots[screen].root;
+    if conn
+        .extension_information(screensaver::X11_EXTENSION_NAME)?
+        .is_none()
+    {
+        return Err(Error::MissingExtension(screensaver::X11_EXTENSION_NAME));
+    }
+    Ok((conn, root))
+}
+
+/// Samples the time since the last input until stopped or the connection fails.
+fn sample(
+    conn: &RustConnection,
+    root: xproto::Window,
+    idle: &mut Idle,
+    clock: &mut Clock,
+    callback: &impl Fn(&event::Event),
+    control: &Control,
+) -> Result<(), Error> {
+    loop {
+        if control.wait_while_paused() == State::Stopped {
+            return Ok(());
+        }
+        let info = conn.screensaver_query_info(root)?.reply()?;
+        let since_input = Duration::from_millis(info.ms_since_user_input.into());
+        let (change, next) = idle.update(since_input);
+        if let Some(data) = change {
+            // idle periods start, and end, with the last input
+            let (timestamp, instant) = clock.ago(since_input);
+            callback(&event::Event {
+                timestamp,
+                instant,
+                app: String::new(),
+                title: None,
+                workspace: None,
+                device: None,
+                process: None,
+                output: None,
+                data,
+            });
+        }
+        if control.sleep(next) {
+            return Ok(());
+        }
+    }
+}
+
+/// Stops a watcher after the recording it runs along finished. A failure is
+/// logged rather than returned, so it does not hide the result of the recording.
+pub fn finish(watcher: Recording) {
+    if let Err(err) = watcher.stop().and_then(|()| watcher.join()) {
+        warn!("Watching for idle periods failed: {err}");
+    }
+}
+
+/// Turns samples of the time since the last input into idle periods.
+struct Idle {
+    threshold: Duration,
+    idle: bool,
+}
+
+impl Idle {
+    fn new(threshold: Duration) -> Self {
+        Idle {
+            threshold,
+            idle: false,
+        }
+    }
+
+    /// Returns the change of the idle state, if any, and when to sample next.
+    fn update(&mut self, since_input: Duration) -> (Option<event::EventData>, Duration) {
+        let change = match (self.idle, since_input >= self.threshold) {
+            (false, true) => Some(event::EventData::IdleStart),
+            (true, false) => Some(event::EventData::IdleEnd),
+            _ => None,
+        };
+        self.idle = since_input >= self.threshold;
+        let next = match self.idle {
+            true => IDLE_INTERVAL,
+            false => (self.threshold - since_input).max(IDLE_INTERVAL),
+        };
+        (change, next)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_idle_periods() {
+        let secs = Duration::from_secs;
+        let mut idle = Idle::new(secs(300));
+        assert_eq!(idle.update(secs(10)), (None, secs(290)));
+        assert_eq!(idle.update(secs(299)), (None, secs(1)));
+        let started = idle.update(secs(300));
+        assert_eq!(started, (Some(event::EventData::IdleStart), secs(1)));
+        assert_eq!(idle.update(secs(1800)), (None, secs(1)));
+        let ended = idle.update(Duration::from_millis(400));
+        let next = Duration::from_millis(299_600);
+        assert_eq!(ended, (Some(event::EventData::IdleEnd), next));
+    }
+}
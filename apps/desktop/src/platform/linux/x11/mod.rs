@@ -0,0 +1,1516 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::{debug, info, warn};
+
+use x11rb::connection::Connection;
+use x11rb::connection::RequestConnection;
+use x11rb::errors::{ConnectError, ConnectionError, ParseError, ReplyError, ReplyOrIdError};
+use x11rb::properties::WmClass;
+use x11rb::protocol::record::{self, ConnectionExt as _};
+use x11rb::protocol::screensaver;
+use x11rb::protocol::xproto::{
+    self, AtomEnum, ChangeWindowAttributesAux, ConnectionExt as _, EventMask,
+};
+use x11rb::rust_connection::RustConnection;
+
+use crate::event;
+use crate::platform::{self, Capabilities, Recorder, UNKNOWN_APP, linux};
+
+pub mod active;
+pub mod cache;
+pub mod clock;
+pub mod idle;
+pub mod poll;
+pub mod randr;
+pub mod recording;
+pub mod stream;
+pub mod toplevels;
+pub mod xinput;
+
+use cache::{AppSource, WindowCache, WindowInfo};
+use clock::Clock;
+use recording::Control;
+pub use recording::{Recording, State};
+use stream::{Element, Stream};
+use toplevels::{Change, Toplevels};
+
+x11rb::atom_manager! {
+    Atoms:
+    AtomsCookie {
+        _NET_ACTIVE_WINDOW,
+        _NET_CURRENT_DESKTOP,
+        _NET_DESKTOP_NAMES,
+        _NET_WM_NAME,
+        _NET_WM_PID,
+        _NET_WM_STATE,
+        _NET_WM_STATE_FULLSCREEN,
+        UTF8_STRING,
+        COMPOUND_TEXT,
+    }
+}
+
+/// Errors that stop the X11 recorder.
+#[derive(Debug)]
+pub enum Error {
+    /// no connection to the X server could be established
+    Connect(ConnectError),
+    /// the connection to the X server broke, or the server rejected a request
+    Connection(ReplyOrIdError),
+    /// the X server does not support a required extension
+    MissingExtension(&'static str),
+    /// the recorded protocol data could not be parsed
+    Parse(ParseError),
+    /// a window property could not be looked up.
+    /// Windows can disappear at any time, so this only ever skips a single event.
+    Property {
+        window: xproto::Window,
+        property: &'static str,
+        reason: String,
+    },
+}
+
+impl Error {
+    /// Whether the recorder cannot continue after this error.
+    fn is_fatal(&self) -> bool {
+        !matches!(self, Error::Property { .. })
+    }
+
+    /// Whether the connection to the X server was lost (or could not be established),
+    /// as opposed to the server rejecting what we asked for.
+    fn is_disconnect(&self) -> bool {
+        matches!(
+            self,
+            Error::Connect(_) | Error::Connection(ReplyOrIdError::ConnectionError(_))
+        )
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Connect(err) => write!(f, "failed to connect to the X server: {err}"),
+            Error::Connection(err) => write!(f, "X server connection failed: {err}"),
+            Error::MissingExtension(name) => {
+                write!(f, "the X server does not support the {name} extension")
+            }
+            Error::Parse(err) => write!(f, "failed to parse recorded data: {err}"),
+            Error::Property {
+                window,
+                property,
+                reason,
+            } => write!(
+                f,
+                "failed to look up {property} of window {window:#x}: {reason}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Connect(err) => Some(err),
+            Error::Connection(err) => Some(err),
+            Error::Parse(err) => Some(err),
+            Error::MissingExtension(_) | Error::Property { .. } => None,
+        }
+    }
+}
+
+impl From<ConnectError> for Error {
+    fn from(err: ConnectError) -> Self {
+        Error::Connect(err)
+    }
+}
+
+impl From<ConnectionError> for Error {
+    fn from(err: ConnectionError) -> Self {
+        Error::Connection(err.into())
+    }
+}
+
+impl From<ReplyError> for Error {
+    fn from(err: ReplyError) -> Self {
+        Error::Connection(err.into())
+    }
+}
+
+impl From<ReplyOrIdError> for Error {
+    fn from(err: ReplyOrIdError) -> Self {
+        Error::Connection(err)
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(err: ParseError) -> Self {
+        Error::Parse(err)
+    }
+}
+
+/// Downgrades a failed lookup for a single event to a logged skip,
+/// while still passing on errors the recorder cannot continue after.
+fn skip_on_error<T>(result: Result<Option<T>, Error>) -> Result<Option<T>, Error> {
+    match result {
+        Err(err) if !err.is_fatal() => {
+            warn!("Skipping event: {err}");
+            Ok(None)
+        }
+        result => result,
+    }
+}
+
+/// The X11 backend, recording through the RECORD extension.
+pub struct X11Recorder {
+    settings: platform::Settings,
+    recording: Option<Recording>,
+    /// the watcher of idle periods, running along the recording
+    idle: Option<Recording>,
+}
+
+impl X11Recorder {
+    /// Creates a recorder that falls back to sampling the active window every
+    /// poll interval if the X server does not support RECORD.
+    pub fn new(settings: &platform::Settings) -> Self {
+        X11Recorder {
+            settings: settings.clone(),
+            recording: None,
+            idle: None,
+        }
+    }
+}
+
+impl Default for X11Recorder {
+    fn default() -> Self {
+        X11Recorder::new(&platform::Settings::default())
+    }
+}
+
+impl Recorder for X11Recorder {
+    fn capabilities(&self) -> Capabilities {
+        let supported = supported(&[record::X11_EXTENSION_NAME, screensaver::X11_EXTENSION_NAME]);
+        recorder_capabilities(&supported)
+    }
+
+    fn start(&mut self, callback: platform::Callback) -> Result<(), platform::Error> {
+        if self.recording.is_some() {
+            return Err("the X11 recorder is already started".into());
+        }
+        let (callback, idle) = share(callback);
+        self.recording = Some(record(callback, self.settings.poll_interval));
+        self.idle = Some(idle::watch(idle, self.settings.idle_threshold));
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<(), platform::Error> {
+        // the idle watcher is stopped once the recording finished
+        if let Some(recording) = &self.recording {
+            recording.stop()?;
+        }
+        self.wait()
+    }
+
+    fn wait(&mut self) -> Result<(), platform::Error> {
+        let result = self.recording.take().map_or(Ok(()), Recording::join);
+        // the idle watcher is of no use without the recording
+        if let Some(idle) = self.idle.take() {
+            idle::finish(idle);
+        }
+        Ok(result?)
+    }
+}
+
+/// What the X11 recorder observes with the supported extensions.
+/// Without RECORD, it falls back to sampling the active window.
+fn recorder_capabilities(supported: &[&str]) -> Capabilities {
+    Capabilities {
+        titles: true,
+        input: supported.contains(&record::X11_EXTENSION_NAME),
+        idle: supported.contains(&screensaver::X11_EXTENSION_NAME),
+    }
+}
+
+/// Asks the X server which of the extensions it supports, none if it cannot be reached.
+fn supported(extensions: &[&'static str]) -> Vec<&'static str> {
+    let conn = match x11rb::connect(None) {
+        Ok((conn, _)) => conn,
+        Err(err) => {
+            debug!("Cannot ask the X server for its extensions: {err}");
+            return Vec::new();
+        }
+    };
+    let is_supported =
+        |name: &&'static str| matches!(conn.extension_information(name), Ok(Some(_)));
+    extensions.iter().copied().filter(is_supported).collect()
+}
+
+/// Returns two callbacks reporting to `callback`, for recorders that report
+/// from two workers.
+fn share(
+    callback: platform::Callback,
+) -> (
+    impl Fn(&event::Event) + Send + Sync + 'static,
+    impl Fn(&event::Event) + Send + Sync + 'static,
+) {
+    let callback = Arc::new(callback);
+    let shared = Arc::clone(&callback);
+    (
+        move |event: &event::Event| callback(event),
+        move |event: &event::Event| shared(event),
+    )
+}
+
+/// Starts recording input and focus events of all clients on a worker thread,
+/// returning a handle to pause, resume and stop the recording.
+///
+/// When the connection to the X server is lost (e.g. the server restarts), a
+/// [`event::EventData::RecorderDisconnected`] marker is reported, and the recorder
+/// keeps reconnecting with an increasing delay. Once it succeeds, recording
+/// continues after a [`event::EventData::RecorderResumed`] marker.
+///
+/// If the X server does not support RECORD, the active window is sampled every
+/// `poll_interval` instead, see [`poll`], reconnecting the same way.
+pub fn record<C>(callback: C, poll_interval: Duration) -> Recording
+where
+    C: Fn(&event::Event) + Send + Sync + 'static,
+{
+    let control = Arc::new(Control::default());
+    let worker = {
+        let control = Arc::clone(&control);
+        std::thread::spawn(move || work(&callback, &control, poll_interval))
+    };
+    Recording::new(control, worker)
+}
+
+/// Records (and reconnects) until stopped or an unrecoverable error occurs.
+fn work(
+    callback: &impl Fn(&event::Event),
+    control: &Control,
+    poll_interval: Duration,
+) -> Result<(), Error> {
+    let run = |session: Session| {
+        control.set_session(Some((Arc::clone(&session.ctrl_conn), session.context)));
+        // every run ends when the context is disabled, by pausing or stopping
+        let result = loop {
+            if control.wait_while_paused() == State::Stopped {
+                break session.free();
+            }
+            if let Err(err) = session.run(callback, control) {
+                break Err(err);
+            }
+        };
+        control.set_session(None);
+        result
+    };
+    let connected = |connected| report_connection(callback, connected);
+    match reconnecting(control, Session::connect, run, connected) {
+        Err(Error::MissingExtension(name)) => {
+            warn!(
+                "The X server does not support the {name} extension, sampling the \
+                 active window every {poll_interval:?} instead, without recording input"
+            );
+            poll::run(callback, control, poll_interval)
+        }
+        result => result,
+    }
+}
+
+/// Connects and runs sessions until stopped or an unrecoverable error occurs.
+///
+/// After the connection to the X server is lost, connecting is retried with an
+/// increasing delay, and `connected` is told about losing and regaining it.
+fn reconnecting<S>(
+    control: &Control,
+    mut connect: impl FnMut() -> Result<S, Error>,
+    mut run: impl FnMut(S) -> Result<(), Error>,
+    mut connected: impl FnMut(bool),
+) -> Result<(), Error> {
+    let mut delay = RECONNECT_DELAY;
+    let mut disconnected = false;
+    loop {
+        let session = match connect() {
+            Ok(session) => session,
+            Err(err) if disconnected && err.is_disconnect() => {
+                info!("Reconnecting in {delay:?}: {err}");
+                if control.sleep(delay) {
+                    return Ok(());
+                }
+                delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+
+        if disconnected {
+            info!("Reconnected to the X server");
+            connected(true);
+            delay = RECONNECT_DELAY;
+        }
+
+        match run(session) {
+            Err(err) if err.is_disconnect() => {
+                warn!("Lost the connection to the X server: {err}");
+                connected(false);
+                disconnected = true;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// The initial delay before reconnecting to the X server
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+/// The delay between reconnection attempts doubles up to this
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+
+/// Reports losing or regaining the connection to the X server.
+fn report_connection(callback: &impl Fn(&event::Event), connected: bool) {
+    let data = match connected {
+        true => event::EventData::RecorderResumed,
+        false => event::EventData::RecorderDisconnected,
+    };
+    report_marker(callback, data);
+}
+
+/// Reports an event of the recorder itself, which is not tied to any application.
+fn report_marker(callback: &impl Fn(&event::Event), data: event::EventData) {
+    let event = event::Event {
+        timestamp: chrono::Utc::now(),
+        instant: std::time::Instant::now(),
+        app: String::new(),
+        title: None,
+        workspace: None,
+        device: None,
+        process: None,
+        output: None,
+        data,
+    };
+    callback(&event);
+}
+
+/// The connections to the X server and the recording context created on them.
+struct Session {
+    data_conn: RustConnection,
+    /// shared with the [`Recording`] handle to disable the context
+    ctrl_conn: Arc<RustConnection>,
+    context: record::Context,
+    atoms: Atoms,
+    /// the root window of the default screen, whose workspace events are reported with
+    root: xproto::Window,
+}
+
+impl Session {
+    fn connect() -> Result<Self, Error> {
+        // From https://www.x.org/releases/X11R7.6/doc/recordproto/record.html
+        // "The typical communication model for a recording client is to open two
+        // connections to the server and use one for RC control and the other for
+        // reading protocol data."
+        let (data_conn, _) = x11rb::connect(None)?;
+        let (ctrl_conn, screen) = x11rb::connect(None)?;
+        let root = ctrl_conn.setup().roots[screen].root;
+
+        // Check if the record extension is supported.
+        if ctrl_conn
+            .extension_information(record::X11_EXTENSION_NAME)?
+            .is_none()
+        {
+            return Err(Error::MissingExtension(record::X11_EXTENSION_NAME));
+        }
+
+        // Intern the atoms used for property lookups once for the whole session
+        let atoms = Atoms::new(&ctrl_conn)?.reply()?;
+
+        // Set up a recording range for events of interest
+        let mut range = record::Range::default();
+        range.delivered_events.first = record::ElementHeader::from(xproto::KEY_PRESS_EVENT);
+        range.delivered_events.last = record::ElementHeader::from(xproto::FOCUS_OUT_EVENT);
+
+        // Property changes and the lifecycle of windows are recorded separately, to keep
+        // the window cache up to date, to see title changes without a focus change, and
+        // to report windows opening and closing
+        let mut property_range = record::Range::default();
+        property_range.delivered_events.first =
+            record::ElementHeader::from(xproto::PROPERTY_NOTIFY_EVENT);
+        property_range.delivered_events.last =
+            record::ElementHeader::from(xproto::PROPERTY_NOTIFY_EVENT);
+        let mut lifecycle_range = record::Range::default();
+        lifecycle_range.delivered_events.first =
+            record::ElementHeader::from(xproto::CREATE_NOTIFY_EVENT);
+        lifecycle_range.delivered_events.last =
+            record::ElementHeader::from(xproto::MAP_NOTIFY_EVENT);
+
+        // Set up a recording context
+        let context = ctrl_conn.generate_id()?;
+        ctrl_conn
+            .record_create_context(
+                context,
+                0,
+                &[record::CS::ALL_CLIENTS.into()],
+                &[range, property_range, lifecycle_range],
+            )?
+            .check()?;
+
+        // Watch the roots for changes of the active window, and for the windows created
+        // on them. Events are only delivered, and so recorded, if a client selected them.
+        for screen in &ctrl_conn.setup().roots {
+            ctrl_conn.change_window_attributes(
+                screen.root,
+                &ChangeWindowAttributesAux::new().event_mask(event_mask(true)),
+            )?;
+        }
+        ctrl_conn.flush()?;
+
+        Ok(Self {
+            data_conn,
+            ctrl_conn: Arc::new(ctrl_conn),
+            context,
+            atoms,
+            root,
+        })
+    }
+
+    /// Frees the recording context once recording has stopped.
+    fn free(&self) -> Result<(), Error> {
+        self.ctrl_conn.record_free_context(self.context)?.check()?;
+        Ok(())
+    }
+
+    /// Records until the recording context is disabled or an error occurs.
+    fn run<C: Fn(&event::Event)>(&self, callback: &C, control: &Control) -> Result<(), Error> {
+        let ctrl_conn = &*self.ctrl_conn;
+
+        // Event times are derived from the server timestamps
+        let mut clock = Clock::new();
+
+        // Window metadata is looked up through the control connection
+        let mut cache = WindowCache::new();
+        let mut windows = Windows {
+            conn: ctrl_conn,
+            atoms: self.atoms,
+            root: self.root,
+            cache: &mut cache,
+            fallbacks: Fallbacks::default(),
+        };
+
+        // The monitors, to tell which one events happen on
+        let mut outputs = match randr::Outputs::watch(ctrl_conn, self.root) {
+            Ok(outputs) if outputs.is_empty() => {
+                warn!("The X server does not report monitors through RandR 1.3");
+                outputs
+            }
+            Ok(outputs) => {
+                info!("Monitors: {outputs}");
+                outputs
+            }
+            // the monitors are unknown until they change
+            Err(err) if !err.is_disconnect() => {
+                warn!("Cannot query the monitors: {err}");
+                randr::Outputs::default()
+            }
+            Err(err) => return Err(err),
+        };
+
+        // The focused window, to report its title changes, and whether it is fullscreen
+        let mut focused: Option<xproto::Window> = None;
+        let mut fullscreen = false;
+        // The top-level windows, to report their lifecycle
+        let mut toplevels = Toplevels::default();
+        // The last property change, every change is recorded once per interested client
+        let mut last_change: Option<(xproto::Window, xproto::Atom, xproto::Timestamp)> = None;
+
+        // We now switch to using "the other" connection.
+        const START_OF_DATA: u8 = 4;
+        const RECORD_FROM_SERVER: u8 = 0;
+        for reply in self.data_conn.record_enable_context(self.context)? {
+            let reply = reply?;
+            if reply.category == RECORD_FROM_SERVER {
+                // the context may have been enabled just after it was disabled
+                if *control.state() != State::Recording {
+                    control.disable()?;
+                    continue;
+                }
+
+                // the data is in the byte order of the client it was recorded for
+                let stream = if reply.client_swapped {
+                    Stream::swapped(&reply.data)
+                } else {
+                    Stream::new(&reply.data)
+                };
+                for element in stream {
+                    match element? {
+                        Element::Reply(data) => {
+                            warn!("unparsed reply: {data:?}");
+                        }
+                        Element::KeyPress(event) => {
+                            let data = event::EventData::KeyPress(event.detail.into());
+                            report(
+                                &callback,
+                                &mut windows,
+                                event.event,
+                                clock.at(event.time),
+                                None,
+                                data,
+                            )?;
+                        }
+                        Element::KeyRelease(event) => {
+                            let data = event::EventData::KeyRelease(event.detail.into());
+                            report(
+                                &callback,
+                                &mut windows,
+                                event.event,
+                                clock.at(event.time),
+                                None,
+                                data,
+                            )?;
+                        }
+                        Element::ButtonPress(event) => {
+                            // wheel buttons are reported as scrolling instead of presses
+                            let data = match scroll_delta(event.detail) {
+                                Some((dx, dy)) => event::EventData::Scroll { dx, dy },
+                                None => event::EventData::PointerPress(event.detail),
+                            };
+                            report(
+                                &callback,
+                                &mut windows,
+                                event.event,
+                                clock.at(event.time),
+                                outputs.at(event.root_x, event.root_y),
+                                data,
+                            )?;
+                        }
+                        // wheel buttons are released immediately after every step
+                        Element::ButtonRelease(event) if scroll_delta(event.detail).is_none() => {
+                            let data = event::EventData::PointerRelease(event.detail);
+                            report(
+                                &callback,
+                                &mut windows,
+                                event.event,
+                                clock.at(event.time),
+                                outputs.at(event.root_x, event.root_y),
+                                data,
+                            )?;
+                        }
+                        Element::MotionNotify(event) => {
+                            // get the __active__ window, because this event will be reported to root,
+                            // not any specific window
+                            if let Some(active_win) = windows.active(event.root)? {
+                                let data = event::EventData::PointerMove {
+                                    x: event.root_x as f64,
+                                    y: event.root_y as f64,
+                                };
+                                let time = clock.at(event.time);
+                                let output = outputs.at(event.root_x, event.root_y);
+                                report(callback, &mut windows, active_win, time, output, data)?;
+                            }
+                        }
+                        Element::EnterNotify(event) if is_crossing(event.mode, event.detail) => {
+                            let data = event::EventData::EnterWindow;
+                            report(
+                                &callback,
+                                &mut windows,
+                                event.event,
+                                clock.at(event.time),
+                                outputs.at(event.root_x, event.root_y),
+                                data,
+                            )?;
+                        }
+                        Element::LeaveNotify(event) if is_crossing(event.mode, event.detail) => {
+                            let data = event::EventData::LeaveWindow;
+                            report(
+                                &callback,
+                                &mut windows,
+                                event.event,
+                                clock.at(event.time),
+                                outputs.at(event.root_x, event.root_y),
+                                data,
+                            )?;
+                        }
+                        Element::FocusIn(event) => {
+                            focused = Some(event.event);
+                            let data = event::EventData::FocusIn;
+                            let output = windows.output(&outputs, event.event)?;
+                            let output = output.as_deref();
+                            let time = clock.now();
+                            report(callback, &mut windows, event.event, time, output, data)?;
+
+                            let is_fullscreen = windows.is_fullscreen(event.event)?;
+                            if let Some(data) = fullscreen_change(fullscreen, is_fullscreen) {
+                                report(callback, &mut windows, event.event, time, output, data)?;
+                            }
+                            fullscreen = is_fullscreen;
+                        }
+                        Element::FocusOut(event) => {
+                            let output = windows.output(&outputs, event.event)?;
+                            let output = output.as_deref();
+                            let time = clock.now();
+                            // leaving a fullscreen window ends watching it
+                            if let Some(data) = fullscreen_change(fullscreen, false) {
+                                report(callback, &mut windows, event.event, time, output, data)?;
+                            }
+                            fullscreen = false;
+
+                            let data = event::EventData::FocusOut;
+                            report(callback, &mut windows, event.event, time, output, data)?;
+                        }
+                        Element::PropertyNotify(event) => {
+                            let change = Some((event.window, event.atom, event.time));
+                            if std::mem::replace(&mut last_change, change) == change {
+                                continue;
+                            }
+
+                            if event.atom == windows.atoms._NET_ACTIVE_WINDOW {
+                                windows.cache.invalidate_active(event.window);
+                            } else if windows.is_workspace(event.atom) {
+                                let previous = windows.cache.invalidate_workspace(event.window);
+
+                                // renaming the desktops is not a switch
+                                if event.atom == windows.atoms._NET_CURRENT_DESKTOP
+                                    && event.window == windows.root
+                                {
+                                    let workspace = windows.workspace()?;
+                                    if previous.is_none_or(|previous| previous != workspace) {
+                                        let time = clock.at(event.time);
+                                        report_workspace(callback, workspace, time);
+                                    }
+                                }
+                            } else if windows.is_metadata(event.atom) {
+                                let previous = windows.cache.invalidate(event.window);
+
+                                // report title and fullscreen changes of the focused window
+                                if focused == Some(event.window) {
+                                    let info = windows.info(event.window)?.unwrap_or_default();
+                                    let time = clock.at(event.time);
+                                    let window = event.window;
+                                    if info.title != previous.and_then(|info| info.title) {
+                                        let data = event::EventData::TitleChange;
+                                        report(callback, &mut windows, window, time, None, data)?;
+                                    }
+                                    if let Some(data) =
+                                        fullscreen_change(fullscreen, info.fullscreen)
+                                    {
+                                        report(callback, &mut windows, window, time, None, data)?;
+                                    }
+                                    fullscreen = info.fullscreen;
+                                }
+                            }
+                        }
+                        Element::CreateNotify(event)
+                            if event.parent == windows.root && !event.override_redirect =>
+                        {
+                            // the lookup selects the events of the window, its
+                            // metadata is looked up again when it is mapped
+                            windows.info(event.window)?;
+                            toplevels.create(event.window, clock.now());
+                        }
+                        // menus and tooltips are never followed, and neither are
+                        // windows that were not created as top-level windows
+                        Element::MapNotify(event)
+                            if !event.override_redirect && toplevels.knows(event.window) =>
+                        {
+                            let window = event.window;
+                            let info = windows.info(window)?;
+                            if let Some((created, info)) = toplevels.map(window, info) {
+                                let output = windows.output(&outputs, window)?;
+                                let output = output.as_deref();
+                                if let Some(time) = created {
+                                    let (info, data) = (info.clone(), Change::Created.data(window));
+                                    report_info(callback, &mut windows, info, time, output, data)?;
+                                }
+                                let (time, data) = (clock.now(), Change::Mapped.data(window));
+                                report_info(callback, &mut windows, info, time, output, data)?;
+                            }
+                        }
+                        Element::UnmapNotify(event) if toplevels.follows(event.window) => {
+                            let window = event.window;
+                            let info = windows.info(window)?;
+                            if let Some(info) = toplevels.unmap(window, info) {
+                                let (time, data) = (clock.now(), Change::Unmapped.data(window));
+                                report_info(callback, &mut windows, info, time, None, data)?;
+                            }
+                        }
+                        Element::DestroyNotify(event) => {
+                            windows.cache.remove(event.window);
+
+                            // the window is gone, it is reported as it was last seen
+                            let window = event.window;
+                            if let Some(info) = toplevels.destroy(window) {
+                                let (time, data) = (clock.now(), Change::Destroyed.data(window));
+                                report_info(callback, &mut windows, info, time, None, data)?;
+                            }
+                        }
+                        // errors and events we do not track
+                        _ => {}
+                    }
+                }
+
+                // Discard the events we receive ourselves on the control connection,
+                // they were already seen through the recorded stream. Only the
+                // notifications about changed outputs are not recorded.
+                let mut changed = false;
+                while let Some(event) = ctrl_conn.poll_for_event()? {
+                    changed |= randr::is_change(&event);
+                }
+                if changed {
+                    match randr::Outputs::query(ctrl_conn, self.root) {
+                        Ok(current) if current != outputs => {
+                            info!("Monitors changed: {current}");
+                            outputs = current;
+                            let (timestamp, instant) = clock.now();
+                            callback(&event::Event {
+                                timestamp,
+                                instant,
+                                app: String::new(),
+                                title: None,
+                                workspace: None,
+                                device: None,
+                                process: None,
+                                output: Some(outputs.to_string()),
+                                data: event::EventData::MonitorsChange,
+                            });
+                        }
+                        Ok(_) => {}
+                        // outputs can change again while they are queried
+                        Err(err) if !err.is_disconnect() => warn!("Skipping monitors: {err}"),
+                        Err(err) => return Err(err),
+                    }
+                }
+            } else if reply.category == START_OF_DATA {
+                info!("Start of data stream...");
+                // pausing or stopping before the context was enabled could not
+                // disable it, and no recorded data may follow to notice
+                if *control.state() != State::Recording {
+                    control.disable()?;
+                }
+            } else {
+                warn!("Got a reply with an unsupported category: {reply:?}");
+            }
+        }
+
+        if windows.fallbacks.total() > 0 {
+            info!(
+                "Events of windows without a WM_CLASS: {}",
+                windows.fallbacks
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Returns the scroll steps `(dx, dy)` of the wheel buttons 4 to 7.
+fn scroll_delta(button: xproto::Button) -> Option<(f64, f64)> {
+    match button {
+        4 => Some((0.0, -1.0)),
+        5 => Some((0.0, 1.0)),
+        6 => Some((-1.0, 0.0)),
+        7 => Some((1.0, 0.0)),
+        _ => None,
+    }
+}
+
+/// Whether an enter or leave notification is an actual pointer crossing, rather
+/// than a side effect of a grab or a move between a window and its children.
+fn is_crossing(mode: xproto::NotifyMode, detail: xproto::NotifyDetail) -> bool {
+    mode == xproto::NotifyMode::NORMAL && detail != xproto::NotifyDetail::INFERIOR
+}
+
+/// Reports an event for a window. Events of windows that could not be looked
+/// up, or lack anything to identify the app by, are attributed to [`UNKNOWN_APP`].
+fn report<C: Connection>(
+    callback: &impl Fn(&event::Event),
+    windows: &mut Windows<'_, C>,
+    window: xproto::Window,
+    (timestamp, instant): (chrono::DateTime<chrono::Utc>, std::time::Instant),
+    output: Option<&str>,
+    data: event::EventData,
+) -> Result<(), Error> {
+    let info = windows.info(window)?.unwrap_or_default();
+    report_info(callback, windows, info, (timestamp, instant), output, data)
+}
+
+/// Reports an event for a window with metadata that was already looked up.
+fn report_info<C: Connection>(
+    callback: &impl Fn(&event::Event),
+    windows: &mut Windows<'_, C>,
+    info: WindowInfo,
+    (timestamp, instant): (chrono::DateTime<chrono::Utc>, std::time::Instant),
+    output: Option<&str>,
+    data: event::EventData,
+) -> Result<(), Error> {
+    windows.fallbacks.count(info.source);
+    let event = event::Event {
+        timestamp,
+        instant,
+        app: info.app.unwrap_or_else(|| UNKNOWN_APP.to_string()),
+        title: info.title,
+        workspace: windows.workspace()?,
+        device: None,
+        process: info.process,
+        output: output.map(str::to_string),
+        data,
+    };
+    callback(&event);
+    Ok(())
+}
+
+/// Returns the event for a change of whether the focused window is fullscreen.
+fn fullscreen_change(before: bool, after: bool) -> Option<event::EventData> {
+    match (before, after) {
+        (false, true) => Some(event::EventData::FullscreenStart),
+        (true, false) => Some(event::EventData::FullscreenEnd),
+        _ => None,
+    }
+}
+
+/// Reports a switch to another workspace, which is not tied to an application.
+fn report_workspace(
+    callback: &impl Fn(&event::Event),
+    workspace: Option<String>,
+    (timestamp, instant): (chrono::DateTime<chrono::Utc>, std::time::Instant),
+) {
+    callback(&event::Event {
+        timestamp,
+        instant,
+        app: String::new(),
+        title: None,
+        workspace,
+        device: None,
+        process: None,
+        output: None,
+        data: event::EventData::WorkspaceChange,
+    });
+}
+
+/// How many events were attributed through each fallback, rather than the
+/// `WM_CLASS` of their window.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Fallbacks {
+    pub parent: u64,
+    pub process: u64,
+    pub title: u64,
+    pub unknown: u64,
+}
+
+impl Fallbacks {
+    fn count(&mut self, source: AppSource) {
+        match source {
+            AppSource::Class => {}
+            AppSource::Parent => self.parent += 1,
+            AppSource::Process => self.process += 1,
+            AppSource::Title => self.title += 1,
+            AppSource::Unknown => self.unknown += 1,
+        }
+    }
+
+    /// Returns the number of events attributed through any fallback.
+    pub fn total(&self) -> u64 {
+        self.parent + self.process + self.title + self.unknown
+    }
+}
+
+impl fmt::Display for Fallbacks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} by parent window, {} by process, {} by title, {} unknown",
+            self.parent, self.process, self.title, self.unknown
+        )
+    }
+}
+
+/// Returns the events to select on a window whose metadata is looked up.
+///
+/// Selecting events replaces what the client selected before, so root windows
+/// keep the windows created on them selected along with their own changes.
+fn event_mask(is_root: bool) -> EventMask {
+    match is_root {
+        true => EventMask::PROPERTY_CHANGE | EventMask::SUBSTRUCTURE_NOTIFY,
+        false => EventMask::PROPERTY_CHANGE | EventMask::STRUCTURE_NOTIFY,
+    }
+}
+
+/// Looks up window metadata through the control connection, caching the results.
+struct Windows<'c, C: Connection> {
+    conn: &'c C,
+    atoms: Atoms,
+    /// the root window of the default screen
+    root: xproto::Window,
+    cache: &'c mut WindowCache,
+    /// the fallbacks used for the reported events
+    fallbacks: Fallbacks,
+}
+
+impl<C: Connection> Windows<'_, C> {
+    /// Returns the metadata of a window, or `None` if it could not be looked up.
+    fn info(&mut self, window: xproto::Window) -> Result<Option<WindowInfo>, Error> {
+        let Windows {
+            conn, atoms, cache, ..
+        } = self;
+        let info = cache.get_or_fetch(window, || {
+            // get notified when the metadata changes or the window is destroyed
+            let is_root = conn
+                .setup()
+                .roots
+                .iter()
+                .any(|screen| screen.root == window);
+            conn.change_window_attributes(
+                window,
+                &ChangeWindowAttributesAux::new().event_mask(event_mask(is_root)),
+            )?;
+            get_window_info(*conn, atoms, window)
+        });
+        skip_on_error(info.map(|info| Some(info.clone())))
+    }
+
+    /// Returns the active window of the root window.
+    fn active(&mut self, root: xproto::Window) -> Result<Option<xproto::Window>, Error> {
+        let Windows {
+            conn, atoms, cache, ..
+        } = self;
+        skip_on_error(cache.active_or_fetch(root, || get_active_window(*conn, atoms, root)))
+    }
+
+    /// Whether the window is fullscreen, `false` if it could not be looked up.
+    fn is_fullscreen(&mut self, window: xproto::Window) -> Result<bool, Error> {
+        Ok(self.info(window)?.is_some_and(|info| info.fullscreen))
+    }
+
+    /// Returns the output showing most of the window, or `None` if it could not
+    /// be looked up.
+    fn output(
+        &self,
+        outputs: &randr::Outputs,
+        window: xproto::Window,
+    ) -> Result<Option<String>, Error> {
+        let output = outputs.of_window(self.conn, self.root, window);
+        skip_on_error(output.map(|output| output.map(str::to_string)))
+    }
+
+    /// Returns the workspace the user is on, the current desktop of the default screen.
+    fn workspace(&mut self) -> Result<Option<String>, Error> {
+        let Windows {
+            conn,
+            atoms,
+            root,
+            cache,
+            ..
+        } = self;
+        let workspace = cache.workspace_or_fetch(*root, || get_workspace(*conn, atoms, *root));
+        skip_on_error(workspace)
+    }
+
+    /// Whether the property is one of the root window the workspace is derived from.
+    fn is_workspace(&self, atom: xproto::Atom) -> bool {
+        atom == self.atoms._NET_CURRENT_DESKTOP || atom == self.atoms._NET_DESKTOP_NAMES
+    }
+
+    /// Whether the property is one the window metadata is derived from.
+    fn is_metadata(&self, atom: xproto::Atom) -> bool {
+        atom == u32::from(AtomEnum::WM_CLASS)
+            || atom == u32::from(AtomEnum::WM_NAME)
+            || atom == self.atoms._NET_WM_NAME
+            || atom == self.atoms._NET_WM_PID
+            || atom == self.atoms._NET_WM_STATE
+    }
+}
+
+/// How many parents of a window are searched for a `WM_CLASS`.
+const MAX_PARENTS: usize = 8;
+
+/// Looks up the metadata of a window. Windows without a `WM_CLASS`, like
+/// popups and sub-windows, are attributed to the first of: the class of a
+/// parent window, the executable of their process, and their title.
+fn get_window_info(
+    conn: &impl Connection,
+    atoms: &Atoms,
+    window: xproto::Window,
+) -> Result<WindowInfo, Error> {
+    let mut info = WindowInfo {
+        app: get_window_class(conn, window)?,
+        source: AppSource::Class,
+        title: get_window_name(conn, atoms, window)?,
+        process: get_window_process(conn, atoms, window)?.map(Arc::new),
+        fullscreen: is_window_fullscreen(conn, atoms, window)?,
+    };
+    if info.app.is_some() {
+        return Ok(info);
+    }
+
+    if let Some((parent, class)) = get_parent_class(conn, window)? {
+        // sub-windows rarely have a title or process of their own
+        if info.title.is_none() {
+            info.title = get_window_name(conn, atoms, parent)?;
+        }
+        if info.process.is_none() {
+            info.process = get_window_process(conn, atoms, parent)?.map(Arc::new);
+        }
+        info.app = Some(class);
+        info.source = AppSource::Parent;
+    } else if let Some(name) = info.process.as_deref().and_then(linux::process::name) {
+        info.app = Some(name);
+        info.source = AppSource::Process;
+    } else if let Some(title) = &info.title {
+        info.app = Some(title.clone());
+        info.source = AppSource::Title;
+    } else {
+        info.source = AppSource::Unknown;
+    }
+    debug!(
+        "Window {window:#x} has no WM_CLASS, attributed by {:?}",
+        info.source
+    );
+    Ok(info)
+}
+
+/// Returns the closest parent of the window below the root that has a `WM_CLASS`.
+fn get_parent_class(
+    conn: &impl Connection,
+    mut window: xproto::Window,
+) -> Result<Option<(xproto::Window, String)>, Error> {
+    for _ in 0..MAX_PARENTS {
+        let tree = conn
+            .query_tree(window)?
+            .reply()
+            .map_err(|err| property_error(err, window, "parent"))?;
+        if tree.parent == tree.root || tree.parent == x11rb::NONE {
+            break;
+        }
+        window = tree.parent;
+        if let Some(class) = get_window_class(conn, window)? {
+            return Ok(Some((window, class)));
+        }
+    }
+    Ok(None)
+}
+
+/// Returns the class of the `WM_CLASS` property, unless it is empty.
+fn get_window_class(
+    conn: &impl Connection,
+    window: xproto::Window,
+) -> Result<Option<String>, Error> {
+    let wm_class = match WmClass::get(conn, window)?.reply_unchecked()? {
+        Some(wm_class) => wm_class,
+        None => return Ok(None),
+    };
+    // WM_CLASS is a STRING, so ISO 8859-1 by definition, but applications
+    // commonly set UTF-8, which is what non-ASCII classes are decoded as first
+    let class = match std::str::from_utf8(wm_class.class()) {
+        Ok(class) => class.to_string(),
+        Err(_) => decode_latin1(wm_class.class()),
+    };
+    Ok(Some(class).filter(|class| !class.is_empty()))
+}
+
+/// Returns the process owning the window, through its `_NET_WM_PID`. The id
+/// only refers to a local process if the client runs on the machine it names
+/// in `WM_CLIENT_MACHINE`.
+fn get_window_process(
+    conn: &impl Connection,
+    atoms: &Atoms,
+    window: xproto::Window,
+) -> Result<Option<event::Process>, Error> {
+    let pid = conn.get_property(false, window, atoms._NET_WM_PID, AtomEnum::CARDINAL, 0, 1)?;
+    let machine = conn.get_property(
+        false,
+        window,
+        AtomEnum::WM_CLIENT_MACHINE,
+        AtomEnum::STRING,
+        0,
+        0x100,
+    )?;
+    let pid = pid
+        .reply_unchecked()?
+        .and_then(|prop| prop.value32()?.next());
+    let machine = machine
+        .reply_unchecked()?
+        .filter(|prop| prop.value_len > 0 && prop.format == 8)
+        .map(|prop| decode_latin1(&prop.value));
+    match pid {
+        Some(pid) if machine.is_none_or(|machine| linux::process::is_local(&machine)) => {
+            Ok(linux::process::lookup(pid))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Whether the window manager shows the window fullscreen, as told by its
+/// `_NET_WM_STATE`.
+fn is_window_fullscreen(
+    conn: &impl Connection,
+    atoms: &Atoms,
+    window: xproto::Window,
+) -> Result<bool, Error> {
+    let state = conn
+        .get_property(false, window, atoms._NET_WM_STATE, AtomEnum::ATOM, 0, 64)?
+        .reply_unchecked()?;
+    Ok(state
+        .and_then(|prop| {
+            Some(
+                prop.value32()?
+                    .any(|atom| atom == atoms._NET_WM_STATE_FULLSCREEN),
+            )
+        })
+        .unwrap_or(false))
+}
+
+/// Returns the title of the window, preferring the EWMH `_NET_WM_NAME` and
+/// falling back to the ICCCM `WM_NAME` in its legacy encodings.
+fn get_window_name(
+    conn: &impl Connection,
+    atoms: &Atoms,
+    window: xproto::Window,
+) -> Result<Option<String>, Error> {
+    let net_wm_name = conn
+        .get_property(
+            false,
+            window,
+            atoms._NET_WM_NAME,
+            atoms.UTF8_STRING,
+            0,
+            0x1000,
+        )?
+        .reply_unchecked()?;
+    if let Some(prop) = net_wm_name.filter(|prop| prop.value_len > 0) {
+        return Ok(Some(String::from_utf8_lossy(&prop.value).into_owned()));
+    }
+
+    let wm_name = match conn
+        .get_property(false, window, AtomEnum::WM_NAME, AtomEnum::ANY, 0, 0x1000)?
+        .reply_unchecked()?
+    {
+        Some(prop) if prop.value_len > 0 && prop.format == 8 => prop,
+        _ => return Ok(None),
+    };
+
+    let name = if wm_name.type_ == u32::from(AtomEnum::STRING) {
+        Some(decode_latin1(&wm_name.value))
+    } else if wm_name.type_ == atoms.COMPOUND_TEXT {
+        Some(decode_compound_text(&wm_name.value))
+    } else if wm_name.type_ == atoms.UTF8_STRING {
+        Some(String::from_utf8_lossy(&wm_name.value).into_owned())
+    } else {
+        None
+    };
+    Ok(name)
+}
+
+/// Decodes a `STRING` property, which is ISO 8859-1 by definition.
+fn decode_latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| char::from(b)).collect()
+}
+
+/// Decodes the subset of `COMPOUND_TEXT` that applications produce in practice:
+/// ISO 8859-1 (the initial state), and UTF-8 segments. Text in other character
+/// sets is replaced with U+FFFD.
+fn decode_compound_text(bytes: &[u8]) -> String {
+    enum Charset {
+        Latin1,
+        Utf8,
+        Unsupported,
+    }
+
+    const ESC: u8 = 0x1b;
+    let mut name = String::with_capacity(bytes.len());
+    let mut charset = Charset::Latin1;
+    let mut rest = bytes;
+    while !rest.is_empty() {
+        if rest[0] == ESC {
+            // an escape sequence consists of intermediate bytes (0x20..=0x2f)
+            // followed by a single final byte (0x30..=0x7e)
+            let end = rest[1..]
+                .iter()
+                .position(|b| !(0x20..=0x2f).contains(b))
+                .map_or(rest.len(), |i| i + 2)
+                .min(rest.len());
+            charset = match &rest[1..end] {
+                // ASCII or ISO 8859-1 right-hand part
+                b"(B" | b"-A" => Charset::Latin1,
+                // UTF-8 segment, terminated by ESC % @
+                b"%G" => Charset::Utf8,
+                b"%@" => Charset::Latin1,
+                _ => Charset::Unsupported,
+            };
+            rest = &rest[end..];
+            continue;
+        }
+
+        let end = rest.iter().position(|&b| b == ESC).unwrap_or(rest.len());
+        let (segment, remaining) = rest.split_at(end);
+        match charset {
+            Charset::Latin1 => name.push_str(&decode_latin1(segment)),
+            Charset::Utf8 => name.push_str(&String::from_utf8_lossy(segment)),
+            Charset::Unsupported => name.push(char::REPLACEMENT_CHARACTER),
+        }
+        rest = remaining;
+    }
+    name
+}
+
+/// Returns the window that currently has the focus according to the window manager,
+/// or `None` when no window is active (e.g. the desktop is focused).
+fn get_active_window(
+    conn: &impl Connection,
+    atoms: &Atoms,
+    root: xproto::Window,
+) -> Result<Option<xproto::Window>, Error> {
+    let reply = conn
+        .get_property(
+            false,
+            root,
+            atoms._NET_ACTIVE_WINDOW,
+            AtomEnum::WINDOW,
+            0,
+            1,
+        )?
+        .reply()
+        .map_err(|err| property_error(err, root, "_NET_ACTIVE_WINDOW"))?;
+    active_window(root, &reply)
+}
+
+/// Returns the window in a `_NET_ACTIVE_WINDOW` property of the root window.
+fn active_window(
+    root: xproto::Window,
+    reply: &xproto::GetPropertyReply,
+) -> Result<Option<xproto::Window>, Error> {
+    // window managers without EWMH support do not set the property at all
+    if reply.type_ == x11rb::NONE || reply.format == 0 {
+        return Ok(None);
+    }
+    let win = reply
+        .value32()
+        .ok_or_else(|| Error::Property {
+            window: root,
+            property: "_NET_ACTIVE_WINDOW",
+            reason: "incorrect format".to_string(),
+        })?
+        .next()
+        .filter(|&win| win != x11rb::NONE);
+    Ok(win)
+}
+
+/// Returns the name of the current desktop of the root window, or `None` if the
+/// window manager does not support desktops.
+fn get_workspace(
+    conn: &impl Connection,
+    atoms: &Atoms,
+    root: xproto::Window,
+) -> Result<Option<String>, Error> {
+    let current = conn.get_property(
+        false,
+        root,
+        atoms._NET_CURRENT_DESKTOP,
+        AtomEnum::CARDINAL,
+        0,
+        1,
+    )?;
+    let names = conn.get_property(
+        false,
+        root,
+        atoms._NET_DESKTOP_NAMES,
+        atoms.UTF8_STRING,
+        0,
+        0x1000,
+    )?;
+    let current = current
+        .reply()
+        .map_err(|err| property_error(err, root, "_NET_CURRENT_DESKTOP"))?
+        .value32()
+        .and_then(|mut value| value.next());
+    let names = names
+        .reply()
+        .map_err(|err| property_error(err, root, "_NET_DESKTOP_NAMES"))?;
+    Ok(current.map(|desktop| desktop_name(&names.value, desktop)))
+}
+
+/// Returns the name of a desktop from `_NET_DESKTOP_NAMES`, a list of null
+/// terminated names. Desktops without a name are named by their number,
+/// counting from 1 like pagers do.
+fn desktop_name(names: &[u8], desktop: u32) -> String {
+    names
+        .split(|&byte| byte == 0)
+        .nth(desktop as usize)
+        .filter(|name| !name.is_empty())
+        .map(|name| String::from_utf8_lossy(name).into_owned())
+        .unwrap_or_else(|| (desktop + 1).to_string())
+}
+
+/// Classifies a failed property request: the window being gone (or any other
+/// X error) only affects this lookup, whereas a broken connection is fatal.
+fn property_error(err: ReplyError, window: xproto::Window, property: &'static str) -> Error {
+    match err {
+        ReplyError::X11Error(err) => Error::Property {
+            window,
+            property,
+            reason: format!("{:?}", err.error_kind),
+        },
+        ReplyError::ConnectionError(err) => err.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_desktops() {
+        let names = b"comms\0code\0\0research\0";
+        assert_eq!(desktop_name(names, 0), "comms");
+        assert_eq!(desktop_name(names, 1), "code");
+        assert_eq!(desktop_name(names, 2), "3");
+        assert_eq!(desktop_name(names, 3), "research");
+        assert_eq!(desktop_name(names, 4), "5");
+        assert_eq!(desktop_name(b"", 0), "1");
+    }
+
+    #[test]
+    fn reads_the_active_window() {
+        let property = |type_: AtomEnum, format, value: &[u8]| xproto::GetPropertyReply {
+            format,
+            sequence: 1,
+            length: 1,
+            type_: type_.into(),
+            bytes_after: 0,
+            value_len: value.len() as u32 / 4,
+            value: value.to_vec(),
+        };
+        let active = property(AtomEnum::WINDOW, 32, &0x1e00005u32.to_ne_bytes());
+        assert_eq!(active_window(1, &active).unwrap(), Some(0x1e00005));
+        let desktop = property(AtomEnum::WINDOW, 32, &[0; 4]);
+        assert_eq!(active_window(1, &desktop).unwrap(), None);
+        // the property is missing without an EWMH window manager
+        let missing = property(AtomEnum::NONE, 0, &[]);
+        assert_eq!(active_window(1, &missing).unwrap(), None);
+        let broken = property(AtomEnum::WINDOW, 8, b"x");
+        assert!(active_window(1, &broken).is_err());
+    }
+
+    #[test]
+    fn reports_capabilities_of_the_supported_extensions() {
+        let all =
+            recorder_capabilities(&[record::X11_EXTENSION_NAME, screensaver::X11_EXTENSION_NAME]);
+        assert_eq!(
+            all,
+            Capabilities {
+                titles: true,
+                input: true,
+                idle: true
+            }
+        );
+        // without RECORD, the active window is sampled
+        let polled = recorder_capabilities(&[screensaver::X11_EXTENSION_NAME]);
+        assert!(!polled.input && polled.idle);
+        let bare = recorder_capabilities(&[]);
+        assert!(bare.titles && !bare.input && !bare.idle);
+    }
+
+    #[test]
+    fn reconnects_after_losing_the_connection() {
+        let lost = || Error::from(ConnectionError::UnknownError);
+        let mut sessions = 0;
+        let mut runs = vec![Ok(()), Err(lost())];
+        let mut markers = Vec::new();
+        let connect = || {
+            sessions += 1;
+            Ok(sessions)
+        };
+        let run = |_| runs.pop().unwrap();
+        let result = reconnecting(&Control::default(), connect, run, |connected| {
+            markers.push(connected)
+        });
+        assert!(result.is_ok());
+        assert_eq!(sessions, 2);
+        assert_eq!(markers, [false, true]);
+
+        // failing to connect at all is not retried
+        let refused = reconnecting(
+            &Control::default(),
+            || Err::<(), _>(lost()),
+            |_| Ok(()),
+            |_| {},
+        );
+        assert!(refused.is_err_and(|err| err.is_disconnect()));
+    }
+
+    #[test]
+    #[ignore = "needs an X server, like Xvfb"]
+    fn keeps_watching_the_root_after_looking_it_up() {
+        let (conn, screen) = x11rb::connect(None).unwrap();
+        let root = conn.setup().roots[screen].root;
+        let select = ChangeWindowAttributesAux::new().event_mask(event_mask(true));
+        conn.change_window_attributes(root, &select)
+            .unwrap()
+            .check()
+            .unwrap();
+        let mut cache = WindowCache::new();
+        let mut windows = Windows {
+            conn: &conn,
+            atoms: Atoms::new(&conn).unwrap().reply().unwrap(),
+            root,
+            cache: &mut cache,
+            fallbacks: Fallbacks::default(),
+        };
+        // events on the root, like key presses with no window focused, are reported with its info
+        windows.info(root).unwrap();
+
+        let window = conn.generate_id().unwrap();
+        conn.create_window(
+            x11rb::COPY_DEPTH_FROM_PARENT,
+            window,
+            root,
+            0,
+            0,
+            1,
+            1,
+            0,
+            xproto::WindowClass::INPUT_OUTPUT,
+            x11rb::COPY_FROM_PARENT,
+            &xproto::CreateWindowAux::new(),
+        )
+        .unwrap()
+        .check()
+        .unwrap();
+        let mut events = std::iter::from_fn(|| conn.poll_for_event().unwrap());
+        assert!(events.any(|event| matches!(
+            event,
+            x11rb::protocol::Event::CreateNotify(event) if event.window == window
+        )));
+    }
+
+    #[test]
+    #[ignore = "needs an X server with RECORD, like Xvfb"]
+    fn stops_before_the_context_is_enabled() {
+        let session = Session::connect().unwrap();
+        let control = Control::default();
+        control.set_session(Some((Arc::clone(&session.ctrl_conn), session.context)));
+        // the context is not enabled yet, so it cannot be disabled
+        control.request(State::Stopped).unwrap();
+
+        let (sender, finished) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let result = session.run(&|_: &event::Event| {}, &control);
+            sender.send(result.is_ok()).unwrap();
+        });
+        // no client needs to produce recorded data for the recording to end
+        let finished = finished.recv_timeout(Duration::from_secs(5));
+        assert_eq!(finished, Ok(true));
+    }
+
+    #[test]
+    fn counts_fallbacks_only() {
+        let mut fallbacks = Fallbacks::default();
+        for source in [
+            AppSource::Class,
+            AppSource::Parent,
+            AppSource::Parent,
+            AppSource::Title,
+            AppSource::Unknown,
+        ] {
+            fallbacks.count(source);
+        }
+        assert_eq!(fallbacks.total(), 4);
+        assert_eq!(
+            fallbacks.to_string(),
+            "2 by parent window, 0 by process, 1 by title, 1 unknown"
+        );
+    }
+}
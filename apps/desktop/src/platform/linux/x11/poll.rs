@@ -0,0 +1,385 @@
+//! Follows the active window by sampling `_NET_ACTIVE_WINDOW` at an interval,
+//! for X servers without the RECORD extension.
+//!
+//! Focus changes are reported when they are sampled, so they are late by up to
+//! the interval, and windows focused for a shorter time can be missed entirely.
+//! No input is recorded. Like the recording, sampling reconnects after losing
+//! the connection to the X server.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use log::warn;
+
+use x11rb::connection::Connection;
+use x11rb::protocol::{screensaver, xproto};
+use x11rb::rust_connection::RustConnection;
+
+use crate::event;
+use crate::platform::{self, Capabilities, Recorder, UNKNOWN_APP};
+
+use super::cache::WindowInfo;
+use super::recording::{Control, Recording, State};
+use super::{
+    Atoms, Error, fullscreen_change, get_active_window, get_window_info, get_workspace, idle,
+    reconnecting, report_connection, report_workspace, share, supported,
+};
+
+/// How often the active window is sampled, unless configured otherwise.
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Follows the active window by sampling it.
+pub struct PollRecorder {
+    settings: platform::Settings,
+    recording: Option<Recording>,
+    /// the watcher of idle periods, running along the sampling
+    idle: Option<Recording>,
+}
+
+impl PollRecorder {
+    /// Creates a recorder sampling the active window every poll interval.
+    pub fn new(settings: &platform::Settings) -> Self {
+        PollRecorder {
+            settings: settings.clone(),
+            recording: None,
+            idle: None,
+        }
+    }
+}
+
+impl Default for PollRecorder {
+    fn default() -> Self {
+        PollRecorder::new(&platform::Settings::default())
+    }
+}
+
+impl Recorder for PollRecorder {
+    fn capabilities(&self) -> Capabilities {
+        let supported = supported(&[screensaver::X11_EXTENSION_NAME]);
+        Capabilities {
+            titles: true,
+            input: false,
+            idle: !supported.is_empty(),
+        }
+    }
+
+    fn start(&mut self, callback: platform::Callback) -> Result<(), platform::Error> {
+        if self.recording.is_some() {
+            return Err("the polling recorder is already started".into());
+        }
+        let (callback, idle) = share(callback);
+        self.recording = Some(poll(callback, self.settings.poll_interval));
+        self.idle = Some(idle::watch(idle, self.settings.idle_threshold));
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<(), platform::Error> {
+        // the idle watcher is stopped once the recording finished
+        if let Some(recording) = &self.recording {
+            recording.stop()?;
+        }
+        self.wait()
+    }
+
+    fn wait(&mut self) -> Result<(), platform::Error> {
+        let result = self.recording.take().map_or(Ok(()), Recording::join);
+        if let Some(idle) = self.idle.take() {
+            idle::finish(idle);
+        }
+        Ok(result?)
+    }
+}
+
+/// Starts sampling the active window every `interval` on a worker thread,
+/// returning a handle to pause, resume and stop it.
+pub fn poll<C>(callback: C, interval: Duration) -> Recording
+where
+    C: Fn(&event::Event) + Send + Sync + 'static,
+{
+    let control = Arc::new(Control::default());
+    let worker = {
+        let control = Arc::clone(&control);
+        std::thread::spawn(move || run(&callback, &control, interval))
+    };
+    Recording::new(control, worker)
+}
+
+/// Samples the active window until stopped or an unrecoverable error occurs,
+/// reconnecting after the connection to the X server is lost.
+pub(super) fn run(
+    callback: &impl Fn(&event::Event),
+    control: &Control,
+    interval: Duration,
+) -> Result<(), Error> {
+    let follow = |(conn, root, atoms)| follow(&conn, &atoms, root, callback, control, interval);
+    let connected = |connected| report_connection(callback, connected);
+    reconnecting(control, connect, follow, connected)
+}
+
+/// Connects to the X server, returning the root window to sample.
+fn connect() -> Result<(RustConnection, xproto::Window, Atoms), Error> {
+    let (conn, screen) = x11rb::connect(None)?;
+    let root = conn.setup().roots[screen].root;
+    let atoms = Atoms::new(&conn)?.reply()?;
+    Ok((conn, root, atoms))
+}
+
+/// Samples the active window until stopped or the connection fails.
+fn follow(
+    conn: &RustConnection,
+    atoms: &Atoms,
+    root: xproto::Window,
+    callback: &impl Fn(&event::Event),
+    control: &Control,
+    interval: Duration,
+) -> Result<(), Error> {
+    let mut focus = Focus::default();
+    loop {
+        if control.wait_while_paused() == State::Stopped {
+            return Ok(());
+        }
+        match sample(conn, atoms, root) {
+            Ok((active, workspace)) => focus.update(active, workspace, callback),
+            Err(err) if !err.is_fatal() => warn!("Skipping sample: {err}"),
+            Err(err) => return Err(err),
+        }
+        // errors of lookups on windows that are gone end up as events
+        while conn.poll_for_event()?.is_some() {}
+
+        if control.sleep(interval) {
+            return Ok(());
+        }
+    }
+}
+
+/// Looks up the active window and its metadata, and the workspace.
+fn sample(
+    conn: &impl Connection,
+    atoms: &Atoms,
+    root: xproto::Window,
+) -> Result<(Active, Option<String>), Error> {
+    let workspace = get_workspace(conn, atoms, root)?;
+    let Some(window) = get_active_window(conn, atoms, root)? else {
+        return Ok((None, workspace));
+    };
+    Ok((
+        Some((window, get_window_info(conn, atoms, window)?)),
+        workspace,
+    ))
+}
+
+/// The active window and its metadata, if any.
+type Active = Option<(xproto::Window, WindowInfo)>;
+
+/// The last sampled active window and workspace, to report the changes between samples.
+#[derive(Default)]
+struct Focus {
+    window: Active,
+    workspace: Option<String>,
+}
+
+impl Focus {
+    fn update(
+        &mut self,
+        active: Active,
+        workspace: Option<String>,
+        callback: &impl Fn(&event::Event),
+    ) {
+        let same = match (&self.window, &active) {
+            (Some((focused, _)), Some((window, _))) => focused == window,
+            _ => false,
+        };
+        if let Some((_, previous)) = self.window.as_ref().filter(|_| !same) {
+            if let Some(data) = fullscreen_change(previous.fullscreen, false) {
+                report(previous, &self.workspace, data, callback);
+            }
+            report(
+                previous,
+                &self.workspace,
+                event::EventData::FocusOut,
+                callback,
+            );
+        }
+        if self.workspace != workspace {
+            let now = (chrono::Utc::now(), Instant::now());
+            report_workspace(callback, workspace.clone(), now);
+        }
+        match (&self.window, &active) {
+            (Some((_, previous)), Some((_, info))) if same => {
+                if previous.title != info.title {
+                    report(info, &workspace, event::EventData::TitleChange, callback);
+                }
+                if let Some(data) = fullscreen_change(previous.fullscreen, info.fullscreen) {
+                    report(info, &workspace, data, callback);
+                }
+            }
+            (_, Some((_, info))) => {
+                report(info, &workspace, event::EventData::FocusIn, callback);
+                if let Some(data) = fullscreen_change(false, info.fullscreen) {
+                    report(info, &workspace, data, callback);
+                }
+            }
+            _ => {}
+        }
+        self.window = active;
+        self.workspace = workspace;
+    }
+}
+
+/// Reports an event for a window.
+fn report(
+    info: &WindowInfo,
+    workspace: &Option<String>,
+    data: event::EventData,
+    callback: &impl Fn(&event::Event),
+) {
+    callback(&event::Event {
+        timestamp: chrono::Utc::now(),
+        instant: Instant::now(),
+        app: info.app.as_deref().unwrap_or(UNKNOWN_APP).to_string(),
+        title: info.title.clone(),
+        workspace: workspace.clone(),
+        device: None,
+        process: info.process.clone(),
+        output: None,
+        data,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::super::cache::AppSource;
+    use super::*;
+
+    fn window(
+        id: xproto::Window,
+        class: &str,
+        title: &str,
+    ) -> Option<(xproto::Window, WindowInfo)> {
+        let info = WindowInfo {
+            app: Some(class.to_string()),
+            source: AppSource::Class,
+            title: Some(title.to_string()),
+            process: None,
+            fullscreen: false,
+        };
+        Some((id, info))
+    }
+
+    #[test]
+    fn reports_changes_between_samples() {
+        let reported = RefCell::new(Vec::new());
+        let callback = |event: &event::Event| {
+            let title = event.title.clone().unwrap_or_default();
+            reported
+                .borrow_mut()
+                .push((event.data, event.app.clone(), title));
+        };
+
+        let mut focus = Focus::default();
+        focus.update(window(1, "firefox", "Start"), None, &callback);
+        focus.update(window(1, "firefox", "Start"), None, &callback);
+        focus.update(window(1, "firefox", "Docs"), None, &callback);
+        focus.update(window(2, "kitty", "~"), None, &callback);
+        focus.update(None, None, &callback);
+
+        use event::EventData::{FocusIn, FocusOut, TitleChange};
+        let expected = [
+            (FocusIn, "firefox", "Start"),
+            (TitleChange, "firefox", "Docs"),
+            (FocusOut, "firefox", "Docs"),
+            (FocusIn, "kitty", "~"),
+            (FocusOut, "kitty", "~"),
+        ];
+        let expected: Vec<_> = expected
+            .iter()
+            .map(|&(data, app, title)| (data, app.to_string(), title.to_string()))
+            .collect();
+        assert_eq!(reported.into_inner(), expected);
+    }
+
+    #[test]
+    fn reports_fullscreen_windows() {
+        let reported = RefCell::new(Vec::new());
+        let callback = |event: &event::Event| {
+            reported.borrow_mut().push((event.data, event.app.clone()));
+        };
+
+        let fullscreen = |id, class| {
+            let (id, info) = window(id, class, "")?;
+            Some((
+                id,
+                WindowInfo {
+                    fullscreen: true,
+                    ..info
+                },
+            ))
+        };
+        let mut focus = Focus::default();
+        focus.update(window(1, "mpv", ""), None, &callback);
+        focus.update(fullscreen(1, "mpv"), None, &callback);
+        focus.update(fullscreen(1, "mpv"), None, &callback);
+        focus.update(window(2, "kitty", ""), None, &callback);
+        focus.update(fullscreen(1, "mpv"), None, &callback);
+        focus.update(window(1, "mpv", ""), None, &callback);
+
+        use event::EventData::{FocusIn, FocusOut, FullscreenEnd, FullscreenStart};
+        let expected = [
+            (FocusIn, "mpv"),
+            (FullscreenStart, "mpv"),
+            (FullscreenEnd, "mpv"),
+            (FocusOut, "mpv"),
+            (FocusIn, "kitty"),
+            (FocusOut, "kitty"),
+            (FocusIn, "mpv"),
+            (FullscreenStart, "mpv"),
+            (FullscreenEnd, "mpv"),
+        ];
+        let expected: Vec<_> = expected
+            .iter()
+            .map(|&(data, app)| (data, app.to_string()))
+            .collect();
+        assert_eq!(reported.into_inner(), expected);
+    }
+
+    #[test]
+    fn reports_workspace_switches() {
+        let reported = RefCell::new(Vec::new());
+        let callback = |event: &event::Event| {
+            let workspace = event.workspace.clone().unwrap_or_default();
+            reported
+                .borrow_mut()
+                .push((event.data, event.app.clone(), workspace));
+        };
+
+        let workspace = |name: &str| Some(name.to_string());
+        let mut focus = Focus::default();
+        focus.update(window(1, "slack", "general"), workspace("comms"), &callback);
+        focus.update(window(2, "code", "main.rs"), workspace("code"), &callback);
+        // a sticky window stays focused
+        focus.update(
+            window(2, "code", "main.rs"),
+            workspace("research"),
+            &callback,
+        );
+        focus.update(None, workspace("research"), &callback);
+
+        use event::EventData::{FocusIn, FocusOut, WorkspaceChange};
+        let expected = [
+            (WorkspaceChange, "", "comms"),
+            (FocusIn, "slack", "comms"),
+            (FocusOut, "slack", "comms"),
+            (WorkspaceChange, "", "code"),
+            (FocusIn, "code", "code"),
+            (WorkspaceChange, "", "research"),
+            (FocusOut, "code", "research"),
+        ];
+        let expected: Vec<_> = expected
+            .iter()
+            .map(|&(data, app, workspace)| (data, app.to_string(), workspace.to_string()))
+            .collect();
+        assert_eq!(reported.into_inner(), expected);
+    }
+}
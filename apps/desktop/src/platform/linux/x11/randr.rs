@@ -0,0 +1,250 @@
+//! Finds the monitor events happen on through the RandR extension.
+//!
+//! Each active output (a connector like "HDMI-1" with a CRTC driving it) shows
+//! a rectangle of the root window. Outputs mirroring each other show the same
+//! rectangle, events there are attributed to the output that sorts first.
+//! Changes of the outputs are reported with the new layout, described like
+//! `xrandr` does: "eDP-1 1920x1080+0+0 primary, HDMI-1 2560x1440+1920+0".
+
+use std::fmt;
+
+use x11rb::connection::Connection;
+use x11rb::protocol::Event;
+use x11rb::protocol::randr::{self, ConnectionExt as _};
+use x11rb::protocol::xproto::{self, ConnectionExt as _};
+
+use super::{Error, property_error};
+
+/// An output showing a part of the root window.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Output {
+    pub name: String,
+    pub x: i16,
+    pub y: i16,
+    pub width: u16,
+    pub height: u16,
+    /// whether this is the primary output, which desktops put their panels on
+    pub primary: bool,
+}
+
+impl Output {
+    /// Returns the area of the intersection with a rectangle.
+    fn overlap(&self, x: i16, y: i16, width: u16, height: u16) -> u64 {
+        let span = |start: i16, len: u16, other: i16, other_len: u16| {
+            let end =
+                (i32::from(start) + i32::from(len)).min(i32::from(other) + i32::from(other_len));
+            (end - i32::from(start.max(other))).max(0) as u64
+        };
+        span(self.x, self.width, x, width) * span(self.y, self.height, y, height)
+    }
+}
+
+/// The active outputs of a screen, sorted by name.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Outputs(Vec<Output>);
+
+impl Outputs {
+    pub fn new(mut outputs: Vec<Output>) -> Self {
+        outputs.sort_by(|a, b| a.name.cmp(&b.name));
+        Outputs(outputs)
+    }
+
+    /// Queries the active outputs of the screen of the root window, and selects
+    /// their changes to be notified about. Without RandR 1.3 there are none.
+    pub fn watch(conn: &impl Connection, root: xproto::Window) -> Result<Self, Error> {
+        if conn
+            .extension_information(randr::X11_EXTENSION_NAME)?
+            .is_none()
+        {
+            return Ok(Outputs::default());
+        }
+        let version = conn.randr_query_version(1, 3)?.reply()?;
+        if (version.major_version, version.minor_version) < (1, 3) {
+            return Ok(Outputs::default());
+        }
+
+        let changes = randr::NotifyMask::SCREEN_CHANGE
+            | randr::NotifyMask::CRTC_CHANGE
+            | randr::NotifyMask::OUTPUT_CHANGE;
+        conn.randr_select_input(root, changes)?;
+        Outputs::query(conn, root)
+    }
+
+    /// Queries the active outputs of the screen of the root window.
+    pub fn query(conn: &impl Connection, root: xproto::Window) -> Result<Self, Error> {
+        let primary = conn.randr_get_output_primary(root)?;
+        let resources = conn.randr_get_screen_resources_current(root)?.reply()?;
+        let primary = primary.reply()?.output;
+        let timestamp = resources.config_timestamp;
+        let infos = resources
+            .outputs
+            .iter()
+            .map(|&output| Ok((output, conn.randr_get_output_info(output, timestamp)?)))
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let mut outputs = Vec::new();
+        for (output, info) in infos {
+            let info = info.reply()?;
+            // disconnected and disabled outputs have no CRTC
+            if info.crtc == x11rb::NONE {
+                continue;
+            }
+            let crtc = conn.randr_get_crtc_info(info.crtc, timestamp)?.reply()?;
+            outputs.push(Output {
+                name: String::from_utf8_lossy(&info.name).into_owned(),
+                x: crtc.x,
+                y: crtc.y,
+                width: crtc.width,
+                height: crtc.height,
+                primary: output == primary,
+            });
+        }
+        Ok(Outputs::new(outputs))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns the output showing a point of the root window.
+    pub fn at(&self, x: i16, y: i16) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|output| output.overlap(x, y, 1, 1) > 0)
+            .map(|output| output.name.as_str())
+    }
+
+    /// Returns the output showing most of a window, `None` if it is not shown at all.
+    pub fn of_window(
+        &self,
+        conn: &impl Connection,
+        root: xproto::Window,
+        window: xproto::Window,
+    ) -> Result<Option<&str>, Error> {
+        if self.0.is_empty() {
+            return Ok(None);
+        }
+        let geometry = conn.get_geometry(window)?;
+        let position = conn.translate_coordinates(window, root, 0, 0)?;
+        let geometry = geometry
+            .reply()
+            .map_err(|err| property_error(err, window, "geometry"))?;
+        let position = position
+            .reply()
+            .map_err(|err| property_error(err, window, "geometry"))?;
+        Ok(self.covering(
+            position.dst_x,
+            position.dst_y,
+            geometry.width,
+            geometry.height,
+        ))
+    }
+
+    /// Returns the output showing most of a rectangle of the root window.
+    fn covering(&self, x: i16, y: i16, width: u16, height: u16) -> Option<&str> {
+        // the first of the outputs with the largest overlap
+        let (output, overlap) = self
+            .0
+            .iter()
+            .map(|output| (output, output.overlap(x, y, width, height)))
+            .rev()
+            .max_by_key(|&(_, overlap)| overlap)?;
+        (overlap > 0).then_some(output.name.as_str())
+    }
+}
+
+impl fmt::Display for Outputs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, output) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            let Output {
+                name,
+                x,
+                y,
+                width,
+                height,
+                primary,
+            } = output;
+            write!(f, "{name} {width}x{height}{x:+}{y:+}")?;
+            if *primary {
+                write!(f, " primary")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether the event notifies about a change of the outputs.
+pub fn is_change(event: &Event) -> bool {
+    matches!(
+        event,
+        Event::RandrScreenChangeNotify(_) | Event::RandrNotify(_)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(name: &str, x: i16, y: i16, width: u16, height: u16) -> Output {
+        Output {
+            name: name.to_string(),
+            x,
+            y,
+            width,
+            height,
+            primary: false,
+        }
+    }
+
+    /// A laptop screen with a larger monitor on its right.
+    fn outputs() -> Outputs {
+        Outputs::new(vec![
+            output("HDMI-1", 1920, 0, 2560, 1440),
+            output("eDP-1", 0, 0, 1920, 1080),
+        ])
+    }
+
+    #[test]
+    fn finds_the_output_of_a_point() {
+        let outputs = outputs();
+        assert_eq!(outputs.at(0, 0), Some("eDP-1"));
+        assert_eq!(outputs.at(1919, 1079), Some("eDP-1"));
+        assert_eq!(outputs.at(1920, 1079), Some("HDMI-1"));
+        // below the laptop screen nothing is shown
+        assert_eq!(outputs.at(100, 1200), None);
+    }
+
+    #[test]
+    fn finds_the_output_showing_most_of_a_window() {
+        let outputs = outputs();
+        assert_eq!(outputs.covering(1800, 100, 800, 600), Some("HDMI-1"));
+        assert_eq!(outputs.covering(-100, -100, 1000, 500), Some("eDP-1"));
+        assert_eq!(outputs.covering(0, 1100, 500, 300), None);
+
+        let mirrored = Outputs::new(vec![
+            output("HDMI-1", 0, 0, 1920, 1080),
+            output("DP-1", 0, 0, 1920, 1080),
+        ]);
+        assert_eq!(mirrored.covering(0, 0, 800, 600), Some("DP-1"));
+        assert_eq!(mirrored.at(0, 0), Some("DP-1"));
+    }
+
+    #[test]
+    fn describes_the_layout() {
+        assert_eq!(
+            outputs().to_string(),
+            "HDMI-1 2560x1440+1920+0, eDP-1 1920x1080+0+0"
+        );
+        let primary = Output {
+            primary: true,
+            ..output("eDP-1", 0, 0, 1920, 1080)
+        };
+        assert_eq!(
+            Outputs::new(vec![primary]).to_string(),
+            "eDP-1 1920x1080+0+0 primary"
+        );
+    }
+}
@@ -0,0 +1,173 @@
+//! Control over a recording running on a worker thread.
+//!
+//! The worker blocks on the data connection while the context is enabled, so
+//! pausing and stopping disable the context through the control connection,
+//! which makes the server end the stream of recorded data.
+
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use x11rb::connection::Connection;
+use x11rb::protocol::record::{self, ConnectionExt as _};
+use x11rb::rust_connection::RustConnection;
+
+use super::Error;
+
+/// The state of a recording, as requested through its [`Recording`] handle.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum State {
+    #[default]
+    Recording,
+    Paused,
+    /// stopping is final, the worker frees the context and finishes
+    Stopped,
+}
+
+/// A handle to a recording running in the background, see [`super::record`].
+pub struct Recording {
+    control: Arc<Control>,
+    worker: JoinHandle<Result<(), Error>>,
+}
+
+impl Recording {
+    pub(super) fn new(control: Arc<Control>, worker: JoinHandle<Result<(), Error>>) -> Self {
+        Self { control, worker }
+    }
+
+    /// Returns the requested state of the recording.
+    pub fn state(&self) -> State {
+        *self.control.state()
+    }
+
+    /// Pauses recording, events that happen while paused are not reported.
+    pub fn pause(&self) -> Result<(), Error> {
+        self.control.request(State::Paused)
+    }
+
+    /// Resumes a paused recording.
+    pub fn resume(&self) -> Result<(), Error> {
+        self.control.request(State::Recording)
+    }
+
+    /// Stops recording. Use [`Recording::join`] to wait for the worker to finish.
+    pub fn stop(&self) -> Result<(), Error> {
+        self.control.request(State::Stopped)
+    }
+
+    /// Whether the worker has finished, either because it was stopped or it failed.
+    pub fn is_finished(&self) -> bool {
+        self.worker.is_finished()
+    }
+
+    /// Waits for the worker to finish and returns its final status.
+    pub fn join(self) -> Result<(), Error> {
+        match self.worker.join() {
+            Ok(result) => result,
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    }
+}
+
+/// The state shared between a [`Recording`] and its worker.
+#[derive(Default)]
+pub(super) struct Control {
+    state: Mutex<State>,
+    changed: Condvar,
+    /// the control connection and context of the connected session
+    session: Mutex<Option<(Arc<RustConnection>, record::Context)>>,
+}
+
+impl Control {
+    pub(super) fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub(super) fn request(&self, state: State) -> Result<(), Error> {
+        {
+            let mut current = self.state();
+            if *current == State::Stopped {
+                return Ok(());
+            }
+            *current = state;
+        }
+        self.changed.notify_all();
+
+        if state == State::Recording {
+            Ok(())
+        } else {
+            self.disable()
+        }
+    }
+
+    /// Disables the context of the connected session, if any, which ends the
+    /// stream of recorded data.
+    pub(super) fn disable(&self) -> Result<(), Error> {
+        let session = self.session.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some((conn, context)) = &*session {
+            // disabling a context that is not enabled yet fails harmlessly, the
+            // error ends up with the events discarded by the worker, which
+            // disables the context again once the server enabled it
+            conn.record_disable_context(*context)?;
+            conn.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Publishes the session of the worker, or that it has none.
+    pub(super) fn set_session(&self, session: Option<(Arc<RustConnection>, record::Context)>) {
+        *self.session.lock().unwrap_or_else(PoisonError::into_inner) = session;
+    }
+
+    /// Blocks while the recording is paused, returning the new state.
+    pub(super) fn wait_while_paused(&self) -> State {
+        let state = self
+            .changed
+            .wait_while(self.state(), |state| *state == State::Paused)
+            .unwrap_or_else(PoisonError::into_inner);
+        *state
+    }
+
+    /// Sleeps for `timeout`, unless the recording is stopped earlier.
+    /// Returns whether it was stopped.
+    pub(super) fn sleep(&self, timeout: Duration) -> bool {
+        let (state, _) = self
+            .changed
+            .wait_timeout_while(self.state(), timeout, |state| *state != State::Stopped)
+            .unwrap_or_else(PoisonError::into_inner);
+        *state == State::Stopped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+
+    #[test]
+    fn stopping_is_final() {
+        let control = Control::default();
+        control.request(State::Paused).unwrap();
+        assert_eq!(*control.state(), State::Paused);
+        control.request(State::Stopped).unwrap();
+        control.request(State::Recording).unwrap();
+        assert_eq!(*control.state(), State::Stopped);
+    }
+
+    #[test]
+    fn stopping_wakes_the_worker() {
+        let control = Arc::new(Control::default());
+        control.request(State::Paused).unwrap();
+        let worker = {
+            let control = Arc::clone(&control);
+            std::thread::spawn(move || control.wait_while_paused())
+        };
+        control.request(State::Stopped).unwrap();
+        assert_eq!(worker.join().unwrap(), State::Stopped);
+
+        let start = Instant::now();
+        assert!(control.sleep(Duration::from_secs(60)));
+        assert!(start.elapsed() < Duration::from_secs(60));
+    }
+}
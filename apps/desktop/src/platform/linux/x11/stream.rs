@@ -0,0 +1,621 @@
+//! Decoding of the protocol data intercepted by the RECORD extension.
+//!
+//! A `FromServer` reply of a recording context carries the wire bytes of any
+//! number of replies, errors and events, back to back. This module splits such
+//! a payload into its elements without touching the connection, so it can be
+//! tested (and fuzzed) in isolation.
+//!
+//! The intercepted data is in the byte order of the recorded client, which
+//! differs from ours for clients on a machine of the other endianness. The
+//! fields of the errors and events decoded here are swapped accordingly,
+//! replies and other events are passed on as they were recorded.
+
+use x11rb::errors::ParseError;
+use x11rb::protocol::xproto;
+use x11rb::x11_utils::TryParse;
+
+/// Every error and event, and the fixed part of every reply is 32 bytes long
+const ELEMENT_SIZE: usize = 32;
+/// Marks events that were generated by a `SendEvent` request
+const SEND_EVENT_MASK: u8 = 0x80;
+
+const ERROR: u8 = 0;
+const REPLY: u8 = 1;
+
+/// An X error, as intercepted from the server.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Error {
+    pub error_code: u8,
+    pub sequence: u16,
+    pub bad_value: u32,
+    pub minor_opcode: u16,
+    pub major_opcode: u8,
+}
+
+/// A single protocol element of the intercepted data.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Element<'a> {
+    /// a reply, undecoded since its format depends on the request
+    Reply(&'a [u8]),
+    Error(Error),
+    KeyPress(xproto::KeyPressEvent),
+    KeyRelease(xproto::KeyReleaseEvent),
+    ButtonPress(xproto::ButtonPressEvent),
+    ButtonRelease(xproto::ButtonReleaseEvent),
+    MotionNotify(xproto::MotionNotifyEvent),
+    EnterNotify(xproto::EnterNotifyEvent),
+    LeaveNotify(xproto::LeaveNotifyEvent),
+    FocusIn(xproto::FocusInEvent),
+    FocusOut(xproto::FocusOutEvent),
+    CreateNotify(xproto::CreateNotifyEvent),
+    DestroyNotify(xproto::DestroyNotifyEvent),
+    UnmapNotify(xproto::UnmapNotifyEvent),
+    MapNotify(xproto::MapNotifyEvent),
+    PropertyNotify(xproto::PropertyNotifyEvent),
+    /// a generic (XGE) event, which carries its own length
+    Generic(&'a [u8]),
+    /// any other core or extension event
+    Other(&'a [u8]),
+}
+
+/// An iterator over the elements of an intercepted data payload.
+///
+/// Malformed input yields a single error, after which the iterator is exhausted.
+#[derive(Debug, Clone)]
+pub struct Stream<'a> {
+    data: &'a [u8],
+    swapped: bool,
+}
+
+impl<'a> Stream<'a> {
+    /// Decodes data of a client with our byte order.
+    pub fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            swapped: false,
+        }
+    }
+
+    /// Decodes data of a client with the other byte order.
+    pub fn swapped(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            swapped: true,
+        }
+    }
+}
+
+impl<'a> Iterator for Stream<'a> {
+    type Item = Result<Element<'a>, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.is_empty() {
+            return None;
+        }
+        match decode(self.data, self.swapped) {
+            Ok((element, remaining)) => {
+                self.data = remaining;
+                Some(Ok(element))
+            }
+            Err(err) => {
+                // the element boundaries are lost, there is no way to resynchronise
+                self.data = &[];
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+/// Decodes the element at the start of `data`, returning it and the remaining data.
+/// `swapped` tells whether the data is in the other byte order than ours.
+pub fn decode(data: &[u8], swapped: bool) -> Result<(Element<'_>, &[u8]), ParseError> {
+    let code = *data.first().ok_or(ParseError::InsufficientData)?;
+    let length = element_length(data, swapped)?;
+    if data.len() < length {
+        return Err(ParseError::InsufficientData);
+    }
+    let (raw, remaining) = data.split_at(length);
+
+    // the decoded elements are parsed from a copy in our byte order
+    let native;
+    let bytes = match swapped_fields(code) {
+        Some((u16s, u32s)) if swapped => {
+            native = swap_fields(raw, u16s, u32s);
+            &native[..]
+        }
+        _ => raw,
+    };
+
+    let element = match code {
+        ERROR => Element::Error(parse_error(bytes)?),
+        REPLY => Element::Reply(raw),
+        _ => match code & !SEND_EVENT_MASK {
+            xproto::KEY_PRESS_EVENT => Element::KeyPress(parse(bytes)?),
+            xproto::KEY_RELEASE_EVENT => Element::KeyRelease(parse(bytes)?),
+            xproto::BUTTON_PRESS_EVENT => Element::ButtonPress(parse(bytes)?),
+            xproto::BUTTON_RELEASE_EVENT => Element::ButtonRelease(parse(bytes)?),
+            xproto::MOTION_NOTIFY_EVENT => Element::MotionNotify(parse(bytes)?),
+            xproto::ENTER_NOTIFY_EVENT => Element::EnterNotify(parse(bytes)?),
+            xproto::LEAVE_NOTIFY_EVENT => Element::LeaveNotify(parse(bytes)?),
+            xproto::FOCUS_IN_EVENT => Element::FocusIn(parse(bytes)?),
+            xproto::FOCUS_OUT_EVENT => Element::FocusOut(parse(bytes)?),
+            xproto::CREATE_NOTIFY_EVENT => Element::CreateNotify(parse(bytes)?),
+            xproto::DESTROY_NOTIFY_EVENT => Element::DestroyNotify(parse(bytes)?),
+            xproto::UNMAP_NOTIFY_EVENT => Element::UnmapNotify(parse(bytes)?),
+            xproto::MAP_NOTIFY_EVENT => Element::MapNotify(parse(bytes)?),
+            xproto::PROPERTY_NOTIFY_EVENT => Element::PropertyNotify(parse(bytes)?),
+            xproto::GE_GENERIC_EVENT => Element::Generic(raw),
+            _ => Element::Other(raw),
+        },
+    };
+    Ok((element, remaining))
+}
+
+/// Returns the offsets of the 16-bit and 32-bit fields of the errors and events
+/// we decode, which have to be swapped for clients of the other byte order.
+fn swapped_fields(code: u8) -> Option<(&'static [usize], &'static [usize])> {
+    const SEQUENCE: &[usize] = &[2];
+    let fields: (&[usize], &[usize]) = match code {
+        // error code, sequence, bad value, minor opcode, major opcode
+        ERROR => (&[2, 8], &[4]),
+        REPLY => return None,
+        _ => match code & !SEND_EVENT_MASK {
+            // detail, sequence, time, root, event, child,
+            // root x/y, event x/y, state, and two 8-bit fields
+            xproto::KEY_PRESS_EVENT
+            | xproto::KEY_RELEASE_EVENT
+            | xproto::BUTTON_PRESS_EVENT
+            | xproto::BUTTON_RELEASE_EVENT
+            | xproto::MOTION_NOTIFY_EVENT
+            | xproto::ENTER_NOTIFY_EVENT
+            | xproto::LEAVE_NOTIFY_EVENT => (&[2, 20, 22, 24, 26, 28], &[4, 8, 12, 16]),
+            // detail, sequence, event, mode
+            xproto::FOCUS_IN_EVENT | xproto::FOCUS_OUT_EVENT => (SEQUENCE, &[4]),
+            // sequence, parent, window, x, y, width, height, border width
+            xproto::CREATE_NOTIFY_EVENT => (&[2, 12, 14, 16, 18, 20], &[4, 8]),
+            // sequence, event, window
+            xproto::DESTROY_NOTIFY_EVENT
+            | xproto::UNMAP_NOTIFY_EVENT
+            | xproto::MAP_NOTIFY_EVENT => (SEQUENCE, &[4, 8]),
+            // sequence, window, atom, time, state
+            xproto::PROPERTY_NOTIFY_EVENT => (SEQUENCE, &[4, 8, 12]),
+            _ => return None,
+        },
+    };
+    Some(fields)
+}
+
+fn swap_fields(raw: &[u8], u16s: &[usize], u32s: &[usize]) -> [u8; ELEMENT_SIZE] {
+    let mut bytes = [0; ELEMENT_SIZE];
+    bytes.copy_from_slice(&raw[..ELEMENT_SIZE]);
+    for &offset in u16s {
+        bytes[offset..offset + 2].reverse();
+    }
+    for &offset in u32s {
+        bytes[offset..offset + 4].reverse();
+    }
+    bytes
+}
+
+/// Computes the total length of the element at the start of `data`.
+fn element_length(data: &[u8], swapped: bool) -> Result<usize, ParseError> {
+    let code = data[0];
+    if code != REPLY && code & !SEND_EVENT_MASK != xproto::GE_GENERIC_EVENT {
+        return Ok(ELEMENT_SIZE);
+    }
+
+    // replies and generic events have their additional length in 4-byte units at offset 4
+    let (extra, _) = u32::try_parse(data.get(4..).ok_or(ParseError::InsufficientData)?)?;
+    let extra = if swapped { extra.swap_bytes() } else { extra };
+    usize::try_from(extra)
+        .ok()
+        .and_then(|extra| extra.checked_mul(4))
+        .and_then(|extra| extra.checked_add(ELEMENT_SIZE))
+        .ok_or(ParseError::ConversionFailed)
+}
+
+fn parse<T: TryParse>(bytes: &[u8]) -> Result<T, ParseError> {
+    T::try_parse(bytes).map(|(value, _)| value)
+}
+
+fn parse_error(bytes: &[u8]) -> Result<Error, ParseError> {
+    let (error_code, rest) = u8::try_parse(&bytes[1..])?;
+    let (sequence, rest) = u16::try_parse(rest)?;
+    let (bad_value, rest) = u32::try_parse(rest)?;
+    let (minor_opcode, rest) = u16::try_parse(rest)?;
+    let (major_opcode, _) = u8::try_parse(rest)?;
+    Ok(Error {
+        error_code,
+        sequence,
+        bad_value,
+        minor_opcode,
+        major_opcode,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// KeyPress of keycode 38 on window 0x3a00007, as delivered to its client
+    #[rustfmt::skip]
+    const KEY_PRESS: [u8; 32] = [
+        0x02, 0x26, 0x23, 0x01, // code, detail, sequence
+        0x3d, 0x2c, 0x1b, 0x0a, // time
+        0xe9, 0x01, 0x00, 0x00, // root
+        0x07, 0x00, 0xa0, 0x03, // event
+        0x00, 0x00, 0x00, 0x00, // child
+        0x2c, 0x03, 0x93, 0x01, // root x, root y
+        0x70, 0x00, 0x57, 0x00, // event x, event y
+        0x10, 0x00, 0x01, 0x00, // state, same screen
+    ];
+
+    /// MotionNotify on the root window
+    #[rustfmt::skip]
+    const MOTION_NOTIFY: [u8; 32] = [
+        0x06, 0x00, 0x24, 0x01,
+        0x55, 0x2c, 0x1b, 0x0a,
+        0xe9, 0x01, 0x00, 0x00,
+        0xe9, 0x01, 0x00, 0x00,
+        0x07, 0x00, 0xa0, 0x03,
+        0x30, 0x03, 0x90, 0x01,
+        0x30, 0x03, 0x90, 0x01,
+        0x00, 0x00, 0x01, 0x00,
+    ];
+
+    /// FocusIn on window 0x3a00007, sent with SendEvent
+    #[rustfmt::skip]
+    const FOCUS_IN_SENT: [u8; 32] = [
+        0x89, 0x03, 0x25, 0x01,
+        0x07, 0x00, 0xa0, 0x03,
+        0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00,
+    ];
+
+    /// PropertyNotify of `WM_NAME` (atom 39) on window 0x3a00007
+    #[rustfmt::skip]
+    const PROPERTY_NOTIFY: [u8; 32] = [
+        0x1c, 0x00, 0x26, 0x01,
+        0x07, 0x00, 0xa0, 0x03,
+        0x27, 0x00, 0x00, 0x00,
+        0x80, 0x2c, 0x1b, 0x0a,
+        0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00,
+    ];
+
+    /// CreateNotify of window 0x3a00007 at 10,20 with 800x600 on the root window
+    #[rustfmt::skip]
+    const CREATE_NOTIFY: [u8; 32] = [
+        0x10, 0x00, 0x2a, 0x01,
+        0xe9, 0x01, 0x00, 0x00,
+        0x07, 0x00, 0xa0, 0x03,
+        0x0a, 0x00, 0x14, 0x00,
+        0x20, 0x03, 0x58, 0x02,
+        0x01, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00,
+    ];
+
+    /// MapNotify of the override-redirect window 0x3a00009, seen on the root window
+    #[rustfmt::skip]
+    const MAP_NOTIFY: [u8; 32] = [
+        0x13, 0x00, 0x2b, 0x01,
+        0xe9, 0x01, 0x00, 0x00,
+        0x09, 0x00, 0xa0, 0x03,
+        0x01, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00,
+    ];
+
+    /// BadWindow error for a GetProperty request
+    #[rustfmt::skip]
+    const BAD_WINDOW: [u8; 32] = [
+        0x00, 0x03, 0x27, 0x01,
+        0x07, 0x00, 0xa0, 0x03,
+        0x00, 0x00, 0x14, 0x00,
+        0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00,
+    ];
+
+    /// A reply with 2 words of additional data
+    #[rustfmt::skip]
+    const REPLY_WITH_DATA: [u8; 40] = [
+        0x01, 0x08, 0x28, 0x01,
+        0x02, 0x00, 0x00, 0x00,
+        0x1f, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00,
+        0x07, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00,
+        b'f', b'i', b'r', b'e',
+        b'f', b'o', b'x', 0x00,
+    ];
+
+    /// A generic event of extension 131 with 1 word of additional data
+    #[rustfmt::skip]
+    const GENERIC: [u8; 36] = [
+        0x23, 0x83, 0x29, 0x01,
+        0x01, 0x00, 0x00, 0x00,
+        0x11, 0x00, 0x02, 0x00,
+        0x3d, 0x2c, 0x1b, 0x0a,
+        0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00,
+        0xaa, 0xbb, 0xcc, 0xdd,
+    ];
+
+    fn payload(elements: &[&[u8]]) -> Vec<u8> {
+        elements.concat()
+    }
+
+    fn decode_all(data: &[u8]) -> Result<Vec<Element<'_>>, ParseError> {
+        Stream::new(data).collect()
+    }
+
+    #[test]
+    fn decodes_single_event() {
+        let elements = decode_all(&KEY_PRESS).unwrap();
+        assert_eq!(elements.len(), 1);
+        let Element::KeyPress(event) = &elements[0] else {
+            panic!("expected a key press, got {:?}", elements[0]);
+        };
+        assert_eq!(event.detail, 38);
+        assert_eq!(event.time, 0x0a1b2c3d);
+        assert_eq!(event.root, 0x1e9);
+        assert_eq!(event.event, 0x3a00007);
+        assert_eq!((event.root_x, event.root_y), (812, 403));
+    }
+
+    #[test]
+    fn decodes_every_event_of_a_batched_reply() {
+        let data = payload(&[&KEY_PRESS, &MOTION_NOTIFY, &PROPERTY_NOTIFY, &KEY_PRESS]);
+        let elements = decode_all(&data).unwrap();
+        assert!(matches!(
+            elements.as_slice(),
+            [
+                Element::KeyPress(_),
+                Element::MotionNotify(_),
+                Element::PropertyNotify(_),
+                Element::KeyPress(_),
+            ]
+        ));
+        let Element::MotionNotify(motion) = &elements[1] else {
+            unreachable!()
+        };
+        assert_eq!((motion.root_x, motion.root_y), (816, 400));
+        let Element::PropertyNotify(property) = &elements[2] else {
+            unreachable!()
+        };
+        assert_eq!(property.atom, 39);
+    }
+
+    #[test]
+    fn decodes_releases_and_crossings() {
+        let mut key_release = KEY_PRESS;
+        key_release[0] = xproto::KEY_RELEASE_EVENT;
+        let mut button_release = KEY_PRESS;
+        button_release[0] = xproto::BUTTON_RELEASE_EVENT;
+        button_release[1] = 1;
+        let mut enter = MOTION_NOTIFY;
+        enter[0] = xproto::ENTER_NOTIFY_EVENT;
+        let mut leave = MOTION_NOTIFY;
+        leave[0] = xproto::LEAVE_NOTIFY_EVENT;
+
+        let data = payload(&[&key_release, &button_release, &enter, &leave]);
+        let elements = decode_all(&data).unwrap();
+        let [
+            Element::KeyRelease(key),
+            Element::ButtonRelease(button),
+            Element::EnterNotify(_),
+            Element::LeaveNotify(_),
+        ] = elements.as_slice()
+        else {
+            panic!("unexpected elements {elements:?}");
+        };
+        assert_eq!(key.detail, 38);
+        assert_eq!(button.detail, 1);
+    }
+
+    #[test]
+    fn decodes_sent_events() {
+        let elements = decode_all(&FOCUS_IN_SENT).unwrap();
+        let [Element::FocusIn(event)] = elements.as_slice() else {
+            panic!("expected a focus in, got {elements:?}");
+        };
+        assert_eq!(event.event, 0x3a00007);
+    }
+
+    #[test]
+    fn decodes_window_lifecycle() {
+        let mut unmap = MAP_NOTIFY;
+        unmap[0] = xproto::UNMAP_NOTIFY_EVENT;
+        let data = payload(&[&CREATE_NOTIFY, &MAP_NOTIFY, &unmap]);
+        let elements = decode_all(&data).unwrap();
+        let [
+            Element::CreateNotify(create),
+            Element::MapNotify(map),
+            Element::UnmapNotify(unmap),
+        ] = elements.as_slice()
+        else {
+            panic!("unexpected elements {elements:?}");
+        };
+        assert_eq!((create.parent, create.window), (0x1e9, 0x3a00007));
+        assert_eq!((create.x, create.y), (10, 20));
+        assert_eq!((create.width, create.height), (800, 600));
+        assert!(!create.override_redirect);
+        assert_eq!((map.event, map.window), (0x1e9, 0x3a00009));
+        assert!(map.override_redirect);
+        assert_eq!(unmap.window, 0x3a00009);
+    }
+
+    #[test]
+    fn skips_replies_by_their_length() {
+        let data = payload(&[&REPLY_WITH_DATA, &KEY_PRESS]);
+        let elements = decode_all(&data).unwrap();
+        assert_eq!(elements.len(), 2);
+        assert_eq!(elements[0], Element::Reply(&REPLY_WITH_DATA));
+        assert!(matches!(elements[1], Element::KeyPress(_)));
+    }
+
+    #[test]
+    fn skips_generic_events_by_their_length() {
+        let data = payload(&[&GENERIC, &FOCUS_IN_SENT]);
+        let elements = decode_all(&data).unwrap();
+        assert_eq!(elements.len(), 2);
+        assert_eq!(elements[0], Element::Generic(&GENERIC));
+        assert!(matches!(elements[1], Element::FocusIn(_)));
+    }
+
+    #[test]
+    fn decodes_errors() {
+        let data = payload(&[&BAD_WINDOW, &KEY_PRESS]);
+        let elements = decode_all(&data).unwrap();
+        assert_eq!(
+            elements[0],
+            Element::Error(Error {
+                error_code: 3,
+                sequence: 0x127,
+                bad_value: 0x3a00007,
+                minor_opcode: 0,
+                major_opcode: xproto::GET_PROPERTY_REQUEST,
+            })
+        );
+        assert!(matches!(elements[1], Element::KeyPress(_)));
+    }
+
+    #[test]
+    fn passes_other_events_through() {
+        let mut expose = [0; 32];
+        expose[0] = xproto::EXPOSE_EVENT;
+        let elements = decode_all(&expose).unwrap();
+        assert_eq!(elements, vec![Element::Other(&expose)]);
+    }
+
+    #[test]
+    fn rejects_truncated_data() {
+        let data = payload(&[&KEY_PRESS, &REPLY_WITH_DATA]);
+        for length in 1..data.len() {
+            if length == KEY_PRESS.len() {
+                continue;
+            }
+            let result = decode_all(&data[..length]);
+            assert_eq!(result, Err(ParseError::InsufficientData), "length {length}");
+        }
+    }
+
+    #[test]
+    fn stops_after_an_error() {
+        let data = payload(&[&KEY_PRESS, &REPLY_WITH_DATA[..8]]);
+        let mut stream = Stream::new(&data);
+        assert!(matches!(stream.next(), Some(Ok(Element::KeyPress(_)))));
+        assert!(matches!(stream.next(), Some(Err(_))));
+        assert_eq!(stream.next(), None);
+    }
+
+    /// Converts a payload in our byte order into the other one.
+    fn swap(elements: &[&[u8]]) -> Vec<u8> {
+        elements
+            .iter()
+            .flat_map(|element| {
+                let (u16s, u32s) = swapped_fields(element[0]).unwrap();
+                swap_fields(element, u16s, u32s)
+            })
+            .collect()
+    }
+
+    /// The payloads are little-endian, decodes them on any host.
+    fn decode_little_endian(data: &[u8]) -> Result<Vec<Element<'_>>, ParseError> {
+        if cfg!(target_endian = "little") {
+            Stream::new(data).collect()
+        } else {
+            Stream::swapped(data).collect()
+        }
+    }
+
+    /// Decodes big-endian payloads on any host.
+    fn decode_big_endian(data: &[u8]) -> Result<Vec<Element<'_>>, ParseError> {
+        if cfg!(target_endian = "big") {
+            Stream::new(data).collect()
+        } else {
+            Stream::swapped(data).collect()
+        }
+    }
+
+    #[test]
+    fn decodes_big_endian_clients() {
+        /// KeyPress of keycode 38 on window 0x3a00007 by a big-endian client
+        #[rustfmt::skip]
+        const BIG_ENDIAN_KEY_PRESS: [u8; 32] = [
+            0x02, 0x26, 0x01, 0x23,
+            0x0a, 0x1b, 0x2c, 0x3d,
+            0x00, 0x00, 0x01, 0xe9,
+            0x03, 0xa0, 0x00, 0x07,
+            0x00, 0x00, 0x00, 0x00,
+            0x03, 0x2c, 0x01, 0x93,
+            0x00, 0x70, 0x00, 0x57,
+            0x00, 0x10, 0x01, 0x00,
+        ];
+        let big_endian = decode_big_endian(&BIG_ENDIAN_KEY_PRESS).unwrap();
+        let little_endian = decode_little_endian(&KEY_PRESS).unwrap();
+        assert_eq!(big_endian, little_endian);
+    }
+
+    #[test]
+    fn decodes_every_swapped_element() {
+        let elements: [&[u8]; 7] = [
+            &BAD_WINDOW,
+            &KEY_PRESS,
+            &MOTION_NOTIFY,
+            &FOCUS_IN_SENT,
+            &PROPERTY_NOTIFY,
+            &CREATE_NOTIFY,
+            &MAP_NOTIFY,
+        ];
+        let swapped = swap(&elements);
+        assert_eq!(
+            decode_big_endian(&swapped).unwrap(),
+            decode_little_endian(&elements.concat()).unwrap()
+        );
+    }
+
+    #[test]
+    fn skips_swapped_replies_by_their_length() {
+        let mut reply = REPLY_WITH_DATA;
+        reply[4..8].reverse();
+        let data = payload(&[&reply, &swap(&[&KEY_PRESS])]);
+        let elements = decode_big_endian(&data).unwrap();
+        assert_eq!(elements[0], Element::Reply(&reply));
+        assert_eq!(elements[1], decode_little_endian(&KEY_PRESS).unwrap()[0]);
+    }
+
+    #[test]
+    fn never_panics_on_garbage() {
+        // xorshift, so the input is reproducible
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let mut random = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        for _ in 0..10_000 {
+            let length = (random() % 200) as usize;
+            let data: Vec<u8> = (0..length).map(|_| random() as u8).collect();
+            Stream::new(&data).for_each(drop);
+            Stream::swapped(&data).for_each(drop);
+        }
+    }
+}
@@ -0,0 +1,207 @@
+//! Follows top-level windows from their creation to their destruction.
+//!
+//! The recorder selects `SubstructureNotify` on the root window, so the X server
+//! notifies about the windows created as its children. Reparenting window
+//! managers then move each window into a frame of their own, after which it is
+//! followed through the `StructureNotify` selected on it when its metadata was
+//! looked up. Apps only set the properties identifying them before they map a
+//! window, so whether a window is followed is decided when it is first mapped.
+//! Frames and other windows without anything to identify an app by are not
+//! followed, and neither are windows that existed before recording. Other
+//! windows, like override-redirect menus and tooltips, are never looked up.
+//!
+//! Every change is recorded once per interested client, the state of each
+//! window tells the repeated ones apart.
+
+use std::collections::HashMap;
+use std::time::Instant;
+
+use chrono::{DateTime, Utc};
+use x11rb::protocol::xproto::Window;
+
+use crate::event;
+
+use super::cache::{AppSource, WindowInfo};
+
+/// A change in the lifecycle of a window.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Change {
+    Created,
+    Destroyed,
+    Mapped,
+    Unmapped,
+}
+
+impl Change {
+    /// Returns the event reporting the change of the window.
+    pub fn data(self, window: Window) -> event::EventData {
+        match self {
+            Change::Created => event::EventData::WindowCreated(window),
+            Change::Destroyed => event::EventData::WindowDestroyed(window),
+            Change::Mapped => event::EventData::WindowMapped(window),
+            Change::Unmapped => event::EventData::WindowUnmapped(window),
+        }
+    }
+}
+
+/// When something happened, on the wall clock and the monotonic clock.
+pub type Time = (DateTime<Utc>, Instant);
+
+/// A followed window.
+#[derive(Debug)]
+struct Toplevel {
+    /// the metadata the window was last seen with
+    info: WindowInfo,
+    mapped: bool,
+}
+
+/// The created and the followed top-level windows.
+#[derive(Debug, Default)]
+pub struct Toplevels {
+    /// windows created as children of the root window that were not mapped
+    /// yet, with the time they were created
+    created: HashMap<Window, Time>,
+    followed: HashMap<Window, Toplevel>,
+}
+
+impl Toplevels {
+    /// Remembers a window created as a child of the root window, until it is
+    /// mapped or destroyed.
+    pub fn create(&mut self, window: Window, time: Time) {
+        if !self.followed.contains_key(&window) {
+            self.created.entry(window).or_insert(time);
+        }
+    }
+
+    /// Whether the window is followed, or was created and may be followed once mapped.
+    pub fn knows(&self, window: Window) -> bool {
+        self.created.contains_key(&window) || self.follows(window)
+    }
+
+    /// Whether the window is followed.
+    pub fn follows(&self, window: Window) -> bool {
+        self.followed.contains_key(&window)
+    }
+
+    /// Updates a mapped window, returning its metadata if it is followed, and
+    /// when it was created if it was not followed before.
+    ///
+    /// A created window is followed once it is mapped with metadata identifying
+    /// its app, otherwise it is looked at again when mapped the next time.
+    pub fn map(
+        &mut self,
+        window: Window,
+        info: Option<WindowInfo>,
+    ) -> Option<(Option<Time>, WindowInfo)> {
+        if let Some(followed) = self.followed.get_mut(&window) {
+            if followed.mapped {
+                return None;
+            }
+            followed.mapped = true;
+            if let Some(info) = info {
+                followed.info = info;
+            }
+            return Some((None, followed.info.clone()));
+        }
+
+        let info =
+            info.filter(|info| matches!(info.source, AppSource::Class | AppSource::Process))?;
+        let created = self.created.remove(&window)?;
+        let toplevel = Toplevel {
+            info: info.clone(),
+            mapped: true,
+        };
+        self.followed.insert(window, toplevel);
+        Some((Some(created), info))
+    }
+
+    /// Updates an unmapped window, returning its metadata if it is followed.
+    pub fn unmap(&mut self, window: Window, info: Option<WindowInfo>) -> Option<WindowInfo> {
+        let followed = self
+            .followed
+            .get_mut(&window)
+            .filter(|followed| followed.mapped)?;
+        followed.mapped = false;
+        if let Some(info) = info {
+            followed.info = info;
+        }
+        Some(followed.info.clone())
+    }
+
+    /// Forgets a destroyed window, returning the metadata it was last seen with
+    /// if it was followed.
+    pub fn destroy(&mut self, window: Window) -> Option<WindowInfo> {
+        self.created.remove(&window);
+        self.followed.remove(&window).map(|followed| followed.info)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(class: Option<&str>, title: &str) -> WindowInfo {
+        WindowInfo {
+            app: class.map(str::to_string),
+            source: match class {
+                Some(_) => AppSource::Class,
+                None => AppSource::Unknown,
+            },
+            title: Some(title.to_string()),
+            process: None,
+            fullscreen: false,
+        }
+    }
+
+    fn created() -> Time {
+        (Utc::now(), Instant::now())
+    }
+
+    #[test]
+    fn follows_windows_of_apps_once_mapped() {
+        let mut toplevels = Toplevels::default();
+        let time = created();
+        toplevels.create(1, time);
+        // a frame of the window manager
+        toplevels.create(2, created());
+        assert!(toplevels.knows(2) && !toplevels.follows(2));
+        assert_eq!(toplevels.map(2, Some(info(None, ""))), None);
+
+        // the app is identified when mapped, even if it was not when created
+        let mapped = toplevels.map(1, Some(info(Some("kitty"), "~")));
+        assert_eq!(mapped, Some((Some(time), info(Some("kitty"), "~"))));
+        assert!(toplevels.follows(1));
+        // the window is looked up again when unmapped, and kept if that fails
+        let unmapped = toplevels.unmap(1, Some(info(Some("kitty"), "vim")));
+        assert_eq!(unmapped, Some(info(Some("kitty"), "vim")));
+        let mapped = toplevels.map(1, None);
+        assert_eq!(mapped, Some((None, info(Some("kitty"), "vim"))));
+
+        assert_eq!(toplevels.destroy(1), Some(info(Some("kitty"), "vim")));
+        assert_eq!(toplevels.destroy(1), None);
+        assert_eq!(toplevels.destroy(2), None);
+        // windows that existed before recording are not followed
+        assert!(!toplevels.knows(3));
+        assert_eq!(toplevels.map(3, Some(info(Some("firefox"), ""))), None);
+    }
+
+    #[test]
+    fn skips_changes_seen_by_other_clients() {
+        let mut toplevels = Toplevels::default();
+        let time = created();
+        toplevels.create(1, time);
+        toplevels.create(2, created());
+        toplevels.create(1, created());
+        // changes of other windows in between
+        let mapped = toplevels.map(1, Some(info(Some("kitty"), "~")));
+        assert_eq!(mapped, Some((Some(time), info(Some("kitty"), "~"))));
+        assert!(toplevels.map(2, Some(info(Some("foot"), "~"))).is_some());
+        assert!(toplevels.map(1, Some(info(Some("kitty"), "~"))).is_none());
+        toplevels.create(1, created());
+        assert!(toplevels.unmap(1, None).is_some());
+        assert!(toplevels.unmap(2, None).is_some());
+        assert!(toplevels.unmap(1, None).is_none());
+        assert!(toplevels.destroy(1).is_some());
+        assert!(toplevels.destroy(1).is_none());
+    }
+}
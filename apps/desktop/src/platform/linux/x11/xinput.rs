@@ -0,0 +1,671 @@
+//! Records input through the raw events of the XInput 2 extension.
+//!
+//! Unlike RECORD, this also sees touchscreens and tablets, and tells which
+//! device the input came from. Raw events are delivered to the root window
+//! only, so input is attributed to the active window.
+//!
+//! Touchscreens and tablets report where they are touched rather than how far
+//! they moved. Like in the evdev backend, the movement between positions is
+//! reported as pointer motion in device units, for each finger from where it
+//! touched down. Touches are reported as presses and releases of the first
+//! button, the device of the event tells them apart from clicks.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use log::{info, warn};
+
+use x11rb::connection::{Connection, RequestConnection};
+use x11rb::protocol::Event;
+use x11rb::protocol::screensaver;
+use x11rb::protocol::xinput::{self, ConnectionExt as _};
+use x11rb::protocol::xproto::{
+    self, ChangeWindowAttributesAux, ClientMessageEvent, ConnectionExt as _, CreateWindowAux,
+    EventMask, WindowClass,
+};
+use x11rb::rust_connection::RustConnection;
+
+use crate::event::{self, Device, DeviceKind};
+use crate::platform::{self, Capabilities, Recorder, UNKNOWN_APP};
+
+use super::cache::WindowCache;
+use super::clock::Clock;
+use super::recording::Recording;
+use super::{Atoms, Error, Fallbacks, Windows, idle, scroll_delta, share, supported};
+
+/// The XInput version with raw touch events.
+const VERSION: (u16, u16) = (2, 2);
+
+/// Records input from all devices through XInput 2.
+pub struct XInputRecorder {
+    idle_threshold: Duration,
+    running: Option<Running>,
+}
+
+impl XInputRecorder {
+    pub fn new(settings: &platform::Settings) -> Self {
+        XInputRecorder {
+            idle_threshold: settings.idle_threshold,
+            running: None,
+        }
+    }
+}
+
+impl Default for XInputRecorder {
+    fn default() -> Self {
+        XInputRecorder::new(&platform::Settings::default())
+    }
+}
+
+/// A started recording.
+struct Running {
+    /// the connection and the window to wake the worker through
+    conn: Arc<RustConnection>,
+    wake: xproto::Window,
+    /// tells the worker to finish once woken
+    stopped: Arc<AtomicBool>,
+    worker: JoinHandle<Result<(), Error>>,
+    /// the watcher of idle periods, running along the worker
+    idle: Recording,
+}
+
+impl Recorder for XInputRecorder {
+    fn capabilities(&self) -> Capabilities {
+        let supported = supported(&[screensaver::X11_EXTENSION_NAME]);
+        Capabilities {
+            titles: true,
+            input: true,
+            idle: !supported.is_empty(),
+        }
+    }
+
+    fn start(&mut self, callback: platform::Callback) -> Result<(), platform::Error> {
+        if self.running.is_some() {
+            return Err("the XInput recorder is already started".into());
+        }
+
+        let mut session = Session::connect()?;
+        let (conn, wake) = (Arc::clone(&session.conn), session.wake);
+        let stopped = Arc::new(AtomicBool::new(false));
+        let (callback, idle) = share(callback);
+        let worker = {
+            let stopped = Arc::clone(&stopped);
+            std::thread::spawn(move || session.run(&callback, &stopped))
+        };
+        let idle = idle::watch(idle, self.idle_threshold);
+        self.running = Some(Running {
+            conn,
+            wake,
+            stopped,
+            worker,
+            idle,
+        });
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<(), platform::Error> {
+        if let Some(Running {
+            conn,
+            wake,
+            stopped,
+            ..
+        }) = &self.running
+        {
+            stopped.store(true, Ordering::Relaxed);
+            // an event without a mask goes to the client that created the window
+            let event = ClientMessageEvent::new(32, *wake, xproto::AtomEnum::NONE, [0; 5]);
+            conn.send_event(false, *wake, EventMask::NO_EVENT, event)
+                .map_err(Error::from)?;
+            conn.flush().map_err(Error::from)?;
+        }
+        self.wait()
+    }
+
+    fn wait(&mut self) -> Result<(), platform::Error> {
+        let Some(Running { worker, idle, .. }) = self.running.take() else {
+            return Ok(());
+        };
+        let result = match worker.join() {
+            Ok(result) => result,
+            Err(panic) => std::panic::resume_unwind(panic),
+        };
+        idle::finish(idle);
+        Ok(result?)
+    }
+}
+
+/// An input device known to the X server.
+struct InputDevice {
+    device: Arc<Device>,
+    /// whether the pointer axes are positions rather than movements
+    absolute: bool,
+}
+
+/// The connection to the X server, with raw events selected on the root window.
+struct Session {
+    conn: Arc<RustConnection>,
+    atoms: Atoms,
+    root: xproto::Window,
+    /// a window of our own, to wake the worker when stopping
+    wake: xproto::Window,
+    devices: HashMap<xinput::DeviceId, InputDevice>,
+}
+
+impl Session {
+    fn connect() -> Result<Self, Error> {
+        let (conn, screen) = x11rb::connect(None)?;
+        let root = conn.setup().roots[screen].root;
+
+        if conn
+            .extension_information(xinput::X11_EXTENSION_NAME)?
+            .is_none()
+        {
+            return Err(Error::MissingExtension(xinput::X11_EXTENSION_NAME));
+        }
+        let version = conn
+            .xinput_xi_query_version(VERSION.0, VERSION.1)?
+            .reply()?;
+        if (version.major_version, version.minor_version) < VERSION {
+            return Err(Error::MissingExtension("XInputExtension 2.2"));
+        }
+
+        let atoms = Atoms::new(&conn)?.reply()?;
+
+        // Raw events are only delivered to the root window. They are selected
+        // for the master devices, which report the slave device as the source.
+        let raw = xinput::XIEventMask::RAW_KEY_PRESS
+            | xinput::XIEventMask::RAW_KEY_RELEASE
+            | xinput::XIEventMask::RAW_BUTTON_PRESS
+            | xinput::XIEventMask::RAW_BUTTON_RELEASE
+            | xinput::XIEventMask::RAW_MOTION
+            | xinput::XIEventMask::RAW_TOUCH_BEGIN
+            | xinput::XIEventMask::RAW_TOUCH_UPDATE
+            | xinput::XIEventMask::RAW_TOUCH_END;
+        let masks = [
+            xinput::EventMask {
+                deviceid: xinput::Device::ALL_MASTER.into(),
+                mask: vec![raw],
+            },
+            xinput::EventMask {
+                deviceid: xinput::Device::ALL.into(),
+                mask: vec![xinput::XIEventMask::HIERARCHY],
+            },
+        ];
+        conn.xinput_xi_select_events(root, &masks)?.check()?;
+
+        // get notified when the active window changes
+        conn.change_window_attributes(
+            root,
+            &ChangeWindowAttributesAux::new().event_mask(EventMask::PROPERTY_CHANGE),
+        )?
+        .check()?;
+
+        let wake = conn.generate_id()?;
+        conn.create_window(
+            x11rb::COPY_DEPTH_FROM_PARENT,
+            wake,
+            root,
+            0,
+            0,
+            1,
+            1,
+            0,
+            WindowClass::INPUT_ONLY,
+            x11rb::COPY_FROM_PARENT,
+            &CreateWindowAux::new(),
+        )?
+        .check()?;
+
+        let mut session = Session {
+            conn: Arc::new(conn),
+            atoms,
+            root,
+            wake,
+            devices: HashMap::new(),
+        };
+        session.query_devices()?;
+        Ok(session)
+    }
+
+    /// Looks up the slave devices, which the input is reported for.
+    fn query_devices(&mut self) -> Result<(), Error> {
+        let reply = self
+            .conn
+            .xinput_xi_query_device(xinput::Device::ALL)?
+            .reply()?;
+        self.devices = reply
+            .infos
+            .iter()
+            .filter(|info| {
+                info.type_ != xinput::DeviceType::MASTER_POINTER
+                    && info.type_ != xinput::DeviceType::MASTER_KEYBOARD
+            })
+            .map(|info| {
+                let device = Device {
+                    id: Some(info.deviceid.into()),
+                    name: String::from_utf8_lossy(&info.name).into_owned(),
+                    kind: classify(info),
+                };
+                let absolute = info.classes.iter().any(|class| match &class.data {
+                    xinput::DeviceClassData::Valuator(valuator) => {
+                        valuator.number == 0 && valuator.mode == xinput::ValuatorMode::ABSOLUTE
+                    }
+                    _ => false,
+                });
+                let device = InputDevice {
+                    device: Arc::new(device),
+                    absolute,
+                };
+                (info.deviceid, device)
+            })
+            .collect();
+        info!("Found {} input devices", self.devices.len());
+        Ok(())
+    }
+
+    /// Records until stopped or the connection fails.
+    fn run(
+        &mut self,
+        callback: &impl Fn(&event::Event),
+        stopped: &AtomicBool,
+    ) -> Result<(), Error> {
+        let conn = Arc::clone(&self.conn);
+        let mut clock = Clock::new();
+        let mut cache = WindowCache::new();
+        let mut windows = Windows {
+            conn: &*conn,
+            atoms: self.atoms,
+            root: self.root,
+            cache: &mut cache,
+            fallbacks: Fallbacks::default(),
+        };
+        let mut positions = Positions::default();
+
+        loop {
+            let event = conn.wait_for_event()?;
+            if stopped.load(Ordering::Relaxed) {
+                if windows.fallbacks.total() > 0 {
+                    info!(
+                        "Events of windows without a WM_CLASS: {}",
+                        windows.fallbacks
+                    );
+                }
+                return Ok(());
+            }
+
+            let (sourceid, time, data) = match event {
+                Event::XinputRawKeyPress(event) => (
+                    event.sourceid,
+                    event.time,
+                    event::EventData::KeyPress(event.detail),
+                ),
+                Event::XinputRawKeyRelease(event) => (
+                    event.sourceid,
+                    event.time,
+                    event::EventData::KeyRelease(event.detail),
+                ),
+                Event::XinputRawButtonPress(event) => {
+                    let Some(data) = button(&event, true) else {
+                        continue;
+                    };
+                    (event.sourceid, event.time, data)
+                }
+                Event::XinputRawButtonRelease(event) => {
+                    let Some(data) = button(&event, false) else {
+                        continue;
+                    };
+                    (event.sourceid, event.time, data)
+                }
+                Event::XinputRawMotion(event) => {
+                    // the motion emulated for touches is reported with the touches
+                    if event
+                        .flags
+                        .contains(xinput::PointerEventFlags::POINTER_EMULATED)
+                    {
+                        continue;
+                    }
+                    let (mask, values) = (&event.valuator_mask, &event.axisvalues_raw);
+                    let absolute = self
+                        .devices
+                        .get(&event.sourceid)
+                        .is_some_and(|device| device.absolute);
+                    let data = match absolute {
+                        true => positions.motion((event.sourceid, None), mask, values),
+                        false => motion(mask, values),
+                    };
+                    let Some(data) = data else {
+                        continue;
+                    };
+                    (event.sourceid, event.time, data)
+                }
+                // a touch is reported as a press of the first button
+                Event::XinputRawTouchBegin(event) => {
+                    let touch = (event.sourceid, Some(event.detail));
+                    positions.motion(touch, &event.valuator_mask, &event.axisvalues_raw);
+                    let data = event::EventData::PointerPress(1);
+                    (event.sourceid, event.time, data)
+                }
+                Event::XinputRawTouchUpdate(event) => {
+                    let touch = (event.sourceid, Some(event.detail));
+                    let (mask, values) = (&event.valuator_mask, &event.axisvalues_raw);
+                    let Some(data) = positions.motion(touch, mask, values) else {
+                        continue;
+                    };
+                    (event.sourceid, event.time, data)
+                }
+                Event::XinputRawTouchEnd(event) => {
+                    positions.lift((event.sourceid, Some(event.detail)));
+                    let data = event::EventData::PointerRelease(1);
+                    (event.sourceid, event.time, data)
+                }
+                Event::XinputHierarchy(_) => {
+                    self.query_devices()?;
+                    continue;
+                }
+                Event::PropertyNotify(event) if event.atom == windows.atoms._NET_ACTIVE_WINDOW => {
+                    windows.cache.invalidate_active(event.window);
+                    continue;
+                }
+                Event::PropertyNotify(event) if windows.is_workspace(event.atom) => {
+                    windows.cache.invalidate_workspace(event.window);
+                    continue;
+                }
+                Event::PropertyNotify(event) if windows.is_metadata(event.atom) => {
+                    windows.cache.invalidate(event.window);
+                    continue;
+                }
+                Event::DestroyNotify(event) => {
+                    windows.cache.remove(event.window);
+                    continue;
+                }
+                Event::Error(err) => {
+                    warn!("X11 error: {err:?}");
+                    continue;
+                }
+                _ => continue,
+            };
+
+            let info = match windows.active(self.root)? {
+                Some(window) => windows.info(window)?,
+                None => None,
+            };
+            let info = info.unwrap_or_default();
+            windows.fallbacks.count(info.source);
+            let (timestamp, instant) = clock.at(time);
+            callback(&event::Event {
+                timestamp,
+                instant,
+                app: info.app.unwrap_or_else(|| UNKNOWN_APP.to_string()),
+                title: info.title,
+                workspace: windows.workspace()?,
+                device: self
+                    .devices
+                    .get(&sourceid)
+                    .map(|device| Arc::clone(&device.device)),
+                process: info.process,
+                output: None,
+                data,
+            });
+        }
+    }
+}
+
+/// Converts a raw button event, reporting the wheel buttons as scrolling.
+/// Presses emulated for touches are skipped, the touches are reported instead.
+fn button(event: &xinput::RawButtonPressEvent, pressed: bool) -> Option<event::EventData> {
+    let emulated = event
+        .flags
+        .contains(xinput::PointerEventFlags::POINTER_EMULATED);
+    let button = u8::try_from(event.detail).ok()?;
+    match scroll_delta(button) {
+        Some((dx, dy)) if pressed => Some(event::EventData::Scroll { dx, dy }),
+        // wheel buttons are released immediately after every step
+        Some(_) => None,
+        None if emulated => None,
+        None if pressed => Some(event::EventData::PointerPress(button)),
+        None => Some(event::EventData::PointerRelease(button)),
+    }
+}
+
+/// Returns the movement along the first two valuators, which are the x and y
+/// axes of pointers, if the event has any.
+fn motion(mask: &[u32], values: &[xinput::Fp3232]) -> Option<event::EventData> {
+    let (mut dx, mut dy) = (None, None);
+    for (number, value) in valuators(mask, values) {
+        match number {
+            0 => dx = Some(value),
+            1 => dy = Some(value),
+            _ => {}
+        }
+    }
+    match (dx, dy) {
+        (None, None) => None,
+        (dx, dy) => Some(event::EventData::PointerMotion {
+            dx: dx.unwrap_or_default(),
+            dy: dy.unwrap_or_default(),
+        }),
+    }
+}
+
+/// The last positions of absolute devices and of the touches on them, by the
+/// device and the touch, to report their movements between the positions.
+#[derive(Debug, Default)]
+struct Positions(HashMap<Pointer, (Option<f64>, Option<f64>)>);
+
+/// An absolute device, with the id of the touch for touches.
+type Pointer = (xinput::DeviceId, Option<u32>);
+
+impl Positions {
+    /// Returns the movement to the position along the first two valuators,
+    /// if it moved since the last position.
+    fn motion(
+        &mut self,
+        key: Pointer,
+        mask: &[u32],
+        values: &[xinput::Fp3232],
+    ) -> Option<event::EventData> {
+        let last = self.0.entry(key).or_default();
+        let (mut dx, mut dy) = (0.0, 0.0);
+        for (number, value) in valuators(mask, values) {
+            let (axis, delta) = match number {
+                0 => (&mut last.0, &mut dx),
+                1 => (&mut last.1, &mut dy),
+                _ => continue,
+            };
+            if let Some(previous) = axis.replace(value) {
+                *delta = value - previous;
+            }
+        }
+        ((dx, dy) != (0.0, 0.0)).then_some(event::EventData::PointerMotion { dx, dy })
+    }
+
+    /// Forgets the position of a lifted touch, the next one starts where it touches down.
+    fn lift(&mut self, key: Pointer) {
+        self.0.remove(&key);
+    }
+}
+
+/// Pairs the values of a raw event with the numbers of their valuators, which
+/// are the bits set in the mask.
+fn valuators<'a>(
+    mask: &'a [u32],
+    values: &'a [xinput::Fp3232],
+) -> impl Iterator<Item = (usize, f64)> + 'a {
+    let numbers = mask.iter().enumerate().flat_map(|(word, &bits)| {
+        (0..32)
+            .filter(move |bit| bits >> bit & 1 == 1)
+            .map(move |bit| word * 32 + bit)
+    });
+    let values = values
+        .iter()
+        .map(|value| f64::from(value.integral) + f64::from(value.frac) / 4_294_967_296.0);
+    numbers.zip(values)
+}
+
+/// Classifies a slave device by its type and input classes.
+fn classify(info: &xinput::XIDeviceInfo) -> DeviceKind {
+    let touch = info.classes.iter().find_map(|class| match &class.data {
+        xinput::DeviceClassData::Touch(touch) => Some(touch.mode),
+        _ => None,
+    });
+    // the devices the XTEST extension fakes input through
+    if info.name.ends_with(b"XTEST keyboard") || info.name.ends_with(b"XTEST pointer") {
+        return DeviceKind::Other;
+    }
+    match (info.type_, touch) {
+        (_, Some(xinput::TouchMode::DIRECT)) => DeviceKind::Touchscreen,
+        (_, Some(_)) => DeviceKind::Touchpad,
+        (xinput::DeviceType::SLAVE_KEYBOARD, None) => DeviceKind::Keyboard,
+        (xinput::DeviceType::SLAVE_POINTER, None) => DeviceKind::Mouse,
+        _ => DeviceKind::Other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use x11rb::protocol::xtest::ConnectionExt as _;
+
+    use super::*;
+
+    fn fp(value: f64) -> xinput::Fp3232 {
+        xinput::Fp3232 {
+            integral: value.floor() as i32,
+            frac: (value.fract() * 4_294_967_296.0) as u32,
+        }
+    }
+
+    #[test]
+    fn pairs_valuators_with_their_values() {
+        let values = [fp(1.5), fp(-2.0), fp(0.25)];
+        let pairs: Vec<_> = valuators(&[0b101, 1 << 1], &values).collect();
+        assert_eq!(pairs, [(0, 1.5), (2, -2.0), (33, 0.25)]);
+
+        let data = motion(&[0b11], &[fp(3.0), fp(-1.0)]);
+        assert_eq!(
+            data,
+            Some(event::EventData::PointerMotion { dx: 3.0, dy: -1.0 })
+        );
+        // scroll valuators only
+        assert_eq!(motion(&[0b1100], &[fp(15.0), fp(0.0)]), None);
+    }
+
+    #[test]
+    fn reports_the_movement_of_touches() {
+        let mut positions = Positions::default();
+        let (first, second) = ((10, Some(1)), (10, Some(2)));
+        assert_eq!(
+            positions.motion(first, &[0b11], &[fp(100.0), fp(200.0)]),
+            None
+        );
+        assert_eq!(
+            positions.motion(second, &[0b11], &[fp(500.0), fp(500.0)]),
+            None
+        );
+        // only the x axis changed
+        assert_eq!(
+            positions.motion(first, &[0b1], &[fp(110.0)]),
+            Some(event::EventData::PointerMotion { dx: 10.0, dy: 0.0 })
+        );
+        assert_eq!(
+            positions.motion(second, &[0b11], &[fp(490.0), fp(520.0)]),
+            Some(event::EventData::PointerMotion {
+                dx: -10.0,
+                dy: 20.0
+            })
+        );
+        // a new touch starts where the finger is, without moving
+        positions.lift(first);
+        assert_eq!(positions.motion(first, &[0b11], &[fp(0.0), fp(0.0)]), None);
+    }
+
+    #[test]
+    fn classifies_devices() {
+        let device = |name: &str, type_, touch: Option<xinput::TouchMode>| xinput::XIDeviceInfo {
+            deviceid: 10,
+            type_,
+            attachment: 2,
+            enabled: true,
+            name: name.as_bytes().to_vec(),
+            classes: touch
+                .map(|mode| xinput::DeviceClass {
+                    len: 2,
+                    sourceid: 10,
+                    data: xinput::DeviceClassData::Touch(xinput::DeviceClassDataTouch {
+                        mode,
+                        num_touches: 10,
+                    }),
+                })
+                .into_iter()
+                .collect(),
+        };
+        const SLAVE_KEYBOARD: xinput::DeviceType = xinput::DeviceType::SLAVE_KEYBOARD;
+        const SLAVE_POINTER: xinput::DeviceType = xinput::DeviceType::SLAVE_POINTER;
+        let kind = |name, type_, touch| classify(&device(name, type_, touch));
+        assert_eq!(
+            kind("AT keyboard", SLAVE_KEYBOARD, None),
+            DeviceKind::Keyboard
+        );
+        assert_eq!(kind("USB Mouse", SLAVE_POINTER, None), DeviceKind::Mouse);
+        let touchscreen = kind(
+            "ELAN Touchscreen",
+            SLAVE_POINTER,
+            Some(xinput::TouchMode::DIRECT),
+        );
+        assert_eq!(touchscreen, DeviceKind::Touchscreen);
+        let touchpad = kind(
+            "SYNA Touchpad",
+            SLAVE_POINTER,
+            Some(xinput::TouchMode::DEPENDENT),
+        );
+        assert_eq!(touchpad, DeviceKind::Touchpad);
+        let xtest = kind("Virtual core XTEST keyboard", SLAVE_KEYBOARD, None);
+        assert_eq!(xtest, DeviceKind::Other);
+    }
+
+    #[test]
+    #[ignore = "needs an X server with XInput 2.2 and XTEST, like Xvfb"]
+    fn records_faked_input_with_its_device() {
+        let reported = Arc::new(Mutex::new(Vec::new()));
+        let settings = platform::Settings {
+            idle_threshold: Duration::from_secs(3600),
+            ..Default::default()
+        };
+        let mut recorder = XInputRecorder::new(&settings);
+        let events = Arc::clone(&reported);
+        recorder
+            .start(Box::new(move |event| {
+                let device = event.device.as_deref().cloned();
+                events.lock().unwrap().push((event.data, device));
+            }))
+            .unwrap();
+
+        let (conn, screen) = x11rb::connect(None).unwrap();
+        let root = conn.setup().roots[screen].root;
+        for type_ in [xproto::KEY_PRESS_EVENT, xproto::KEY_RELEASE_EVENT] {
+            conn.xtest_fake_input(type_, 38, x11rb::CURRENT_TIME, root, 0, 0, 0)
+                .unwrap()
+                .check()
+                .unwrap();
+        }
+        std::thread::sleep(Duration::from_millis(200));
+        recorder.stop().unwrap();
+
+        let reported = reported.lock().unwrap();
+        let data: Vec<_> = reported.iter().map(|(data, _)| *data).collect();
+        assert_eq!(
+            data,
+            [
+                event::EventData::KeyPress(38),
+                event::EventData::KeyRelease(38)
+            ]
+        );
+        let device = reported[0].1.as_ref().expect("the device is known");
+        assert!(device.name.contains("XTEST"));
+        assert_eq!(device.kind, DeviceKind::Other);
+    }
+}
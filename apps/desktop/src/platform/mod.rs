@@ -1 +1,300 @@
 pub mod linux;
+#[cfg(feature = "rdev")]
+pub mod rdev;
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use crate::event;
+
+/// The app that events are attributed to when nothing identifies their window.
+pub const UNKNOWN_APP: &str = "unknown";
+
+/// The callback recorders report their events to.
+pub type Callback = Box<dyn Fn(&event::Event) + Send + Sync>;
+
+/// Errors of recorders, every backend has its own error type.
+pub type Error = Box<dyn std::error::Error + Send + Sync>;
+
+/// What a recorder is able to observe, besides the application in use.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Capabilities {
+    /// events carry window titles
+    pub titles: bool,
+    /// key presses and pointer activity are reported
+    pub input: bool,
+    /// idle periods are reported
+    pub idle: bool,
+}
+
+impl fmt::Display for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let yes_no = |capable| if capable { "yes" } else { "no" };
+        write!(
+            f,
+            "titles: {}, input: {}, idle: {}",
+            yes_no(self.titles),
+            yes_no(self.input),
+            yes_no(self.idle)
+        )
+    }
+}
+
+/// A source of events, running in the background once started.
+pub trait Recorder: Send {
+    /// Returns what the recorder is able to observe.
+    fn capabilities(&self) -> Capabilities;
+
+    /// Starts reporting events to the callback.
+    fn start(&mut self, callback: Callback) -> Result<(), Error>;
+
+    /// Stops reporting events and waits for the recorder to finish.
+    fn stop(&mut self) -> Result<(), Error>;
+
+    /// Waits for the recorder to finish, which it only does on its own when it fails.
+    fn wait(&mut self) -> Result<(), Error>;
+}
+
+/// The application and title of a window.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Window {
+    pub app: String,
+    pub title: Option<String>,
+    /// the workspace the window is on, if the provider knows
+    pub workspace: Option<String>,
+    /// the process owning the window, if the provider knows
+    pub process: Option<Arc<event::Process>>,
+}
+
+/// Looks up the window the user is interacting with, for recorders that only
+/// observe input.
+pub trait WindowProvider: Send {
+    /// Returns the active window, or `None` if there is none or it is not an application.
+    fn active(&mut self) -> Result<Option<Window>, Error>;
+}
+
+/// A [`WindowProvider`] following the focus events of a recorder, for pairing
+/// input with a recorder that only reports focus changes.
+#[derive(Debug, Clone, Default)]
+pub struct FocusTracker {
+    focused: Arc<Mutex<Option<Window>>>,
+}
+
+impl FocusTracker {
+    /// Updates the focused window from an event of the recorder.
+    pub fn track(&self, event: &event::Event) {
+        let mut focused = self.focused.lock().unwrap_or_else(PoisonError::into_inner);
+        match event.data {
+            event::EventData::FocusIn | event::EventData::TitleChange => {
+                *focused = Some(Window {
+                    app: event.app.clone(),
+                    title: event.title.clone(),
+                    workspace: event.workspace.clone(),
+                    process: event.process.clone(),
+                });
+            }
+            // the focus may already have moved on if events arrive out of order
+            event::EventData::FocusOut
+                if focused
+                    .as_ref()
+                    .is_some_and(|window| window.app == event.app) =>
+            {
+                *focused = None;
+            }
+            _ => {}
+        }
+    }
+}
+
+impl WindowProvider for FocusTracker {
+    fn active(&mut self) -> Result<Option<Window>, Error> {
+        Ok(self
+            .focused
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone())
+    }
+}
+
+/// How recorders are set up. Backends ignore the settings they have no use for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Settings {
+    /// how often the active window is sampled, where it cannot be followed
+    /// through events
+    pub poll_interval: Duration,
+    /// how long the user has to be inactive to be reported as idle
+    pub idle_threshold: Duration,
+    /// the programs whose processes are reported starting and exiting, named by
+    /// their executable, like "syncthing", or by their flatpak or snap id
+    pub processes: Vec<String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            poll_interval: linux::x11::poll::DEFAULT_INTERVAL,
+            idle_threshold: Duration::from_secs(5 * 60),
+            processes: Vec::new(),
+        }
+    }
+}
+
+/// The available recorder implementations.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Backend {
+    /// X11, through the RECORD extension
+    X11,
+    /// X11, through the raw input events of XInput 2
+    XInput,
+    /// X11, sampling the active window without recording input
+    Poll,
+    /// Wayland, through the wlroots foreign toplevel and the idle notify protocols
+    Wayland,
+    /// the IPC of sway, or i3 on X11
+    Sway,
+    /// the IPC of Hyprland
+    Hyprland,
+    /// input read from `/dev/input`, with the focused window followed separately
+    Evdev,
+    /// input through the `rdev` crate, with the active window looked up separately
+    #[cfg(feature = "rdev")]
+    Rdev,
+}
+
+impl Backend {
+    /// The names of the backends compiled in.
+    const NAMES: &str = if cfg!(feature = "rdev") {
+        "x11, xinput, poll, wayland, sway, hyprland, evdev, rdev"
+    } else {
+        "x11, xinput, poll, wayland, sway, hyprland, evdev"
+    };
+
+    /// Selects the backend for the current session.
+    pub fn detect() -> Result<Self, Error> {
+        Self::detect_from(|name| std::env::var(name).ok().filter(|value| !value.is_empty()))
+    }
+
+    /// Selects the backend based on the session type and the displays available,
+    /// as found through `var`. On Wayland, the IPC of the compositor is preferred
+    /// as it also reports workspaces. On X11, the X11 backend is preferred as it
+    /// also records input. Without a display server, only input is recorded.
+    fn detect_from(var: impl Fn(&str) -> Option<String>) -> Result<Self, Error> {
+        let is_wayland = var("XDG_SESSION_TYPE").is_some_and(|session| session == "wayland")
+            || var("WAYLAND_DISPLAY").is_some();
+        let has_x11 = var("DISPLAY").is_some();
+
+        match (is_wayland, has_x11) {
+            (true, _) if var("HYPRLAND_INSTANCE_SIGNATURE").is_some() => Ok(Backend::Hyprland),
+            (true, _) if var("SWAYSOCK").is_some() => Ok(Backend::Sway),
+            (true, _) => Ok(Backend::Wayland),
+            (false, true) => Ok(Backend::X11),
+            (false, false) => Ok(Backend::Evdev),
+        }
+    }
+
+    /// Creates a recorder for the backend.
+    pub fn recorder(self, settings: &Settings) -> Result<Box<dyn Recorder>, Error> {
+        match self {
+            Backend::X11 => Ok(Box::new(linux::x11::X11Recorder::new(settings))),
+            Backend::XInput => Ok(Box::new(linux::x11::xinput::XInputRecorder::new(settings))),
+            Backend::Poll => Ok(Box::new(linux::x11::poll::PollRecorder::new(settings))),
+            Backend::Wayland => Ok(Box::new(linux::wayland::WaylandRecorder::new(settings))),
+            Backend::Sway => Ok(Box::new(linux::ipc::sway::SwayRecorder::default())),
+            Backend::Hyprland => Ok(Box::new(linux::ipc::hyprland::HyprlandRecorder::default())),
+            Backend::Evdev => Ok(Box::new(linux::evdev::EvdevRecorder::new(settings))),
+            #[cfg(feature = "rdev")]
+            Backend::Rdev => Ok(Box::new(rdev::RdevRecorder::default())),
+        }
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Backend::X11 => write!(f, "x11"),
+            Backend::XInput => write!(f, "xinput"),
+            Backend::Poll => write!(f, "poll"),
+            Backend::Wayland => write!(f, "wayland"),
+            Backend::Sway => write!(f, "sway"),
+            Backend::Hyprland => write!(f, "hyprland"),
+            Backend::Evdev => write!(f, "evdev"),
+            #[cfg(feature = "rdev")]
+            Backend::Rdev => write!(f, "rdev"),
+        }
+    }
+}
+
+impl FromStr for Backend {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "x11" => Ok(Backend::X11),
+            "xinput" => Ok(Backend::XInput),
+            "poll" => Ok(Backend::Poll),
+            "wayland" => Ok(Backend::Wayland),
+            "sway" | "i3" => Ok(Backend::Sway),
+            "hyprland" => Ok(Backend::Hyprland),
+            "evdev" => Ok(Backend::Evdev),
+            #[cfg(feature = "rdev")]
+            "rdev" => Ok(Backend::Rdev),
+            _ => Err(format!("unknown backend {name:?}, expected one of: {}", Self::NAMES).into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detect(vars: &[(&str, &str)]) -> Result<Backend, Error> {
+        Backend::detect_from(|name| {
+            vars.iter()
+                .find(|(var, _)| *var == name)
+                .map(|(_, value)| value.to_string())
+        })
+    }
+
+    #[test]
+    fn detects_x11_sessions() {
+        let backend = detect(&[("XDG_SESSION_TYPE", "x11"), ("DISPLAY", ":0")]);
+        assert_eq!(backend.unwrap(), Backend::X11);
+        assert_eq!(detect(&[("DISPLAY", ":1")]).unwrap(), Backend::X11);
+    }
+
+    #[test]
+    fn prefers_wayland_over_xwayland() {
+        let backend = detect(&[("WAYLAND_DISPLAY", "wayland-0"), ("DISPLAY", ":0")]);
+        assert_eq!(backend.unwrap(), Backend::Wayland);
+        let backend = detect(&[("XDG_SESSION_TYPE", "wayland")]);
+        assert_eq!(backend.unwrap(), Backend::Wayland);
+    }
+
+    #[test]
+    fn prefers_compositor_ipc_on_wayland() {
+        let backend = detect(&[("WAYLAND_DISPLAY", "wayland-1"), ("SWAYSOCK", "/run/sway")]);
+        assert_eq!(backend.unwrap(), Backend::Sway);
+        let backend = detect(&[
+            ("XDG_SESSION_TYPE", "wayland"),
+            ("HYPRLAND_INSTANCE_SIGNATURE", "abc"),
+        ]);
+        assert_eq!(backend.unwrap(), Backend::Hyprland);
+        let backend = detect(&[("DISPLAY", ":0"), ("I3SOCK", "/run/i3")]);
+        assert_eq!(backend.unwrap(), Backend::X11);
+    }
+
+    #[test]
+    fn falls_back_to_evdev_without_a_display() {
+        let backend = detect(&[("XDG_SESSION_TYPE", "tty")]);
+        assert_eq!(backend.unwrap(), Backend::Evdev);
+    }
+
+    #[test]
+    fn parses_backend_names() {
+        assert_eq!("x11".parse::<Backend>().unwrap(), Backend::X11);
+        assert_eq!("wayland".parse::<Backend>().unwrap(), Backend::Wayland);
+        assert!("gdi".parse::<Backend>().is_err());
+    }
+}
@@ -0,0 +1,178 @@
+//! A recorder built on the input events of the `rdev` crate, paired with a
+//! [`WindowProvider`] for the application the input goes to.
+//!
+//! On Linux, `rdev` listens through the RECORD extension of the X server as
+//! well, so it does not help on servers where RECORD is disabled. It is meant
+//! for comparing its event stream against the one of the X11 backend.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+use std::time::Instant;
+
+use log::warn;
+
+use crate::event;
+use crate::platform::{Callback, Capabilities, Error, Recorder, WindowProvider};
+
+/// Records input through `rdev`, attributing it to the active window.
+#[derive(Default)]
+pub struct RdevRecorder {
+    stopped: Arc<AtomicBool>,
+    listener: Option<JoinHandle<Result<(), Error>>>,
+}
+
+impl Recorder for RdevRecorder {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            titles: true,
+            input: true,
+            idle: false,
+        }
+    }
+
+    fn start(&mut self, callback: Callback) -> Result<(), Error> {
+        if self.listener.is_some() {
+            return Err("the rdev recorder is already started".into());
+        }
+        let mut windows = windows()?;
+        self.stopped = Arc::default();
+        let stopped = Arc::clone(&self.stopped);
+
+        self.listener = Some(std::thread::spawn(move || {
+            ::rdev::listen(move |input| {
+                if stopped.load(Ordering::Relaxed) {
+                    return;
+                }
+                let Some(data) = convert(&input.event_type) else {
+                    return;
+                };
+                let window = match windows.active() {
+                    Ok(Some(window)) => window,
+                    Ok(None) => return,
+                    Err(err) => return warn!("Skipping input, no active window: {err}"),
+                };
+                callback(&event::Event {
+                    timestamp: input.time.into(),
+                    instant: Instant::now(),
+                    app: window.app,
+                    title: window.title,
+                    workspace: window.workspace,
+                    device: None,
+                    process: window.process,
+                    output: None,
+                    data,
+                });
+            })
+            .map_err(|err| format!("rdev failed to listen: {err:?}").into())
+        }));
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<(), Error> {
+        // rdev offers no way to end listening, so the listener is left behind
+        // without reporting anything
+        self.stopped.store(true, Ordering::Relaxed);
+        self.listener = None;
+        Ok(())
+    }
+
+    fn wait(&mut self) -> Result<(), Error> {
+        match self.listener.take() {
+            Some(listener) => listener
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Creates the provider of the active window for the platform.
+fn windows() -> Result<Box<dyn WindowProvider>, Error> {
+    Ok(Box::new(super::linux::x11::active::ActiveWindow::connect()?))
+}
+
+/// Converts an `rdev` event to the event data of the X11 backend.
+fn convert(event_type: &::rdev::EventType) -> Option<event::EventData> {
+    use ::rdev::EventType;
+
+    Some(match *event_type {
+        EventType::KeyPress(key) => event::EventData::KeyPress(keycode(key)?),
+        EventType::KeyRelease(key) => event::EventData::KeyRelease(keycode(key)?),
+        EventType::ButtonPress(button) => event::EventData::PointerPress(button_number(button)),
+        EventType::ButtonRelease(button) => event::EventData::PointerRelease(button_number(button)),
+        EventType::MouseMove { x, y } => event::EventData::PointerMove { x, y },
+        // rdev scrolls up for positive values, we scroll down
+        EventType::Wheel { delta_x, delta_y } => event::EventData::Scroll {
+            dx: delta_x as f64,
+            dy: -delta_y as f64,
+        },
+    })
+}
+
+/// Returns the X11 button number of a pointer button.
+fn button_number(button: ::rdev::Button) -> u8 {
+    match button {
+        ::rdev::Button::Left => 1,
+        ::rdev::Button::Middle => 2,
+        ::rdev::Button::Right => 3,
+        ::rdev::Button::Unknown(number) => number,
+    }
+}
+
+/// Returns the X11 keycode `rdev` derived a key from, the reverse of its own table.
+fn keycode(key: ::rdev::Key) -> Option<u32> {
+    use ::rdev::Key::*;
+
+    #[rustfmt::skip]
+    let code = match key {
+        Escape => 9, Num1 => 10, Num2 => 11, Num3 => 12, Num4 => 13, Num5 => 14,
+        Num6 => 15, Num7 => 16, Num8 => 17, Num9 => 18, Num0 => 19, Minus => 20,
+        Equal => 21, Backspace => 22, Tab => 23, KeyQ => 24, KeyW => 25, KeyE => 26,
+        KeyR => 27, KeyT => 28, KeyY => 29, KeyU => 30, KeyI => 31, KeyO => 32,
+        KeyP => 33, LeftBracket => 34, RightBracket => 35, Return => 36,
+        ControlLeft => 37, KeyA => 38, KeyS => 39, KeyD => 40, KeyF => 41, KeyG => 42,
+        KeyH => 43, KeyJ => 44, KeyK => 45, KeyL => 46, SemiColon => 47, Quote => 48,
+        BackQuote => 49, ShiftLeft => 50, BackSlash => 51, KeyZ => 52, KeyX => 53,
+        KeyC => 54, KeyV => 55, KeyB => 56, KeyN => 57, KeyM => 58, Comma => 59,
+        Dot => 60, Slash => 61, ShiftRight => 62, KpMultiply => 63, Alt => 64,
+        Space => 65, CapsLock => 66, F1 => 67, F2 => 68, F3 => 69, F4 => 70, F5 => 71,
+        F6 => 72, F7 => 73, F8 => 74, F9 => 75, F10 => 76, NumLock => 77,
+        ScrollLock => 78, Kp7 => 79, Kp8 => 80, Kp9 => 81, KpMinus => 82, Kp4 => 83,
+        Kp5 => 84, Kp6 => 85, KpPlus => 86, Kp1 => 87, Kp2 => 88, Kp3 => 89, Kp0 => 90,
+        KpDelete => 91, IntlBackslash => 94, F11 => 95, F12 => 96, KpReturn => 104,
+        ControlRight => 105, KpDivide => 106, PrintScreen => 107, AltGr => 108,
+        Home => 110, UpArrow => 111, PageUp => 112, LeftArrow => 113, RightArrow => 114,
+        End => 115, DownArrow => 116, PageDown => 117, Insert => 118, Delete => 119,
+        Pause => 127, MetaLeft => 133,
+        Unknown(code) => code,
+        // keys rdev never reports on X11
+        MetaRight | Function => return None,
+    };
+    Some(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::rdev::{Button, EventType, Key};
+
+    #[test]
+    fn keeps_x11_keycodes_and_buttons() {
+        let data = convert(&EventType::KeyPress(Key::KeyA));
+        assert_eq!(data, Some(event::EventData::KeyPress(38)));
+        let data = convert(&EventType::KeyRelease(Key::Unknown(248)));
+        assert_eq!(data, Some(event::EventData::KeyRelease(248)));
+        let data = convert(&EventType::ButtonPress(Button::Right));
+        assert_eq!(data, Some(event::EventData::PointerPress(3)));
+    }
+
+    #[test]
+    fn scrolls_in_the_direction_of_the_x11_backend() {
+        let data = convert(&EventType::Wheel {
+            delta_x: 0,
+            delta_y: 1,
+        });
+        assert_eq!(data, Some(event::EventData::Scroll { dx: 0.0, dy: -1.0 }));
+    }
+}